@@ -0,0 +1,180 @@
+//! Writes a synthetic chat.db to point the app at for demos:
+//! `cargo run --features fixture --bin demo-chat-db -- demo-chat.db`
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use backchannel_lib::fixture::{AttachmentSpec, Fixture, MessageSpec};
+use chrono::{Duration, Utc};
+
+fn main() -> ExitCode {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("demo-chat.db"));
+    if path.exists() {
+        eprintln!("{} already exists", path.display());
+        return ExitCode::FAILURE;
+    }
+
+    match demo().save(&path) {
+        Ok(()) => {
+            println!("Wrote {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Couldn't write {}: {e}", path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// A few weeks of chats covering what the app reads: 1:1, group and SMS
+/// chats, attributedBody-only text, tapbacks, replies, edits, unsends,
+/// attachments and dates stored in seconds
+fn demo() -> Fixture {
+    let now = Utc::now();
+    let ago = |days: i64, minutes: i64| now - Duration::days(days) + Duration::minutes(minutes);
+    let mut fixture = Fixture::new();
+
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let alice_sms = fixture.handle("+15551234567", "SMS");
+    let sam = fixture.handle("sam@example.com", "iMessage");
+    let jordan = fixture.handle("+15559876543", "iMessage");
+    let direct = fixture.chat(alice);
+    let sms = fixture.chat(alice_sms);
+    let family = fixture.group(Some("Family"), &[alice, sam]);
+    let trip = fixture.group(None, &[sam, jordan]);
+
+    fixture.message(
+        sms,
+        MessageSpec {
+            text: "Running late, be there in 10",
+            from: Some(alice_sms),
+            date: ago(40, 0),
+            legacy_seconds: true,
+            ..MessageSpec::default()
+        },
+    );
+
+    let question = fixture.message(
+        direct,
+        MessageSpec {
+            text: "Dinner Friday?",
+            from: Some(alice),
+            date: ago(3, 0),
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        direct,
+        MessageSpec {
+            text: "Yes! Tacos?",
+            date: ago(3, 2),
+            attributed_only: true,
+            read: Some(ago(3, 3)),
+            ..MessageSpec::default()
+        },
+    );
+    let place = fixture.message(
+        direct,
+        MessageSpec {
+            text: "The place on Main St at 7",
+            from: Some(alice),
+            date: ago(3, 5),
+            edits: vec!["The place on Elm St at 7"],
+            ..MessageSpec::default()
+        },
+    );
+    fixture.tapback(direct, &place, 2001, None, ago(3, 6));
+    fixture.message(
+        direct,
+        MessageSpec {
+            text: "Perfect, see you then",
+            date: ago(3, 7),
+            reply_to: Some(&question.guid),
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        direct,
+        MessageSpec {
+            text: "wrong chat sorry",
+            date: ago(3, 8),
+            unsent: true,
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        direct,
+        MessageSpec {
+            text: "\u{FFFC}",
+            from: Some(alice),
+            date: ago(1, 0),
+            attachments: vec![AttachmentSpec {
+                filename: "~/Library/Messages/Attachments/demo/menu.jpg",
+                mime_type: "image/jpeg",
+                total_bytes: 184_320,
+                ..AttachmentSpec::default()
+            }],
+            ..MessageSpec::default()
+        },
+    );
+
+    let plan = fixture.message(
+        family,
+        MessageSpec {
+            text: "Who's bringing dessert on Sunday?",
+            from: Some(sam),
+            date: ago(2, 0),
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        family,
+        MessageSpec {
+            text: "I'll make the pie",
+            date: ago(2, 4),
+            attributed_only: true,
+            ..MessageSpec::default()
+        },
+    );
+    fixture.tapback(family, &plan, 2000, Some(alice), ago(2, 5));
+    fixture.message(
+        family,
+        MessageSpec {
+            text: "\u{FFFC}",
+            from: Some(alice),
+            date: ago(2, 6),
+            attachments: vec![AttachmentSpec {
+                filename: "~/Library/Messages/Attachments/demo/sticker.heic",
+                mime_type: "image/heic",
+                is_sticker: true,
+                ..AttachmentSpec::default()
+            }],
+            ..MessageSpec::default()
+        },
+    );
+
+    fixture.message(
+        trip,
+        MessageSpec {
+            text: "Flights are booked, itinerary at https://example.com/trip",
+            from: Some(jordan),
+            date: ago(10, 0),
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        trip,
+        MessageSpec {
+            text: "Can't wait",
+            from: Some(sam),
+            date: ago(10, 30),
+            legacy_seconds: true,
+            ..MessageSpec::default()
+        },
+    );
+
+    fixture
+}
@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+use tauri::{command, State};
+
+use super::settings::{load_config, save_config};
+use crate::contacts::{
+    default_address_book_dir, parse_vcards, Alias, ContactBook, ContactStore, ContactsStatus,
+    ImportSummary,
+};
+use crate::state::AppState;
+
+/// The AddressBook location in use: the configured path, or the default
+#[command]
+pub async fn get_address_book_path(state: State<'_, AppState>) -> Result<String, String> {
+    let path = state
+        .get_address_book_path()?
+        .unwrap_or_else(default_address_book_dir);
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Read contacts from an AddressBook directory or `.abcddb` file instead of
+/// the user's own. `None` goes back to the default.
+#[command]
+pub async fn set_address_book_path(
+    path: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ContactsStatus, String> {
+    let path = path.map(PathBuf::from);
+
+    // Make sure the contacts are readable before switching to them
+    let candidate = path.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        ContactBook::default().add_address_book(candidate.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+
+    let mut config = load_config();
+    config.address_book_path = path.clone();
+    save_config(&config)?;
+
+    state.update_address_book_path(path)?;
+    state.reload_contacts().await
+}
+
+/// Re-read contacts, e.g. after granting Contacts access. Returns how many
+/// phone numbers and emails have a name and any stores that couldn't be read.
+#[command]
+pub async fn reload_contacts(state: State<'_, AppState>) -> Result<ContactsStatus, String> {
+    state.reload_contacts().await
+}
+
+/// Import the contacts in one or more `.vcf` files
+#[command]
+pub async fn import_vcards(
+    paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<ImportSummary, String> {
+    let summary = with_store(move |store| {
+        let mut total = ImportSummary::default();
+        for path in &paths {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+            let source = PathBuf::from(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone());
+            let summary = store
+                .import_vcards(&parse_vcards(&text), &source)
+                .map_err(|e| e.to_string())?;
+            total.contacts += summary.contacts;
+            total.handles += summary.handles;
+        }
+        Ok(total)
+    })
+    .await?;
+
+    state.clear_contacts()?;
+    Ok(summary)
+}
+
+#[command]
+pub async fn list_aliases() -> Result<Vec<Alias>, String> {
+    with_store(|store| store.list_aliases().map_err(|e| e.to_string())).await
+}
+
+#[command]
+pub async fn add_alias(
+    handle: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<Alias, String> {
+    let alias =
+        with_store(move |store| store.add_alias(&handle, &name).map_err(|e| e.to_string())).await?;
+    state.clear_contacts()?;
+    Ok(alias)
+}
+
+#[command]
+pub async fn update_alias(
+    id: i64,
+    handle: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<Alias, String> {
+    let alias = with_store(move |store| {
+        store
+            .update_alias(id, &handle, &name)
+            .map_err(|e| e.to_string())
+    })
+    .await?;
+    state.clear_contacts()?;
+    Ok(alias)
+}
+
+#[command]
+pub async fn delete_alias(id: i64, state: State<'_, AppState>) -> Result<(), String> {
+    with_store(move |store| store.delete_alias(id).map_err(|e| e.to_string())).await?;
+    state.clear_contacts()
+}
+
+/// Run `f` against the app's contact store on the blocking pool
+async fn with_store<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut ContactStore) -> Result<T, String> + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut store = ContactStore::open().map_err(|e| e.to_string())?;
+        f(&mut store)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
@@ -1,79 +1,183 @@
 use tauri::{command, ipc::Channel, State};
 
-use crate::db::{Conversation, Message};
-use crate::llm::{analyze_prompt, summarize_prompt};
+use chrono::{DateTime, Utc};
+
+use crate::db::{
+    Attachment, ConversationPage, ConversationQuery, Cursor, Message, MessagePage, MessageQuery,
+};
+use crate::llm::{analyze_prompt, prompt_messages_json, summarize_prompt};
 use crate::state::AppState;
+use crate::utils::datetime_to_mac_timestamp;
 
+/// Conversations by most recent activity, a page at a time
 #[command]
-pub async fn get_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, String> {
-    let db = state.get_db()?;
-    db.get_conversations().map_err(|e| e.to_string())
+pub async fn get_conversations(
+    filter: Option<String>,
+    cursor: Option<Cursor>,
+    limit: Option<i64>,
+    include_empty: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<ConversationPage, String> {
+    let defaults = ConversationQuery::default();
+    let query = ConversationQuery {
+        filter,
+        cursor,
+        limit: limit.unwrap_or(defaults.limit),
+        include_empty: include_empty.unwrap_or(defaults.include_empty),
+    };
+    state
+        .with_store(move |store| store.get_conversations(&query).map_err(|e| e.to_string()))
+        .await
 }
 
+/// A page of a chat's messages, newest first. Page with the `before`/`after`
+/// cursors from a previous page and narrow to `start..end`.
 #[command]
 pub async fn get_conversation_messages(
     chat_id: i64,
     limit: Option<i64>,
+    before: Option<Cursor>,
+    after: Option<Cursor>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    state: State<'_, AppState>,
+) -> Result<MessagePage, String> {
+    let query = MessageQuery {
+        before,
+        after,
+        start: start.map(datetime_to_mac_timestamp),
+        end: end.map(datetime_to_mac_timestamp),
+        limit: limit.unwrap_or(MessageQuery::default().limit),
+    };
+    state
+        .with_store(move |store| {
+            store
+                .get_chat_messages(chat_id, &query)
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
+
+/// The page of a chat's messages around `date`, for jumping to a day
+#[command]
+pub async fn jump_to_date(
+    chat_id: i64,
+    date: DateTime<Utc>,
+    limit: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<MessagePage, String> {
+    let date = datetime_to_mac_timestamp(date);
+    state
+        .with_store(move |store| {
+            store
+                .get_chat_messages_around(chat_id, date, limit.unwrap_or(100))
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
+
+#[command]
+pub async fn get_reply_thread(
+    message_guid: String,
     state: State<'_, AppState>,
 ) -> Result<Vec<Message>, String> {
-    let db = state.get_db()?;
-    db.get_messages_for_chat(chat_id, limit.unwrap_or(100))
-        .map_err(|e| e.to_string())
+    state
+        .with_store(move |store| {
+            store
+                .get_reply_thread(&message_guid)
+                .map_err(|e| e.to_string())
+        })
+        .await
 }
 
 #[command]
-pub async fn summarize_conversation(
+pub async fn get_chat_attachments(
     chat_id: i64,
     state: State<'_, AppState>,
+) -> Result<Vec<Attachment>, String> {
+    state
+        .with_store(move |store| {
+            store
+                .get_attachments_for_chat(chat_id)
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
+
+#[command]
+pub async fn get_attachment_path(
+    filename: String,
+    state: State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    let path = state
+        .with_store(move |store| store.attachment_path(&filename).map_err(|e| e.to_string()))
+        .await?;
+    Ok(path.map(|p| p.to_string_lossy().into_owned()))
+}
+
+/// Summarize a chat, or with `person_id`, everything from that person's
+/// 1:1 chats. Chat summaries are reused until the chat gets new messages,
+/// tapbacks, edits or unsends.
+#[command]
+pub async fn summarize_conversation(
+    chat_id: Option<i64>,
+    person_id: Option<String>,
+    state: State<'_, AppState>,
 ) -> Result<String, String> {
-    let db = state.get_db()?;
+    let cache_key = match chat_id.filter(|_| person_id.is_none()) {
+        Some(chat_id) => {
+            let version = state
+                .with_store(move |store| store.get_chat_version(chat_id).map_err(|e| e.to_string()))
+                .await?;
+            if let Some(summary) = state.get_summary(chat_id, version)? {
+                return Ok(summary);
+            }
+            Some((chat_id, version))
+        }
+        None => None,
+    };
+
     let llm = state.get_llm_client()?;
 
     // Get messages
-    let messages = db
-        .get_messages_for_chat(chat_id, 100)
-        .map_err(|e| e.to_string())?;
+    let messages = scoped_messages(&state, chat_id, person_id, 100).await?;
 
     if messages.is_empty() {
         return Err("No messages found in conversation".to_string());
     }
 
-    // Get contact name from first participant
-    let contact_name = messages
-        .first()
-        .and_then(|m| m.contact_id.clone())
-        .unwrap_or_else(|| "Unknown".to_string());
+    let contact_name = conversation_title(&messages);
 
-    let messages_json = serde_json::to_string(&messages).map_err(|e| e.to_string())?;
+    let messages_json = prompt_messages_json(&messages).map_err(|e| e.to_string())?;
     let prompt = summarize_prompt(&messages_json, &contact_name);
 
-    llm.complete(&prompt, None).await
+    let summary = llm.complete(&prompt, None).await?;
+    if let Some((chat_id, version)) = cache_key {
+        state.cache_summary(chat_id, version, summary.clone())?;
+    }
+    Ok(summary)
 }
 
 #[command]
 pub async fn summarize_conversation_streaming(
-    chat_id: i64,
+    chat_id: Option<i64>,
+    person_id: Option<String>,
     message_limit: Option<i64>,
     channel: Channel<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    let db = state.get_db()?;
     let llm = state.get_llm_client()?;
 
-    let messages = db
-        .get_messages_for_chat(chat_id, message_limit.unwrap_or(100))
-        .map_err(|e| e.to_string())?;
+    let messages =
+        scoped_messages(&state, chat_id, person_id, message_limit.unwrap_or(100)).await?;
 
     if messages.is_empty() {
         return Err("No messages found in conversation".to_string());
     }
 
-    let contact_name = messages
-        .first()
-        .and_then(|m| m.contact_id.clone())
-        .unwrap_or_else(|| "Unknown".to_string());
+    let contact_name = conversation_title(&messages);
 
-    let messages_json = serde_json::to_string(&messages).map_err(|e| e.to_string())?;
+    let messages_json = prompt_messages_json(&messages).map_err(|e| e.to_string())?;
     let prompt = summarize_prompt(&messages_json, &contact_name);
 
     llm.stream_complete(&prompt, None, channel).await
@@ -81,24 +185,70 @@ pub async fn summarize_conversation_streaming(
 
 #[command]
 pub async fn analyze_conversation(
-    chat_id: i64,
+    chat_id: Option<i64>,
+    person_id: Option<String>,
     message_limit: Option<i64>,
     channel: Channel<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    let db = state.get_db()?;
     let llm = state.get_llm_client()?;
 
-    let messages = db
-        .get_messages_for_chat(chat_id, message_limit.unwrap_or(200))
-        .map_err(|e| e.to_string())?;
+    let messages =
+        scoped_messages(&state, chat_id, person_id, message_limit.unwrap_or(200)).await?;
 
     if messages.is_empty() {
         return Err("No messages found in conversation".to_string());
     }
 
-    let messages_json = serde_json::to_string(&messages).map_err(|e| e.to_string())?;
+    let messages_json = prompt_messages_json(&messages).map_err(|e| e.to_string())?;
     let prompt = analyze_prompt(&messages_json);
 
     llm.stream_complete(&prompt, None, channel).await
 }
+
+/// The latest messages in a chat or, when `person_id` is given, across
+/// that person's 1:1 chats
+async fn scoped_messages(
+    state: &AppState,
+    chat_id: Option<i64>,
+    person_id: Option<String>,
+    limit: i64,
+) -> Result<Vec<Message>, String> {
+    state
+        .with_store(move |store| match (person_id, chat_id) {
+            (Some(person_id), _) => {
+                let person = store
+                    .get_person(&person_id)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("Unknown person: {person_id}"))?;
+                store
+                    .get_person_timeline(&person, limit)
+                    .map_err(|e| e.to_string())
+            }
+            (None, Some(chat_id)) => store
+                .get_messages_for_chat(chat_id, limit)
+                .map_err(|e| e.to_string()),
+            (None, None) => Err("Either chat_id or person_id is required".to_string()),
+        })
+        .await
+}
+
+/// Who the conversation is with, by contact name where known
+fn conversation_title(messages: &[Message]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for message in messages.iter().filter(|m| !m.is_from_me) {
+        let name = message
+            .contact_name
+            .as_deref()
+            .or(message.contact_id.as_deref());
+        if let Some(name) = name.filter(|n| !names.contains(n)) {
+            names.push(name);
+        }
+    }
+
+    if names.is_empty() {
+        "Unknown".to_string()
+    } else {
+        names.join(", ")
+    }
+}
@@ -1,3 +1,5 @@
+pub mod contacts;
 pub mod conversations;
+pub mod people;
 pub mod search;
 pub mod settings;
@@ -0,0 +1,32 @@
+use tauri::{command, State};
+
+use crate::db::{Message, Person};
+use crate::state::AppState;
+
+#[command]
+pub async fn get_people(state: State<'_, AppState>) -> Result<Vec<Person>, String> {
+    state
+        .with_store(|store| store.get_people().map_err(|e| e.to_string()))
+        .await
+}
+
+/// Messages from all of a person's 1:1 chats, across their numbers, emails
+/// and services, newest first
+#[command]
+pub async fn get_person_timeline(
+    person_id: String,
+    limit: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<Message>, String> {
+    state
+        .with_store(move |store| {
+            let person = store
+                .get_person(&person_id)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("Unknown person: {person_id}"))?;
+            store
+                .get_person_timeline(&person, limit.unwrap_or(100))
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
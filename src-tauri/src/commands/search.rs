@@ -1,8 +1,15 @@
+use chrono::{Duration, Utc};
 use serde::Serialize;
 use tauri::{command, State};
 
-use crate::db::{Message, SearchResult};
-use crate::llm::answer_question_prompt;
+use crate::db::{
+    parse_query, ContextDirection, ContextOptions, Message, MessageStore, SearchResult,
+};
+use crate::index::{
+    apply_rerank, hybrid_scores, parse_rerank_order, rank, Candidate, EmbeddingStatus,
+    IndexStatus, RankingConfig, SearchHit, SearchOptions,
+};
+use crate::llm::{answer_question_prompt, prompt_messages_json, rerank_prompt, Embedder};
 use crate::state::AppState;
 
 #[derive(Debug, Serialize)]
@@ -11,6 +18,24 @@ pub struct QuestionAnswer {
     pub source_messages: Vec<Message>,
 }
 
+/// Search context stops at pauses longer than this, which usually mark
+/// the start of a different conversation
+const CONTEXT_GAP_HOURS: i64 = 6;
+
+/// Share of a hybrid score that comes from BM25 rather than embeddings
+const HYBRID_KEYWORD_WEIGHT: f64 = 0.4;
+/// Chunks sent to the embedding model per request
+const EMBED_BATCH: i64 = 32;
+/// Most chunks embedded before a semantic search runs
+const EMBED_ON_SEARCH: usize = 256;
+
+/// Keyword matches ranked for a question, and how many of the best are
+/// given to the model
+const ASK_CANDIDATES: i64 = 100;
+const ASK_SOURCES: usize = 30;
+/// Characters of each message shown to the model when reranking
+const RERANK_TEXT_CHARS: usize = 300;
+
 #[command]
 pub async fn natural_language_search(
     query: String,
@@ -19,37 +44,346 @@ pub async fn natural_language_search(
     // First, generate SQL from natural language (async)
     let nl2sql = state.get_nl2sql_engine()?;
     let sql = nl2sql.generate_sql(&query).await?;
+    let ranking = state.get_ranking_config()?;
+    let terms = extract_keywords(&query);
 
-    // Then execute the SQL query synchronously
-    let db = state.get_db()?;
-    let messages = db.execute_search_query(&sql)?;
-
-    // Get context for each message
-    let mut results = Vec::new();
-    for msg in messages {
-        let context_before = db.get_context_before(msg.id, 2).map_err(|e| e.to_string())?;
-        let context_after = db.get_context_after(msg.id, 2).map_err(|e| e.to_string())?;
-
-        results.push(SearchResult {
-            message: msg,
-            context_before,
-            context_after,
-            relevance_score: 1.0,
-        });
+    // Then execute the SQL query on the blocking pool
+    let config = ranking.clone();
+    let ranked = state
+        .with_db(move |db| {
+            let candidates = db
+                .execute_search_query(&sql)?
+                .into_iter()
+                .map(|message| Candidate {
+                    message,
+                    retrieval_score: None,
+                })
+                .collect();
+            rank_candidates(db, candidates, &terms, &config)
+        })
+        .await?;
+
+    let ranked = rerank(&state, &query, ranked, &ranking).await;
+    state
+        .with_store(move |store| with_context(store, ranked))
+        .await
+}
+
+/// Order hits by the ranking config, best first
+fn rank_candidates(
+    store: &dyn MessageStore,
+    candidates: Vec<Candidate>,
+    terms: &[String],
+    config: &RankingConfig,
+) -> Result<Vec<(Message, f64)>, String> {
+    let ids: Vec<i64> = candidates.iter().map(|c| c.message.id).collect();
+    let affinity = store
+        .get_conversation_affinity(&ids)
+        .map_err(|e| e.to_string())?;
+    Ok(rank(candidates, terms, &affinity, config, Utc::now()))
+}
+
+/// Have the LLM reorder the top `rerank_top_n` results when that's turned
+/// on. Reranking is a refinement, so the ranked order stands if the model
+/// is unavailable or its answer can't be read.
+async fn rerank(
+    state: &AppState,
+    query: &str,
+    ranked: Vec<(Message, f64)>,
+    config: &RankingConfig,
+) -> Vec<(Message, f64)> {
+    let count = config.rerank_top_n.min(ranked.len());
+    if count < 2 {
+        return ranked;
     }
+    let Ok(llm) = state.get_llm_client() else {
+        return ranked;
+    };
+
+    let numbered: Vec<String> = ranked[..count]
+        .iter()
+        .enumerate()
+        .map(|(i, (message, _))| {
+            let sender = if message.is_from_me {
+                "Me"
+            } else {
+                message
+                    .contact_name
+                    .as_deref()
+                    .or(message.contact_id.as_deref())
+                    .unwrap_or("Unknown")
+            };
+            let text: String = message
+                .text
+                .as_deref()
+                .unwrap_or("")
+                .chars()
+                .take(RERANK_TEXT_CHARS)
+                .collect();
+            format!(
+                "[{}] {} {}: {}",
+                i,
+                message.date.format("%Y-%m-%d"),
+                sender,
+                text.replace('\n', " ")
+            )
+        })
+        .collect();
 
-    Ok(results)
+    let prompt = rerank_prompt(query, &numbered.join("\n"));
+    match llm.complete(&prompt, None).await {
+        Ok(response) => match parse_rerank_order(&response, count) {
+            Some(order) => apply_rerank(ranked, &order),
+            None => ranked,
+        },
+        Err(_) => ranked,
+    }
+}
+
+/// Search results with context for each message from its own chat
+fn with_context(
+    store: &dyn MessageStore,
+    scored: Vec<(Message, f64)>,
+) -> Result<Vec<SearchResult>, String> {
+    let options = ContextOptions {
+        max_gap: Some(Duration::hours(CONTEXT_GAP_HOURS)),
+        ..ContextOptions::default()
+    };
+    scored
+        .into_iter()
+        .map(|(message, relevance_score)| {
+            let context_before = store
+                .get_context(message.id, ContextDirection::Before, &options)
+                .map_err(|e| e.to_string())?;
+            let context_after = store
+                .get_context(message.id, ContextDirection::After, &options)
+                .map_err(|e| e.to_string())?;
+            Ok(SearchResult {
+                message,
+                context_before,
+                context_after,
+                relevance_score: relevance_score as f32,
+            })
+        })
+        .collect()
 }
 
+/// Search by meaning with embeddings from Ollama. With `hybrid` (the
+/// default), BM25 keyword scores are blended in so exact matches still
+/// rank well.
+#[command]
+pub async fn semantic_search(
+    query: String,
+    limit: Option<usize>,
+    hybrid: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<Vec<SearchResult>, String> {
+    let limit = limit.unwrap_or(20);
+    let keyword_weight = if hybrid.unwrap_or(true) {
+        HYBRID_KEYWORD_WEIGHT
+    } else {
+        0.0
+    };
+    let embedder = state.get_embedder()?;
+
+    // Keep up with new messages without embedding the whole history here
+    embed_pending(&state, &embedder, EMBED_ON_SEARCH).await?;
+    let query_vector = embedder
+        .embed(std::slice::from_ref(&query))
+        .await?
+        .pop()
+        .ok_or("No embedding returned for the query")?;
+    let model = embedder.model().to_string();
+    let ranking = state.get_ranking_config()?;
+    let terms = extract_keywords(&query);
+
+    let search_query = query.clone();
+    let hits = state
+        .with_built_index(move |index| {
+            let chunks = index
+                .nearest_chunks(&model, &query_vector, limit)
+                .map_err(|e| e.to_string())?;
+            let keyword = if keyword_weight > 0.0 {
+                let options = SearchOptions {
+                    limit: limit as i64 * 2,
+                    match_any: true,
+                    ..SearchOptions::default()
+                };
+                index
+                    .search(&search_query, &options)
+                    .map_err(|e| e.to_string())?
+            } else {
+                Vec::new()
+            };
+            Ok(hybrid_scores(&keyword, &chunks, keyword_weight))
+        })
+        .await?;
+
+    let config = ranking.clone();
+    let ranked = state
+        .with_store(move |store| {
+            let candidates = hit_candidates(store, &hits)?;
+            let mut ranked = rank_candidates(store, candidates, &terms, &config)?;
+            ranked.truncate(limit);
+            Ok(ranked)
+        })
+        .await?;
+
+    let ranked = rerank(&state, &query, ranked, &ranking).await;
+    state
+        .with_store(move |store| with_context(store, ranked))
+        .await
+}
+
+/// Embed chunks that don't have a vector from the current model yet, up to
+/// `max_chunks` (all of them by default). Run ahead of time so semantic
+/// search covers the whole history.
+#[command]
+pub async fn update_embeddings(
+    max_chunks: Option<usize>,
+    state: State<'_, AppState>,
+) -> Result<EmbeddingStatus, String> {
+    let embedder = state.get_embedder()?;
+    state.sync_search_index().await?;
+    embed_pending(&state, &embedder, max_chunks.unwrap_or(usize::MAX)).await
+}
+
+async fn embed_pending(
+    state: &AppState,
+    embedder: &impl Embedder,
+    max_chunks: usize,
+) -> Result<EmbeddingStatus, String> {
+    let model = embedder.model().to_string();
+    let mut embedded = 0;
+
+    while embedded < max_chunks {
+        let batch_model = model.clone();
+        let batch = state
+            .with_search_index(move |index| {
+                index
+                    .pending_chunks(&batch_model, EMBED_BATCH)
+                    .map_err(|e| e.to_string())
+            })
+            .await?;
+        if batch.is_empty() {
+            break;
+        }
+
+        let texts: Vec<String> = batch.iter().map(|chunk| chunk.text.clone()).collect();
+        let vectors = embedder.embed(&texts).await?;
+        embedded += batch.len();
+
+        let batch_model = model.clone();
+        let embeddings: Vec<_> = batch.into_iter().zip(vectors).collect();
+        state
+            .with_search_index(move |index| {
+                index
+                    .store_embeddings(&batch_model, &embeddings)
+                    .map_err(|e| e.to_string())
+            })
+            .await?;
+    }
+
+    state
+        .with_search_index(move |index| index.embedding_status(&model).map_err(|e| e.to_string()))
+        .await
+}
+
+/// More context around a search hit. Pass the first message of the current
+/// window with `before`, or the last with `after`.
+#[command]
+pub async fn get_message_context(
+    message_id: i64,
+    direction: ContextDirection,
+    count: Option<i64>,
+    max_gap_minutes: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<Message>, String> {
+    let options = ContextOptions {
+        count: count.unwrap_or(10),
+        max_gap: max_gap_minutes.map(Duration::minutes),
+    };
+    state
+        .with_store(move |store| {
+            store
+                .get_context(message_id, direction, &options)
+                .map_err(|e| e.to_string())
+        })
+        .await
+}
+
+/// Search with the query syntax, e.g. `from:alice in:'Family'
+/// after:2024-03-01 has:link "dinner plans" -work`
 #[command]
 pub async fn simple_search(
     query: String,
     limit: Option<i64>,
+    include_edits: Option<bool>,
+    person_id: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<Vec<Message>, String> {
-    let db = state.get_db()?;
-    db.search_messages(&query, limit.unwrap_or(50))
-        .map_err(|e| e.to_string())
+    let query = parse_query(&query).map_err(|e| e.to_string())?;
+    let uses_index = query.uses_index();
+    let search = move |store: &dyn MessageStore| {
+        let options = SearchOptions {
+            limit: limit.unwrap_or(50),
+            include_edits: include_edits.unwrap_or(false),
+            chat_ids: person_chat_ids(store, person_id.as_deref())?,
+            ..SearchOptions::default()
+        };
+        store
+            .search_messages(&query, &options)
+            .map_err(|e| e.to_string())
+    };
+    if uses_index {
+        state.with_index(search).await
+    } else {
+        state.with_store(search).await
+    }
+}
+
+/// Bring the search index up to date and wait for it, e.g. to show
+/// progress after switching data sources
+#[command]
+pub async fn refresh_search_index(state: State<'_, AppState>) -> Result<IndexStatus, String> {
+    state.sync_search_index().await
+}
+
+/// The messages for index hits, carrying each hit's score into ranking
+fn hit_candidates(
+    store: &dyn MessageStore,
+    hits: &[SearchHit],
+) -> Result<Vec<Candidate>, String> {
+    let ids: Vec<i64> = hits.iter().map(|hit| hit.message_id).collect();
+    let messages = store.get_messages_by_ids(&ids).map_err(|e| e.to_string())?;
+    Ok(messages
+        .into_iter()
+        .map(|message| {
+            let score = hits
+                .iter()
+                .find(|hit| hit.message_id == message.id)
+                .map(|hit| hit.score);
+            Candidate {
+                message,
+                retrieval_score: score,
+            }
+        })
+        .collect())
+}
+
+/// The 1:1 chats to search when scoped to a person
+fn person_chat_ids(
+    store: &dyn MessageStore,
+    person_id: Option<&str>,
+) -> Result<Option<Vec<i64>>, String> {
+    let Some(person_id) = person_id else {
+        return Ok(None);
+    };
+    let person = store
+        .get_person(person_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Unknown person: {person_id}"))?;
+    Ok(Some(person.chat_ids))
 }
 
 /// Extract meaningful keywords from a question for searching
@@ -86,30 +420,42 @@ fn extract_keywords(question: &str) -> Vec<String> {
 #[command]
 pub async fn ask_question(
     question: String,
+    person_id: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<QuestionAnswer, String> {
-    let db = state.get_db()?;
-
     // Extract keywords from the question
     let keywords = extract_keywords(&question);
+    let ranking = state.get_ranking_config()?;
 
-    // Search for messages containing any of the keywords
-    let mut all_messages: Vec<Message> = Vec::new();
-
-    for keyword in &keywords {
-        if let Ok(messages) = db.search_messages(keyword, 20) {
-            for msg in messages {
-                // Avoid duplicates
-                if !all_messages.iter().any(|m| m.id == msg.id) {
-                    all_messages.push(msg);
-                }
-            }
-        }
-    }
-
-    // Sort by date descending and limit
-    all_messages.sort_by(|a, b| b.date.cmp(&a.date));
-    all_messages.truncate(30);
+    // Search for messages containing any of the keywords, and keep the
+    // best by match quality, recency and how active the chat is
+    let chat_ids = state
+        .with_store(move |store| person_chat_ids(store, person_id.as_deref()))
+        .await?;
+    let options = SearchOptions {
+        limit: ASK_CANDIDATES,
+        chat_ids,
+        match_any: true,
+        ..SearchOptions::default()
+    };
+    let search_query = keywords.join(" ");
+    let hits = state
+        .with_built_index(move |index| {
+            index
+                .search(&search_query, &options)
+                .map_err(|e| e.to_string())
+        })
+        .await?;
+    let config = ranking.clone();
+    let ranked = state
+        .with_store(move |store| {
+            let candidates = hit_candidates(store, &hits)?;
+            rank_candidates(store, candidates, &keywords, &config)
+        })
+        .await?;
+    let mut ranked = rerank(&state, &question, ranked, &ranking).await;
+    ranked.truncate(ASK_SOURCES);
+    let all_messages: Vec<Message> = ranked.into_iter().map(|(message, _)| message).collect();
 
     // If no messages found, return early
     if all_messages.is_empty() {
@@ -120,7 +466,7 @@ pub async fn ask_question(
     }
 
     // Serialize messages to JSON for the LLM
-    let messages_json = serde_json::to_string_pretty(&all_messages)
+    let messages_json = prompt_messages_json(&all_messages)
         .map_err(|e| format!("Failed to serialize messages: {}", e))?;
 
     // Get LLM client and generate answer
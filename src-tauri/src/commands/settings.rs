@@ -6,36 +6,33 @@ use std::path::PathBuf;
 use std::process::Command;
 use tauri::{command, AppHandle, State};
 
-use crate::db::connection::ChatDb;
-use crate::db::ModelInfo;
+use crate::db::{DataSource, Diagnostics, ModelInfo, Snapshot};
+use crate::index::RankingConfig;
 use crate::llm::LlmProvider;
 use crate::state::AppState;
+use crate::utils::app_data_dir;
 
 const SERVICE_NAME: &str = "com.backchannel.app";
 const API_KEY_NAME: &str = "openrouter_api_key";
 
 /// Config file for non-sensitive settings (no keychain prompts)
 #[derive(Debug, Serialize, Deserialize, Default)]
-struct AppConfig {
+pub(crate) struct AppConfig {
     provider: Option<String>,
     model: Option<String>,
     ollama_url: Option<String>,
+    pub(crate) data_source: Option<DataSource>,
+    pub(crate) snapshot_mode: Option<bool>,
+    pub(crate) address_book_path: Option<PathBuf>,
+    pub(crate) embedding_model: Option<String>,
+    pub(crate) ranking: Option<RankingConfig>,
 }
 
 fn get_config_path() -> PathBuf {
-    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    let config_dir = PathBuf::from(home)
-        .join("Library")
-        .join("Application Support")
-        .join("com.backchannel.app");
-
-    // Create directory if it doesn't exist
-    let _ = fs::create_dir_all(&config_dir);
-
-    config_dir.join("config.json")
+    app_data_dir().join("config.json")
 }
 
-fn load_config() -> AppConfig {
+pub(crate) fn load_config() -> AppConfig {
     let path = get_config_path();
     match fs::read_to_string(&path) {
         Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
@@ -43,7 +40,7 @@ fn load_config() -> AppConfig {
     }
 }
 
-fn save_config(config: &AppConfig) -> Result<(), String> {
+pub(crate) fn save_config(config: &AppConfig) -> Result<(), String> {
     let path = get_config_path();
     let content = serde_json::to_string_pretty(config)
         .map_err(|e| format!("Failed to serialize config: {}", e))?;
@@ -81,8 +78,8 @@ pub async fn get_api_key(state: State<'_, AppState>) -> Result<Option<String>, S
 }
 
 #[command]
-pub async fn check_permissions() -> Result<bool, String> {
-    let db_path = ChatDb::db_path();
+pub async fn check_permissions(state: State<'_, AppState>) -> Result<bool, String> {
+    let db_path = state.get_data_source()?.db_path();
 
     match std::fs::metadata(&db_path) {
         Ok(_) => {
@@ -225,11 +222,10 @@ pub async fn save_provider_settings(
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     // Save to config file (no keychain prompts!)
-    let config = AppConfig {
-        provider: Some(provider.clone()),
-        model: Some(model.clone()),
-        ollama_url: Some(ollama_url.clone()),
-    };
+    let mut config = load_config();
+    config.provider = Some(provider.clone());
+    config.model = Some(model.clone());
+    config.ollama_url = Some(ollama_url.clone());
     save_config(&config)?;
 
     // Update state
@@ -245,6 +241,102 @@ pub async fn save_provider_settings(
     Ok(())
 }
 
+#[command]
+pub async fn get_data_source(state: State<'_, AppState>) -> Result<DataSource, String> {
+    state.get_data_source()
+}
+
+#[command]
+pub async fn set_data_source(
+    source: DataSource,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    // Make sure the new source is readable before switching to it
+    let candidate = source.clone();
+    tauri::async_runtime::spawn_blocking(move || candidate.open().map(|_| ()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let mut config = load_config();
+    config.data_source = Some(source.clone());
+    save_config(&config)?;
+
+    state.update_data_source(source)?;
+    state.sync_search_index_in_background()?;
+    state.watch_messages(app)
+}
+
+/// What the current database supports and which optional columns it lacks
+#[command]
+pub async fn get_database_diagnostics(state: State<'_, AppState>) -> Result<Diagnostics, String> {
+    state
+        .with_db(|db| db.diagnostics().map_err(|e| e.to_string()))
+        .await
+}
+
+#[command]
+pub async fn get_snapshot_mode(state: State<'_, AppState>) -> Result<bool, String> {
+    state.get_snapshot_mode()
+}
+
+#[command]
+pub async fn set_snapshot_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let mut config = load_config();
+    config.snapshot_mode = Some(enabled);
+    save_config(&config)?;
+
+    state.update_snapshot_mode(enabled)
+}
+
+/// The snapshot reads are currently served from, if any
+#[command]
+pub async fn get_snapshot_info(state: State<'_, AppState>) -> Result<Option<Snapshot>, String> {
+    state.get_snapshot()
+}
+
+/// Re-copy the data source so reads pick up everything written since the
+/// last snapshot
+#[command]
+pub async fn refresh_snapshot(state: State<'_, AppState>) -> Result<Snapshot, String> {
+    state.refresh_snapshot().await
+}
+
+#[command]
+pub async fn get_embedding_model(state: State<'_, AppState>) -> Result<String, String> {
+    state.get_embedding_model()
+}
+
+/// Switch the Ollama model used for semantic search. Chunks are embedded
+/// again with the new model as searches run.
+#[command]
+pub async fn set_embedding_model(model: String, state: State<'_, AppState>) -> Result<(), String> {
+    let mut config = load_config();
+    config.embedding_model = Some(model.clone());
+    save_config(&config)?;
+
+    state.update_embedding_model(model)
+}
+
+#[command]
+pub async fn get_ranking_config(state: State<'_, AppState>) -> Result<RankingConfig, String> {
+    state.get_ranking_config()
+}
+
+/// Change how search results are ordered
+#[command]
+pub async fn set_ranking_config(
+    ranking: RankingConfig,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut config = load_config();
+    config.ranking = Some(ranking.clone());
+    save_config(&config)?;
+
+    state.update_ranking_config(ranking)
+}
+
 #[derive(Debug, Deserialize)]
 struct OllamaModel {
     name: String,
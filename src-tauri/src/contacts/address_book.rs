@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OpenFlags};
+
+use super::{ContactBook, ContactsError};
+
+/// Where Contacts.app keeps its stores: a root `.abcddb` plus one per
+/// account under `Sources/<id>/`
+pub fn default_address_book_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home)
+        .join("Library")
+        .join("Application Support")
+        .join("AddressBook")
+}
+
+/// Every `.abcddb` store at `path`, which may be a single store or an
+/// AddressBook directory
+pub fn find_stores(path: &Path) -> Result<Vec<PathBuf>, ContactsError> {
+    if !path.exists() {
+        return Err(ContactsError::NotFound(path.to_path_buf()));
+    }
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut stores = stores_in(path);
+    if let Ok(sources) = fs::read_dir(path.join("Sources")) {
+        for source in sources.flatten() {
+            stores.extend(stores_in(&source.path()));
+        }
+    }
+    Ok(stores)
+}
+
+fn stores_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut stores: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "abcddb"))
+        .collect();
+    stores.sort();
+    stores
+}
+
+/// Add every phone number and email in one store to `book`
+pub fn load_store(path: &Path, book: &mut ContactBook) -> Result<(), ContactsError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let name_columns = r#"
+        r.ZFIRSTNAME, r.ZLASTNAME, r.ZNICKNAME, r.ZORGANIZATION
+    "#;
+    let queries = [
+        format!(
+            "SELECT p.ZFULLNUMBER, r.Z_PK, {name_columns} FROM ZABCDPHONENUMBER p
+             JOIN ZABCDRECORD r ON p.ZOWNER = r.Z_PK"
+        ),
+        format!(
+            "SELECT e.ZADDRESS, r.Z_PK, {name_columns} FROM ZABCDEMAILADDRESS e
+             JOIN ZABCDRECORD r ON e.ZOWNER = r.Z_PK"
+        ),
+    ];
+
+    for sql in &queries {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, i64>(1)?,
+                display_name(row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?),
+            ))
+        })?;
+        for row in rows {
+            if let (Some(handle), record, Some(name)) = row? {
+                let contact = format!("addressbook:{}:{record}", path.display());
+                book.insert(&contact, &handle, &name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// "First Last", falling back to the nickname and then the company
+fn display_name(
+    first: Option<String>,
+    last: Option<String>,
+    nickname: Option<String>,
+    organization: Option<String>,
+) -> Option<String> {
+    let full = [first, last]
+        .into_iter()
+        .flatten()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(full)
+        .filter(|name| !name.is_empty())
+        .or(nickname)
+        .or(organization)
+        .filter(|name| !name.trim().is_empty())
+}
@@ -0,0 +1,162 @@
+mod address_book;
+mod normalize;
+mod store;
+mod vcard;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use thiserror::Error;
+
+pub use address_book::default_address_book_dir;
+pub use normalize::normalize_handle;
+pub use store::{Alias, ContactStore, ImportSummary};
+pub use vcard::parse_vcards;
+
+#[derive(Error, Debug)]
+pub enum ContactsError {
+    #[error("Contacts not found at {0}")]
+    NotFound(PathBuf),
+    #[error("Alias {0} not found")]
+    AliasNotFound(i64),
+    #[error("Invalid alias: {0}")]
+    InvalidAlias(String),
+    #[error("Failed to read contacts: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Contacts database error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+}
+
+/// An AddressBook store that couldn't be read
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedStore {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// What reloading contacts found
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContactsStatus {
+    /// Phone numbers and emails with a name
+    pub handles: usize,
+    pub skipped_stores: Vec<SkippedStore>,
+}
+
+/// Names for handles, keyed by normalized phone number or email
+#[derive(Debug, Clone, Default)]
+pub struct ContactBook {
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    /// Identifies the card the handle came from, so one person's phone
+    /// numbers and emails can be grouped
+    contact: String,
+}
+
+impl ContactBook {
+    /// The contacts the app keeps itself: aliases and imported vCards
+    pub fn load_local() -> Result<Self, ContactsError> {
+        let mut book = Self::default();
+        ContactStore::open()?.add_to(&mut book)?;
+        Ok(book)
+    }
+
+    /// Add every AddressBook store under `path` (a directory or a single
+    /// `.abcddb`), defaulting to the user's Contacts. One damaged or locked
+    /// store doesn't hide the others; the ones skipped are returned.
+    pub fn add_address_book(
+        &mut self,
+        path: Option<&Path>,
+    ) -> Result<Vec<SkippedStore>, ContactsError> {
+        let dir = default_address_book_dir();
+        let path = path.unwrap_or(&dir);
+        let mut skipped = Vec::new();
+        for store in address_book::find_stores(path)? {
+            if let Err(e) = address_book::load_store(&store, self) {
+                skipped.push(SkippedStore {
+                    error: e.to_string(),
+                    path: store,
+                });
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Map a handle on the card `contact` to a name. The first name seen
+    /// for a handle wins.
+    pub fn insert(&mut self, contact: &str, handle: &str, name: &str) {
+        if let Some(key) = normalize_handle(handle) {
+            self.entries.entry(key).or_insert_with(|| Entry {
+                name: name.to_string(),
+                contact: contact.to_string(),
+            });
+        }
+    }
+
+    pub fn resolve(&self, handle: &str) -> Option<&str> {
+        self.entry(handle).map(|entry| entry.name.as_str())
+    }
+
+    /// The card a handle belongs to
+    pub fn contact_key(&self, handle: &str) -> Option<&str> {
+        self.entry(handle).map(|entry| entry.contact.as_str())
+    }
+
+    fn entry(&self, handle: &str) -> Option<&Entry> {
+        self.entries.get(&normalize_handle(handle)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_load_address_book_store() {
+        let dir = std::env::temp_dir().join(format!("backchannel-contacts-{}", std::process::id()));
+        let source = dir.join("Sources").join("ABC");
+        std::fs::create_dir_all(&source).unwrap();
+
+        let conn = Connection::open(source.join("AddressBook-v22.abcddb")).unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE ZABCDRECORD (Z_PK INTEGER PRIMARY KEY, ZFIRSTNAME TEXT,
+                ZLASTNAME TEXT, ZNICKNAME TEXT, ZORGANIZATION TEXT);
+            CREATE TABLE ZABCDPHONENUMBER (ZOWNER INTEGER, ZFULLNUMBER TEXT);
+            CREATE TABLE ZABCDEMAILADDRESS (ZOWNER INTEGER, ZADDRESS TEXT);
+            INSERT INTO ZABCDRECORD VALUES (1, 'Sam', 'Lee', NULL, NULL);
+            INSERT INTO ZABCDRECORD VALUES (2, NULL, NULL, NULL, 'Pizza Place');
+            INSERT INTO ZABCDPHONENUMBER VALUES (1, '(555) 123-4567');
+            INSERT INTO ZABCDPHONENUMBER VALUES (2, '+1 555 000 1111');
+            INSERT INTO ZABCDEMAILADDRESS VALUES (1, 'Sam@Example.com');
+            "#,
+        )
+        .unwrap();
+        drop(conn);
+        // Read before the sources, and mustn't stop them being read
+        std::fs::write(dir.join("AddressBook-v22.abcddb"), b"not a database").unwrap();
+
+        let mut book = ContactBook::default();
+        let skipped = book.add_address_book(Some(&dir)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, dir.join("AddressBook-v22.abcddb"));
+
+        assert_eq!(book.resolve("+15551234567"), Some("Sam Lee"));
+        assert_eq!(book.resolve("sam@example.com"), Some("Sam Lee"));
+        assert_eq!(book.resolve("5550001111"), Some("Pizza Place"));
+        assert_eq!(book.resolve("+15559999999"), None);
+        assert_eq!(book.contact_key("sam@example.com"), book.contact_key("5551234567"));
+        assert_ne!(book.contact_key("5550001111"), book.contact_key("5551234567"));
+    }
+}
@@ -0,0 +1,72 @@
+/// Country calling code assumed for numbers written without one
+const DEFAULT_COUNTRY_CODE: &str = "1";
+
+/// Normalize a handle (phone number or email) to the key used for lookups
+pub fn normalize_handle(handle: &str) -> Option<String> {
+    if handle.contains('@') {
+        normalize_email(handle)
+    } else {
+        normalize_phone(handle)
+    }
+}
+
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().trim_start_matches("mailto:").to_lowercase();
+    (email.contains('@')).then_some(email)
+}
+
+/// Normalize a phone number to E.164 (`+15551234567`). Short codes and
+/// local numbers without an area code are kept as bare digits since they
+/// have no country.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let phone = phone.trim().trim_start_matches("tel:");
+    let has_plus = phone.starts_with('+');
+    let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+
+    if digits.is_empty() {
+        return None;
+    }
+    if has_plus {
+        return Some(format!("+{digits}"));
+    }
+    if let Some(international) = digits.strip_prefix("00") {
+        return Some(format!("+{international}"));
+    }
+    match digits.len() {
+        0..=9 => Some(digits),
+        10 => Some(format!("+{DEFAULT_COUNTRY_CODE}{digits}")),
+        _ => Some(format!("+{digits}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_handles() {
+        for raw in [
+            "+1 (555) 123-4567",
+            "555.123.4567",
+            "15551234567",
+            "tel:+15551234567",
+        ] {
+            assert_eq!(
+                normalize_handle(raw).as_deref(),
+                Some("+15551234567"),
+                "{raw}"
+            );
+        }
+        assert_eq!(
+            normalize_handle("0044 20 7946 0958").as_deref(),
+            Some("+442079460958")
+        );
+        assert_eq!(normalize_handle("262966").as_deref(), Some("262966"));
+        assert_eq!(normalize_handle("555-1234").as_deref(), Some("5551234"));
+        assert_eq!(
+            normalize_handle(" Sam@Example.COM ").as_deref(),
+            Some("sam@example.com")
+        );
+        assert_eq!(normalize_handle("no digits"), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use super::vcard::VCard;
+use super::{normalize_handle, ContactBook, ContactsError};
+use crate::utils::app_data_dir;
+
+/// A name the user gave a handle by hand
+#[derive(Debug, Clone, Serialize)]
+pub struct Alias {
+    pub id: i64,
+    pub handle: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportSummary {
+    pub contacts: usize,
+    pub handles: usize,
+}
+
+/// Contacts the app owns: imported vCards and manual aliases, kept in
+/// `contacts.db` in the app data directory
+pub struct ContactStore {
+    conn: Connection,
+}
+
+impl ContactStore {
+    pub fn open() -> Result<Self, ContactsError> {
+        Self::open_at(&app_data_dir().join("contacts.db"))
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self, ContactsError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS imported_contact (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                handle TEXT NOT NULL,
+                normalized_handle TEXT NOT NULL UNIQUE,
+                source TEXT,
+                imported_at TEXT NOT NULL,
+                card INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS alias (
+                id INTEGER PRIMARY KEY,
+                handle TEXT NOT NULL,
+                normalized_handle TEXT NOT NULL UNIQUE,
+                name TEXT NOT NULL
+            );
+            "#,
+        )?;
+        // Stores from before cards were numbered: keep each handle apart
+        let has_card: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('imported_contact') WHERE name = 'card'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_card {
+            conn.execute_batch(
+                "ALTER TABLE imported_contact ADD COLUMN card INTEGER;
+                 UPDATE imported_contact SET card = id;",
+            )?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Save the handles from `cards`, numbering each card so its handles
+    /// stay together. Re-importing a handle replaces the name and card it
+    /// had before.
+    pub fn import_vcards(
+        &mut self,
+        cards: &[VCard],
+        source: &str,
+    ) -> Result<ImportSummary, ContactsError> {
+        let tx = self.conn.transaction()?;
+        let mut summary = ImportSummary::default();
+        {
+            let mut stmt = tx.prepare(
+                r#"
+                INSERT INTO imported_contact (name, handle, normalized_handle, source, imported_at, card)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT(normalized_handle) DO UPDATE SET
+                    name = excluded.name,
+                    handle = excluded.handle,
+                    source = excluded.source,
+                    imported_at = excluded.imported_at,
+                    card = excluded.card
+                "#,
+            )?;
+            let now = Utc::now().to_rfc3339();
+            let mut next_card: i64 = tx.query_row(
+                "SELECT COALESCE(MAX(card), 0) FROM imported_contact",
+                [],
+                |row| row.get(0),
+            )?;
+            for card in cards {
+                next_card += 1;
+                let mut imported = false;
+                for handle in &card.handles {
+                    let Some(normalized) = normalize_handle(handle) else {
+                        continue;
+                    };
+                    stmt.execute(params![
+                        card.name, handle, normalized, source, now, next_card
+                    ])?;
+                    summary.handles += 1;
+                    imported = true;
+                }
+                summary.contacts += usize::from(imported);
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    pub fn list_aliases(&self) -> Result<Vec<Alias>, ContactsError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, handle, name FROM alias ORDER BY name COLLATE NOCASE")?;
+        let aliases = stmt
+            .query_map([], |row| {
+                Ok(Alias {
+                    id: row.get(0)?,
+                    handle: row.get(1)?,
+                    name: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(aliases)
+    }
+
+    /// Name a handle, replacing any alias it already had
+    pub fn add_alias(&self, handle: &str, name: &str) -> Result<Alias, ContactsError> {
+        let normalized = normalize_alias(handle, name)?;
+        let id = self.conn.query_row(
+            r#"
+            INSERT INTO alias (handle, normalized_handle, name) VALUES (?1, ?2, ?3)
+            ON CONFLICT(normalized_handle) DO UPDATE SET
+                handle = excluded.handle,
+                name = excluded.name
+            RETURNING id
+            "#,
+            params![handle.trim(), normalized, name.trim()],
+            |row| row.get(0),
+        )?;
+        Ok(Alias {
+            id,
+            handle: handle.trim().to_string(),
+            name: name.trim().to_string(),
+        })
+    }
+
+    pub fn update_alias(&self, id: i64, handle: &str, name: &str) -> Result<Alias, ContactsError> {
+        let normalized = normalize_alias(handle, name)?;
+        let updated = self
+            .conn
+            .query_row(
+                r#"
+                UPDATE alias SET handle = ?2, normalized_handle = ?3, name = ?4
+                WHERE id = ?1
+                RETURNING id
+                "#,
+                params![id, handle.trim(), normalized, name.trim()],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?;
+        match updated {
+            Some(id) => Ok(Alias {
+                id,
+                handle: handle.trim().to_string(),
+                name: name.trim().to_string(),
+            }),
+            None => Err(ContactsError::AliasNotFound(id)),
+        }
+    }
+
+    pub fn delete_alias(&self, id: i64) -> Result<(), ContactsError> {
+        match self
+            .conn
+            .execute("DELETE FROM alias WHERE id = ?1", params![id])?
+        {
+            0 => Err(ContactsError::AliasNotFound(id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add aliases, then imported contacts, to `book`. Aliases come first
+    /// so they win over any other name for the same handle. Each alias is
+    /// its own contact, since two people can share a name.
+    pub fn add_to(&self, book: &mut ContactBook) -> Result<(), ContactsError> {
+        for sql in [
+            "SELECT handle, name, 'alias:' || id FROM alias",
+            r#"
+            SELECT handle, name, 'vcard:' || COALESCE(card, 'row' || id)
+            FROM imported_contact
+            ORDER BY imported_at DESC
+            "#,
+        ] {
+            let mut stmt = self.conn.prepare(sql)?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (handle, name, contact) = row?;
+                book.insert(&contact, &handle, &name);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn normalize_alias(handle: &str, name: &str) -> Result<String, ContactsError> {
+    if name.trim().is_empty() {
+        return Err(ContactsError::InvalidAlias("name is empty".to_string()));
+    }
+    normalize_handle(handle).ok_or_else(|| {
+        ContactsError::InvalidAlias(format!("{handle:?} is not a phone number or email"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contacts::vcard::parse_vcards;
+
+    #[test]
+    fn test_aliases_win_over_imported_contacts() {
+        let mut store = ContactStore::open_at(Path::new(":memory:")).unwrap();
+        let cards = parse_vcards(
+            "BEGIN:VCARD\nFN:Linda Smith\nTEL:555-123-4567\nEMAIL:linda@example.com\nEND:VCARD\n",
+        );
+        let summary = store.import_vcards(&cards, "family.vcf").unwrap();
+        assert_eq!((summary.contacts, summary.handles), (1, 2));
+
+        let alias = store.add_alias("+1 555 123 4567", "Mom").unwrap();
+        let mut book = ContactBook::default();
+        store.add_to(&mut book).unwrap();
+        assert_eq!(book.resolve("+15551234567"), Some("Mom"));
+        assert_eq!(book.resolve("linda@example.com"), Some("Linda Smith"));
+
+        let sam = "BEGIN:VCARD\nFN:Sam\nTEL:555-000-1111\nEMAIL:sam@one.example\nEND:VCARD\n";
+        let other_sam = "BEGIN:VCARD\nFN:Sam\nTEL:555-000-2222\nEND:VCARD\n";
+        store
+            .import_vcards(&parse_vcards(&format!("{sam}{other_sam}")), "work.vcf")
+            .unwrap();
+        let other_mom = store.add_alias("555-000-3333", "Mom").unwrap();
+        let mut book = ContactBook::default();
+        store.add_to(&mut book).unwrap();
+        assert_eq!(
+            book.contact_key("5550001111"),
+            book.contact_key("sam@one.example")
+        );
+        assert_ne!(
+            book.contact_key("5550001111"),
+            book.contact_key("5550002222")
+        );
+        assert_ne!(
+            book.contact_key("5551234567"),
+            book.contact_key("5550003333")
+        );
+
+        store.delete_alias(alias.id).unwrap();
+        store.delete_alias(other_mom.id).unwrap();
+        assert!(store.list_aliases().unwrap().is_empty());
+        assert!(store.add_alias("not a handle", "Someone").is_err());
+    }
+}
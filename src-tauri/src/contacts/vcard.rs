@@ -0,0 +1,152 @@
+/// One contact read from a `.vcf` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct VCard {
+    pub name: String,
+    /// Phone numbers and emails, as written in the card
+    pub handles: Vec<String>,
+}
+
+/// Parse every card in a vCard 3.0/4.0 file. Cards without a usable name
+/// or without any phone number or email are skipped.
+pub fn parse_vcards(text: &str) -> Vec<VCard> {
+    let mut cards = Vec::new();
+    let mut current: Option<CardFields> = None;
+
+    for line in unfold(text) {
+        let Some((property, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = property.split(';');
+        // Apple exports group related lines as `item1.TEL`
+        let name = params.next().unwrap_or_default();
+        let name = name.rsplit('.').next().unwrap_or(name).to_ascii_uppercase();
+
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VCARD") => {
+                current = Some(CardFields::default());
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => {
+                cards.extend(current.take().and_then(CardFields::finish));
+            }
+            ("FN", Some(card)) => card.formatted_name = Some(unescape(value)),
+            ("N", Some(card)) => card.structured_name = Some(structured_name(value)),
+            ("NICKNAME", Some(card)) => card.nickname = Some(unescape(value)),
+            ("ORG", Some(card)) => {
+                card.organization = value.split(';').next().map(unescape);
+            }
+            ("TEL" | "EMAIL", Some(card)) => {
+                let value = unescape(value);
+                if !value.trim().is_empty() {
+                    card.handles.push(value.trim().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    cards
+}
+
+#[derive(Default)]
+struct CardFields {
+    formatted_name: Option<String>,
+    structured_name: Option<String>,
+    nickname: Option<String>,
+    organization: Option<String>,
+    handles: Vec<String>,
+}
+
+impl CardFields {
+    fn finish(self) -> Option<VCard> {
+        let name = [
+            self.formatted_name,
+            self.structured_name,
+            self.nickname,
+            self.organization,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())?;
+
+        (!self.handles.is_empty()).then_some(VCard {
+            name,
+            handles: self.handles,
+        })
+    }
+}
+
+/// Lines starting with a space or tab continue the previous line
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(previous)) => previous.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// `N:Last;First;Middle;Prefix;Suffix` as "First Middle Last"
+fn structured_name(value: &str) -> String {
+    let parts: Vec<String> = value.split(';').map(unescape).collect();
+    let get = |i: usize| parts.get(i).map(String::as_str).unwrap_or_default();
+    [get(3), get(1), get(2), get(0), get(4)]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(escaped) => out.push(escaped),
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vcards() {
+        let text = "BEGIN:VCARD\r\n\
+            VERSION:3.0\r\n\
+            N:Lee;Sam;;;\r\n\
+            FN:Sam Lee\r\n\
+            item1.TEL;type=CELL;type=pref:+1 (555) 123-4567\r\n\
+            TEL;TYPE=HOME:555-000-\r\n 1111\r\n\
+            EMAIL;TYPE=INTERNET:sam@example.com\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            N:Garc\\,ia;Ana;;Dr.;\r\n\
+            TEL;VALUE=uri;TYPE=\"voice,cell\":tel:+44-20-7946-0958\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            FN:No Handles\r\n\
+            END:VCARD\r\n";
+
+        let cards = parse_vcards(text);
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].name, "Sam Lee");
+        assert_eq!(
+            cards[0].handles,
+            ["+1 (555) 123-4567", "555-000-1111", "sam@example.com"]
+        );
+        assert_eq!(cards[1].name, "Dr. Ana Garc,ia");
+        assert_eq!(cards[1].handles, ["tel:+44-20-7946-0958"]);
+    }
+}
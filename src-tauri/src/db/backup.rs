@@ -0,0 +1,94 @@
+use plist::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+use super::connection::{open_read_only, DbError};
+
+const SMS_DB_DOMAIN: &str = "HomeDomain";
+const SMS_DB_RELATIVE_PATH: &str = "Library/SMS/sms.db";
+const ATTACHMENT_DOMAIN: &str = "MediaDomain";
+
+/// An unencrypted Finder/iTunes iPhone backup. Files inside a backup are
+/// stored under hashed names, so every lookup goes through Manifest.db.
+pub struct IosBackup {
+    root: PathBuf,
+    manifest: Connection,
+}
+
+impl IosBackup {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, DbError> {
+        let root = root.as_ref().to_path_buf();
+        if !root.is_dir() {
+            return Err(DbError::NotFound(root));
+        }
+
+        if Self::is_encrypted(&root)? {
+            return Err(DbError::EncryptedBackup);
+        }
+
+        let manifest = open_read_only(&root.join("Manifest.db"))?;
+        Ok(Self { root, manifest })
+    }
+
+    fn is_encrypted(root: &Path) -> Result<bool, DbError> {
+        let path = root.join("Manifest.plist");
+        if !path.exists() {
+            return Err(DbError::InvalidBackup(format!(
+                "Manifest.plist not found in {}",
+                root.display()
+            )));
+        }
+
+        let manifest =
+            Value::from_file(&path).map_err(|e| DbError::InvalidBackup(e.to_string()))?;
+
+        Ok(manifest
+            .as_dictionary()
+            .and_then(|dict| dict.get("IsEncrypted"))
+            .and_then(Value::as_boolean)
+            .unwrap_or(false))
+    }
+
+    /// Location of the Messages database inside the backup
+    pub fn sms_db_path(&self) -> Result<PathBuf, DbError> {
+        self.resolve(SMS_DB_DOMAIN, SMS_DB_RELATIVE_PATH)?
+            .ok_or_else(|| DbError::InvalidBackup("backup does not contain sms.db".to_string()))
+    }
+
+    /// Map an attachment filename as stored in sms.db
+    /// (e.g. `~/Library/SMS/Attachments/ab/11/<guid>/IMG_0001.HEIC`)
+    /// to the file holding it in the backup
+    pub fn resolve_attachment(&self, filename: &str) -> Result<Option<PathBuf>, DbError> {
+        let relative_path = filename
+            .strip_prefix("~/")
+            .or_else(|| filename.strip_prefix("/var/mobile/"))
+            .unwrap_or(filename);
+        self.resolve(ATTACHMENT_DOMAIN, relative_path)
+    }
+
+    /// Look up a file by its domain and path on the phone
+    pub fn resolve(&self, domain: &str, relative_path: &str) -> Result<Option<PathBuf>, DbError> {
+        let file_id: Option<String> = self
+            .manifest
+            .query_row(
+                "SELECT fileID FROM Files WHERE domain = ?1 AND relativePath = ?2",
+                params![domain, relative_path],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(file_id.and_then(|id| self.file_path(&id)))
+    }
+
+    fn file_path(&self, file_id: &str) -> Option<PathBuf> {
+        // Backups since iOS 10 shard files into folders named after the
+        // first two characters of the hash; older ones keep them flat
+        let sharded = self.root.join(file_id.get(..2)?).join(file_id);
+        if sharded.exists() {
+            return Some(sharded);
+        }
+
+        let flat = self.root.join(file_id);
+        flat.exists().then_some(flat)
+    }
+}
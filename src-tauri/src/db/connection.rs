@@ -1,13 +1,27 @@
 use rusqlite::{Connection, OpenFlags};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use thiserror::Error;
 
+use super::backup::IosBackup;
+use super::edits;
+use super::schema::Schema;
+use crate::contacts::ContactBook;
+
 #[derive(Error, Debug)]
 pub enum DbError {
     #[error("Database not found at {0}")]
     NotFound(PathBuf),
     #[error("Permission denied - Full Disk Access required")]
     PermissionDenied,
+    #[error("Invalid iPhone backup: {0}")]
+    InvalidBackup(String),
+    #[error("iPhone backup is encrypted - disable \"Encrypt local backup\" in Finder and back up again")]
+    EncryptedBackup,
+    #[error("Failed to snapshot database: {0}")]
+    Snapshot(String),
+    #[error("Unsupported database: {0}")]
+    UnsupportedSchema(String),
     #[error("Database error: {0}")]
     SqliteError(#[from] rusqlite::Error),
 }
@@ -21,37 +35,107 @@ impl serde::Serialize for DbError {
     }
 }
 
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
 pub struct ChatDb {
     pub conn: Connection,
+    /// Set when reading sms.db out of an iPhone backup, so attachment
+    /// paths can be mapped to the backup's hashed file names
+    pub backup: Option<IosBackup>,
+    /// Names used to fill in `contact_name`; empty until the app hands
+    /// over the user's contacts
+    pub contacts: Arc<ContactBook>,
+    /// Which optional tables and columns this database has
+    pub schema: Schema,
 }
 
 impl ChatDb {
+    /// Open the default Messages database for the current user
     pub fn new() -> Result<Self, DbError> {
-        let home = std::env::var("HOME").expect("HOME not set");
-        let db_path = PathBuf::from(home).join("Library/Messages/chat.db");
+        Self::open(Self::db_path())
+    }
 
-        if !db_path.exists() {
-            return Err(DbError::NotFound(db_path));
-        }
+    /// Open a chat.db at an arbitrary path
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DbError> {
+        let conn = open_read_only(path.as_ref())?;
+        Self::from_connection(conn, None)
+    }
 
-        // Open in read-only mode to avoid conflicts with Messages.app
-        let conn = Connection::open_with_flags(
-            &db_path,
-            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        )
-        .map_err(|e| {
-            if e.to_string().contains("unable to open") {
-                DbError::PermissionDenied
-            } else {
-                DbError::SqliteError(e)
-            }
-        })?;
+    /// Open the sms.db stored inside an unencrypted iPhone backup folder
+    pub fn open_backup(backup_dir: impl AsRef<Path>) -> Result<Self, DbError> {
+        let backup = IosBackup::open(backup_dir)?;
+        let conn = open_read_only(&backup.sms_db_path()?)?;
+        Self::from_connection(conn, Some(backup))
+    }
 
-        Ok(Self { conn })
+    pub(super) fn from_connection(conn: Connection, backup: Option<IosBackup>) -> Result<Self, DbError> {
+        edits::register_functions(&conn)?;
+        let schema = Schema::read(&conn)?;
+        Ok(Self {
+            conn,
+            backup,
+            contacts: Arc::default(),
+            schema,
+        })
     }
 
+    /// Location of the current user's Messages database
     pub fn db_path() -> PathBuf {
         let home = std::env::var("HOME").expect("HOME not set");
         PathBuf::from(home).join("Library/Messages/chat.db")
     }
+
+    /// Resolve an attachment filename from the `attachment` table to the
+    /// file on disk, expanding `~` or going through the backup manifest
+    pub fn attachment_path(&self, filename: &str) -> Result<Option<PathBuf>, DbError> {
+        if let Some(backup) = &self.backup {
+            return backup.resolve_attachment(filename);
+        }
+
+        let path = expand_home(filename);
+        Ok(path.exists().then_some(path))
+    }
+
+    /// Best-effort path for showing an attachment: the resolved file when it
+    /// can be found, otherwise the stored name with `~` expanded
+    pub fn attachment_display_path(&self, filename: &str) -> String {
+        match self.attachment_path(filename) {
+            Ok(Some(path)) => path.to_string_lossy().into_owned(),
+            _ if self.backup.is_some() => filename.to_string(),
+            _ => expand_home(filename).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+fn expand_home(filename: &str) -> PathBuf {
+    match filename.strip_prefix("~/") {
+        Some(rest) => {
+            let home = std::env::var("HOME").expect("HOME not set");
+            PathBuf::from(home).join(rest)
+        }
+        None => PathBuf::from(filename),
+    }
+}
+
+pub(crate) fn open_read_only(db_path: &Path) -> Result<Connection, DbError> {
+    if !db_path.exists() {
+        return Err(DbError::NotFound(db_path.to_path_buf()));
+    }
+
+    // Open in read-only mode to avoid conflicts with Messages.app
+    let conn = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|e| {
+        if e.to_string().contains("unable to open") {
+            DbError::PermissionDenied
+        } else {
+            DbError::SqliteError(e)
+        }
+    })?;
+
+    // Connections are pooled, so keep every query's statement prepared
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(conn)
 }
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use plist::Value;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+
+use super::models::MessageVersion;
+use super::parser::decode_attributed_body;
+use crate::utils::{mac_timestamp_to_datetime, mac_timestamp_to_nanos};
+
+/// What `message_summary_info` records about edits and unsends
+#[derive(Debug, Default)]
+pub struct SummaryInfo {
+    /// Every version of every edited part, oldest first
+    pub versions: Vec<MessageVersion>,
+    /// Parts that were unsent
+    pub retracted_parts: Vec<i64>,
+}
+
+/// Decode the `message_summary_info` plist. Edited parts live under `ec`
+/// (part index -> list of `{d: date, t: attributedBody}` versions) and
+/// unsent parts under `rp`.
+pub fn parse_summary_info(data: &[u8]) -> Option<SummaryInfo> {
+    let plist: Value = plist::from_bytes(data).ok()?;
+    let dict = plist.as_dictionary()?;
+    let mut info = SummaryInfo::default();
+
+    if let Some(edited) = dict.get("ec").and_then(Value::as_dictionary) {
+        for (part, versions) in edited {
+            let part_index = part.parse().unwrap_or(0);
+            for version in versions.as_array().into_iter().flatten() {
+                let Some(version) = version.as_dictionary() else {
+                    continue;
+                };
+                info.versions.push(MessageVersion {
+                    part_index,
+                    text: version
+                        .get("t")
+                        .and_then(Value::as_data)
+                        .and_then(decode_attributed_body),
+                    date: version.get("d").and_then(plist_date),
+                });
+            }
+        }
+    }
+    info.versions.sort_by_key(|v| (v.part_index, v.date));
+
+    if let Some(retracted) = dict.get("rp").and_then(Value::as_array) {
+        info.retracted_parts = retracted
+            .iter()
+            .filter_map(|part| part.as_signed_integer())
+            .collect();
+    }
+
+    Some(info)
+}
+
+/// Edit dates are stored as seconds since 2001-01-01, either as an integer
+/// or a real, unlike the nanosecond columns in `message`
+fn plist_date(value: &Value) -> Option<DateTime<Utc>> {
+    let seconds = match value {
+        Value::Integer(i) => i.as_signed()? as f64,
+        Value::Real(r) => *r,
+        _ => return None,
+    };
+    Some(mac_timestamp_to_datetime(
+        (seconds * 1_000_000_000.0) as i64,
+    ))
+}
+
+/// Register `message_edit_text(message_summary_info)`, which returns the
+/// text of every earlier version of a message so SQL can search it, and
+/// `mac_timestamp_nanos(date)`, which reads a date in either unit as
+/// nanoseconds so rows stored in seconds filter and sort correctly
+pub fn register_functions(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.create_scalar_function(
+        "mac_timestamp_nanos",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let timestamp: Option<i64> = ctx.get(0)?;
+            Ok(timestamp.map(mac_timestamp_to_nanos))
+        },
+    )?;
+    conn.create_scalar_function(
+        "message_edit_text",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let data: Option<Vec<u8>> = ctx.get(0)?;
+            let text = data.and_then(|data| parse_summary_info(&data)).map(|info| {
+                info.versions
+                    .into_iter()
+                    .filter_map(|v| v.text)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+            Ok(text)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use plist::Dictionary;
+
+    fn version(seconds: i64) -> Value {
+        let mut dict = Dictionary::new();
+        dict.insert("d".to_string(), Value::Integer(seconds.into()));
+        Value::Dictionary(dict)
+    }
+
+    #[test]
+    fn test_parse_summary_info() {
+        let mut edited = Dictionary::new();
+        edited.insert(
+            "0".to_string(),
+            Value::Array(vec![version(700_000_100), version(700_000_000)]),
+        );
+        let mut root = Dictionary::new();
+        root.insert("ec".to_string(), Value::Dictionary(edited));
+        root.insert(
+            "rp".to_string(),
+            Value::Array(vec![Value::Integer(1.into())]),
+        );
+
+        let mut data = Vec::new();
+        Value::Dictionary(root).to_writer_binary(&mut data).unwrap();
+
+        let info = parse_summary_info(&data).unwrap();
+        assert_eq!(info.versions.len(), 2);
+        assert_eq!(
+            info.versions[0].date,
+            Some(Utc.timestamp_opt(978_307_200 + 700_000_000, 0).unwrap())
+        );
+        assert_eq!(info.retracted_parts, vec![1]);
+    }
+}
@@ -0,0 +1,431 @@
+//! Builds synthetic chat.db databases shaped like the ones Messages writes,
+//! so queries can be tested without a Mac.
+
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use plist::{Dictionary, Value};
+use rusqlite::{params, Connection};
+
+use super::connection::{ChatDb, DbError};
+use crate::utils::datetime_to_mac_timestamp;
+
+/// The tables and columns the app reads, as a recent macOS creates them
+const SCHEMA: &str = r#"
+    CREATE TABLE handle (
+        ROWID INTEGER PRIMARY KEY AUTOINCREMENT, id TEXT NOT NULL, country TEXT,
+        service TEXT NOT NULL, uncanonicalized_id TEXT, person_centric_id TEXT
+    );
+    CREATE TABLE chat (
+        ROWID INTEGER PRIMARY KEY AUTOINCREMENT, guid TEXT UNIQUE NOT NULL, style INTEGER,
+        state INTEGER, account_id TEXT, properties BLOB, chat_identifier TEXT,
+        service_name TEXT, room_name TEXT, display_name TEXT
+    );
+    CREATE TABLE message (
+        ROWID INTEGER PRIMARY KEY AUTOINCREMENT, guid TEXT UNIQUE NOT NULL, text TEXT,
+        handle_id INTEGER DEFAULT 0, service TEXT, date INTEGER, date_read INTEGER,
+        date_delivered INTEGER, is_from_me INTEGER DEFAULT 0, attributedBody BLOB,
+        balloon_bundle_id TEXT, associated_message_guid TEXT,
+        associated_message_type INTEGER DEFAULT 0, thread_originator_guid TEXT,
+        thread_originator_part TEXT, date_edited INTEGER DEFAULT 0,
+        date_retracted INTEGER DEFAULT 0, message_summary_info BLOB,
+        cache_has_attachments INTEGER DEFAULT 0
+    );
+    CREATE TABLE attachment (
+        ROWID INTEGER PRIMARY KEY AUTOINCREMENT, guid TEXT UNIQUE NOT NULL, filename TEXT,
+        uti TEXT, mime_type TEXT, transfer_name TEXT, total_bytes INTEGER DEFAULT 0,
+        is_sticker INTEGER DEFAULT 0
+    );
+    CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER, UNIQUE(chat_id, handle_id));
+    CREATE TABLE chat_message_join (
+        chat_id INTEGER, message_id INTEGER, message_date INTEGER DEFAULT 0,
+        PRIMARY KEY (chat_id, message_id)
+    );
+    CREATE TABLE message_attachment_join (
+        message_id INTEGER, attachment_id INTEGER, UNIQUE(message_id, attachment_id)
+    );
+"#;
+
+/// Chat styles Messages uses for group and 1:1 chats
+const GROUP_STYLE: i64 = 43;
+const DIRECT_STYLE: i64 = 45;
+
+/// One message to add. `from` is a handle ROWID; `None` means I sent it.
+#[derive(Debug, Clone, Default)]
+pub struct MessageSpec<'a> {
+    pub text: &'a str,
+    pub from: Option<i64>,
+    pub date: DateTime<Utc>,
+    /// Store the text only in `attributedBody`, as most messages since
+    /// High Sierra are
+    pub attributed_only: bool,
+    /// Store dates in seconds, as databases from before High Sierra do
+    pub legacy_seconds: bool,
+    pub read: Option<DateTime<Utc>>,
+    /// Guid of the message this is an inline reply to
+    pub reply_to: Option<&'a str>,
+    /// Earlier versions of the text, oldest first; the message is edited
+    /// once per version
+    pub edits: Vec<&'a str>,
+    pub unsent: bool,
+    /// Parts unsent while the rest of the message stays
+    pub unsent_parts: Vec<i64>,
+    pub attachments: Vec<AttachmentSpec<'a>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentSpec<'a> {
+    /// Stored as given, so `~/Library/...` paths are expanded on read
+    pub filename: &'a str,
+    pub mime_type: &'a str,
+    pub total_bytes: i64,
+    pub is_sticker: bool,
+}
+
+/// A message row the fixture wrote
+#[derive(Debug, Clone)]
+pub struct FixtureMessage {
+    pub id: i64,
+    pub guid: String,
+}
+
+/// A chat.db under construction, in memory
+pub struct Fixture {
+    conn: Connection,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let conn = Connection::open_in_memory().expect("in-memory database");
+        conn.execute_batch(SCHEMA).expect("fixture schema");
+        Self { conn }
+    }
+
+    pub fn handle(&mut self, identifier: &str, service: &str) -> i64 {
+        self.conn
+            .execute(
+                "INSERT INTO handle (id, service, uncanonicalized_id) VALUES (?1, ?2, ?1)",
+                params![identifier, service],
+            )
+            .expect("insert handle");
+        self.conn.last_insert_rowid()
+    }
+
+    /// A 1:1 chat with `handle`
+    pub fn chat(&mut self, handle: i64) -> i64 {
+        let (identifier, service): (String, String) = self
+            .conn
+            .query_row(
+                "SELECT id, service FROM handle WHERE ROWID = ?1",
+                [handle],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .expect("chat handle exists");
+        let guid = format!("{service};-;{identifier}");
+        self.insert_chat(&guid, DIRECT_STYLE, &identifier, &service, None, &[handle])
+    }
+
+    /// A group chat, named or not
+    pub fn group(&mut self, name: Option<&str>, handles: &[i64]) -> i64 {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM chat", [], |row| row.get(0))
+            .unwrap_or_default();
+        let identifier = format!("chat{}", 100_000 + count);
+        let guid = format!("iMessage;+;{identifier}");
+        self.insert_chat(&guid, GROUP_STYLE, &identifier, "iMessage", name, handles)
+    }
+
+    fn insert_chat(
+        &mut self,
+        guid: &str,
+        style: i64,
+        identifier: &str,
+        service: &str,
+        name: Option<&str>,
+        handles: &[i64],
+    ) -> i64 {
+        self.conn
+            .execute(
+                "INSERT INTO chat (guid, style, chat_identifier, service_name, display_name) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![guid, style, identifier, service, name],
+            )
+            .expect("insert chat");
+        let chat_id = self.conn.last_insert_rowid();
+        for handle in handles {
+            self.conn
+                .execute(
+                    "INSERT INTO chat_handle_join (chat_id, handle_id) VALUES (?1, ?2)",
+                    params![chat_id, handle],
+                )
+                .expect("insert chat_handle_join");
+        }
+        chat_id
+    }
+
+    pub fn message(&mut self, chat_id: i64, spec: MessageSpec) -> FixtureMessage {
+        let guid = self.next_guid("M");
+        let timestamp = |date: DateTime<Utc>| {
+            let nanos = datetime_to_mac_timestamp(date);
+            if spec.legacy_seconds {
+                nanos / 1_000_000_000
+            } else {
+                nanos
+            }
+        };
+        let date = timestamp(spec.date);
+        let service: String = match spec.from {
+            Some(handle) => self
+                .conn
+                .query_row(
+                    "SELECT service FROM handle WHERE ROWID = ?1",
+                    [handle],
+                    |row| row.get(0),
+                )
+                .expect("sender handle exists"),
+            None => "iMessage".to_string(),
+        };
+
+        let text = (!spec.attributed_only && !spec.unsent).then_some(spec.text);
+        let attributed_body = (!spec.unsent).then(|| attributed_body(spec.text));
+        let date_edited = spec
+            .edits
+            .last()
+            .map(|_| timestamp(edit_date(&spec, spec.edits.len())));
+        let date_retracted = (spec.unsent || !spec.unsent_parts.is_empty())
+            .then(|| timestamp(spec.date + Duration::minutes(1)));
+        let summary_info = summary_info(&spec);
+        let reply_part = spec.reply_to.map(|_| "0:0:1");
+
+        self.conn
+            .execute(
+                r#"
+                INSERT INTO message (
+                    guid, text, handle_id, service, date, date_read, date_delivered,
+                    is_from_me, attributedBody, thread_originator_guid,
+                    thread_originator_part, date_edited, date_retracted,
+                    message_summary_info, cache_has_attachments
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?5, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                "#,
+                params![
+                    guid,
+                    text,
+                    spec.from.unwrap_or(0),
+                    service,
+                    date,
+                    spec.read.map(timestamp).unwrap_or(0),
+                    spec.from.is_none(),
+                    attributed_body,
+                    spec.reply_to,
+                    reply_part,
+                    date_edited.unwrap_or(0),
+                    date_retracted.unwrap_or(0),
+                    summary_info,
+                    !spec.attachments.is_empty(),
+                ],
+            )
+            .expect("insert message");
+        let id = self.conn.last_insert_rowid();
+        self.join_chat(chat_id, id, date);
+
+        for attachment in &spec.attachments {
+            self.attachment(id, attachment);
+        }
+        FixtureMessage { id, guid }
+    }
+
+    /// A tapback on `target`. `associated_type` is 2000-2006 to add one and
+    /// 3000-3006 to remove it.
+    pub fn tapback(
+        &mut self,
+        chat_id: i64,
+        target: &FixtureMessage,
+        associated_type: i64,
+        from: Option<i64>,
+        date: DateTime<Utc>,
+    ) -> FixtureMessage {
+        let guid = self.next_guid("T");
+        let date = datetime_to_mac_timestamp(date);
+        self.conn
+            .execute(
+                r#"
+                INSERT INTO message (
+                    guid, text, handle_id, service, date, is_from_me,
+                    associated_message_guid, associated_message_type
+                ) VALUES (?1, 'Reacted to a message', ?2, 'iMessage', ?3, ?4, ?5, ?6)
+                "#,
+                params![
+                    guid,
+                    from.unwrap_or(0),
+                    date,
+                    from.is_none(),
+                    format!("p:0/{}", target.guid),
+                    associated_type,
+                ],
+            )
+            .expect("insert tapback");
+        let id = self.conn.last_insert_rowid();
+        self.join_chat(chat_id, id, date);
+        FixtureMessage { id, guid }
+    }
+
+    fn attachment(&mut self, message_id: i64, spec: &AttachmentSpec) {
+        let guid = self.next_guid("A");
+        let transfer_name = Path::new(spec.filename)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.conn
+            .execute(
+                r#"
+                INSERT INTO attachment (guid, filename, uti, mime_type, transfer_name, total_bytes, is_sticker)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                "#,
+                params![
+                    guid,
+                    spec.filename,
+                    uti(spec.mime_type),
+                    spec.mime_type,
+                    transfer_name,
+                    spec.total_bytes,
+                    spec.is_sticker,
+                ],
+            )
+            .expect("insert attachment");
+        self.conn
+            .execute(
+                "INSERT INTO message_attachment_join (message_id, attachment_id) VALUES (?1, ?2)",
+                params![message_id, self.conn.last_insert_rowid()],
+            )
+            .expect("insert message_attachment_join");
+    }
+
+    fn join_chat(&mut self, chat_id: i64, message_id: i64, date: i64) {
+        self.conn
+            .execute(
+                "INSERT INTO chat_message_join (chat_id, message_id, message_date) VALUES (?1, ?2, ?3)",
+                params![chat_id, message_id, date],
+            )
+            .expect("insert chat_message_join");
+    }
+
+    /// Guids are unique across every table, like the UUIDs Messages uses
+    fn next_guid(&self, prefix: &str) -> String {
+        let count: i64 = self
+            .conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM message) + (SELECT COUNT(*) FROM attachment)",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or_default();
+        format!("{prefix}{:08X}-FIXTURE", count + 1)
+    }
+
+    /// Copy the database to `path`, e.g. to point the app at for a demo
+    pub fn save(&self, path: &Path) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute("VACUUM INTO ?1", [path.to_string_lossy()])
+            .map(|_| ())
+    }
+
+    pub fn into_db(self) -> Result<ChatDb, DbError> {
+        ChatDb::from_connection(self.conn, None)
+    }
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dates the message was edited to each version after the first
+fn edit_date(spec: &MessageSpec, edit: usize) -> DateTime<Utc> {
+    spec.date + Duration::minutes(edit as i64)
+}
+
+/// `message_summary_info` recording edits (`ec`) and unsends (`rp`). Each
+/// version in `ec` holds the text before the next edit; the last is the
+/// current text.
+fn summary_info(spec: &MessageSpec) -> Option<Vec<u8>> {
+    if spec.edits.is_empty() && !spec.unsent && spec.unsent_parts.is_empty() {
+        return None;
+    }
+
+    let mut root = Dictionary::new();
+    if !spec.edits.is_empty() {
+        let versions = spec
+            .edits
+            .iter()
+            .chain(std::iter::once(&spec.text))
+            .enumerate()
+            .map(|(i, text)| {
+                let seconds = datetime_to_mac_timestamp(edit_date(spec, i)) / 1_000_000_000;
+                let mut version = Dictionary::new();
+                version.insert("d".to_string(), Value::Integer(seconds.into()));
+                version.insert("t".to_string(), Value::Data(attributed_body(text)));
+                Value::Dictionary(version)
+            })
+            .collect();
+        let mut edited = Dictionary::new();
+        edited.insert("0".to_string(), Value::Array(versions));
+        root.insert("ec".to_string(), Value::Dictionary(edited));
+    }
+    let retracted: Vec<Value> = if spec.unsent {
+        vec![Value::Integer(0.into())]
+    } else {
+        spec.unsent_parts
+            .iter()
+            .map(|&part| Value::Integer(part.into()))
+            .collect()
+    };
+    if !retracted.is_empty() {
+        root.insert("rp".to_string(), Value::Array(retracted));
+    }
+
+    let mut data = Vec::new();
+    Value::Dictionary(root).to_writer_binary(&mut data).ok()?;
+    Some(data)
+}
+
+fn uti(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/jpeg" => "public.jpeg",
+        "image/png" => "public.png",
+        "image/heic" => "public.heic",
+        "video/quicktime" => "com.apple.quicktime-movie",
+        "application/pdf" => "com.adobe.pdf",
+        _ => "public.data",
+    }
+}
+
+/// Encode `text` as the `attributedBody` typedstream Messages writes: an
+/// `NSMutableAttributedString` with one run carrying message part 0
+pub fn attributed_body(text: &str) -> Vec<u8> {
+    const HEADER: &[u8] = b"\x04\x0bstreamtyped\x81\xe8\x03\x84\x01@\x84\x84\x84\x19NSMutableAttributedString\x00\x84\x84\x12NSAttributedString\x00\x84\x84\x08NSObject\x00\x85\x92\x84\x84\x84\x08NSString\x01\x94\x84\x01+";
+    const RUN: &[u8] = b"\x86\x84\x02iI\x01";
+    const ATTRIBUTES: &[u8] = b"\x92\x84\x84\x84\x0cNSDictionary\x00\x94\x84\x01i\x01\x92\x84\x96\x97\x1d__kIMMessagePartAttributeName\x86\x92\x84\x84\x84\x08NSNumber\x00\x84\x84\x07NSValue\x00\x94\x84\x01*\x84\x9a\x9a\x00\x86\x86\x86";
+
+    let mut data = HEADER.to_vec();
+    push_int(&mut data, text.len());
+    data.extend_from_slice(text.as_bytes());
+    data.extend_from_slice(RUN);
+    push_int(&mut data, text.encode_utf16().count());
+    data.extend_from_slice(ATTRIBUTES);
+    data
+}
+
+/// Typedstream integers: one byte when it can't be mistaken for a tag,
+/// otherwise a tag and two or four little-endian bytes
+fn push_int(data: &mut Vec<u8>, value: usize) {
+    if value < 0x80 {
+        data.push(value as u8);
+    } else if let Ok(value) = i16::try_from(value) {
+        data.push(0x81);
+        data.extend_from_slice(&value.to_le_bytes());
+    } else {
+        data.push(0x82);
+        data.extend_from_slice(&(value as i32).to_le_bytes());
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::Duration;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Deserialize;
+
+use super::connection::ChatDb;
+use super::models::{Cursor, Message, MessagePage};
+use crate::utils::mac_timestamp_to_datetime;
+
+/// Options for `get_chat_messages`. `start`/`end` are raw `message.date`
+/// values; `end` is exclusive.
+#[derive(Debug, Clone)]
+pub struct MessageQuery {
+    /// Only messages older than this
+    pub before: Option<Cursor>,
+    /// Only messages newer than this
+    pub after: Option<Cursor>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub limit: i64,
+}
+
+impl Default for MessageQuery {
+    fn default() -> Self {
+        Self {
+            before: None,
+            after: None,
+            start: None,
+            end: None,
+            limit: 100,
+        }
+    }
+}
+
+/// How much of the surrounding conversation to show around a message
+#[derive(Debug, Clone, Copy)]
+pub struct ContextOptions {
+    pub count: i64,
+    /// Stop at the first gap between messages longer than this
+    pub max_gap: Option<Duration>,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            count: 2,
+            max_gap: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextDirection {
+    Before,
+    After,
+}
+
+/// Shared by the page and "is there more?" queries. Cursors compare
+/// (date, ROWID) so messages with the same timestamp aren't skipped. Dates
+/// are compared as nanoseconds, whatever unit each row stores.
+const PAGE_FILTER: &str = r#"
+    cmj.chat_id = ?1
+    AND (?2 IS NULL OR mac_timestamp_nanos(m.date) >= ?2)
+    AND (?3 IS NULL OR mac_timestamp_nanos(m.date) < ?3)
+    AND (?4 IS NULL OR (mac_timestamp_nanos(m.date), m.ROWID) < (mac_timestamp_nanos(?4), ?5))
+    AND (?6 IS NULL OR (mac_timestamp_nanos(m.date), m.ROWID) > (mac_timestamp_nanos(?6), ?7))
+"#;
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Older,
+    Newer,
+}
+
+impl ChatDb {
+    /// A page of a chat's messages, newest first. With `after`, the page
+    /// is the oldest messages past that cursor, so scrolling forward
+    /// doesn't skip anything.
+    pub fn get_chat_messages(
+        &self,
+        chat_id: i64,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, rusqlite::Error> {
+        let direction = if query.after.is_some() && query.before.is_none() {
+            Direction::Newer
+        } else {
+            Direction::Older
+        };
+        let mut messages = self.query_page(chat_id, query, direction, query.limit)?;
+        if let Direction::Newer = direction {
+            messages.reverse();
+        }
+        self.hydrate_messages(&mut messages)?;
+        self.page_with_cursors(chat_id, query, messages)
+    }
+
+    /// The page of messages around `date`: half at or before it, half after
+    pub fn get_chat_messages_around(
+        &self,
+        chat_id: i64,
+        date: i64,
+        limit: i64,
+    ) -> Result<MessagePage, rusqlite::Error> {
+        let range = MessageQuery::default();
+        let newer_limit = limit / 2;
+
+        let before_query = MessageQuery {
+            before: Some(Cursor {
+                date,
+                rowid: i64::MAX,
+            }),
+            ..range.clone()
+        };
+        let older = self.query_page(
+            chat_id,
+            &before_query,
+            Direction::Older,
+            limit - newer_limit,
+        )?;
+
+        let after_query = MessageQuery {
+            after: Some(Cursor {
+                date,
+                rowid: i64::MAX,
+            }),
+            ..range.clone()
+        };
+        let mut messages = self.query_page(chat_id, &after_query, Direction::Newer, newer_limit)?;
+        messages.reverse();
+        messages.extend(older);
+
+        self.hydrate_messages(&mut messages)?;
+        self.page_with_cursors(chat_id, &range, messages)
+    }
+
+    /// Messages from the same chat either side of `message_id`, in
+    /// chronological order. Expand a context window by passing its first or
+    /// last message back in.
+    pub fn get_context(
+        &self,
+        message_id: i64,
+        direction: ContextDirection,
+        options: &ContextOptions,
+    ) -> Result<Vec<Message>, rusqlite::Error> {
+        let anchor: Option<(i64, i64)> = self
+            .conn
+            .prepare_cached(
+                r#"
+                SELECT cmj.chat_id, mac_timestamp_nanos(m.date)
+                FROM message m
+                INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+                WHERE m.ROWID = ?1
+                ORDER BY cmj.chat_id
+                LIMIT 1
+            "#,
+            )?
+            .query_row(params![message_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let Some((chat_id, date)) = anchor else {
+            return Ok(Vec::new());
+        };
+
+        let cursor = Some(Cursor {
+            date,
+            rowid: message_id,
+        });
+        let (query, walk) = match direction {
+            ContextDirection::Before => (
+                MessageQuery {
+                    before: cursor,
+                    ..MessageQuery::default()
+                },
+                Direction::Older,
+            ),
+            ContextDirection::After => (
+                MessageQuery {
+                    after: cursor,
+                    ..MessageQuery::default()
+                },
+                Direction::Newer,
+            ),
+        };
+        let mut messages = self.query_page(chat_id, &query, walk, options.count)?;
+
+        // Messages come back nearest first, so cut at the first long pause
+        if let Some(max_gap) = options.max_gap {
+            let mut previous = mac_timestamp_to_datetime(date);
+            let keep = messages
+                .iter()
+                .take_while(|message| {
+                    let gap = (message.date - previous).abs();
+                    previous = message.date;
+                    gap <= max_gap
+                })
+                .count();
+            messages.truncate(keep);
+        }
+
+        if let ContextDirection::Before = direction {
+            messages.reverse();
+        }
+        self.hydrate_messages(&mut messages)?;
+        Ok(messages)
+    }
+
+    fn query_page(
+        &self,
+        chat_id: i64,
+        query: &MessageQuery,
+        direction: Direction,
+        limit: i64,
+    ) -> Result<Vec<Message>, rusqlite::Error> {
+        let order = match direction {
+            Direction::Older => "DESC",
+            Direction::Newer => "ASC",
+        };
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM message m
+            INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            WHERE {PAGE_FILTER}
+              AND NOT {is_reaction}
+            ORDER BY mac_timestamp_nanos(m.date) {order}, m.ROWID {order}
+            LIMIT ?8
+        "#
+        );
+
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let params = page_params(chat_id, query);
+        let rows = stmt
+            .query_map(
+                params![
+                    params.0, params.1, params.2, params.3, params.4, params.5, params.6, limit
+                ],
+                Self::static_row_to_message,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Attach cursors for the messages on either side of `messages` (newest
+    /// first), if there are any within the query's date range
+    fn page_with_cursors(
+        &self,
+        chat_id: i64,
+        query: &MessageQuery,
+        messages: Vec<Message>,
+    ) -> Result<MessagePage, rusqlite::Error> {
+        let cursor_for = |message: &Message| Cursor {
+            date: message.date_raw,
+            rowid: message.id,
+        };
+        let range = MessageQuery {
+            before: None,
+            after: None,
+            ..query.clone()
+        };
+
+        let older_cursor = match messages.last().map(cursor_for) {
+            Some(oldest) => {
+                let older = MessageQuery {
+                    before: Some(oldest),
+                    ..range.clone()
+                };
+                self.has_messages(chat_id, &older)?.then_some(oldest)
+            }
+            None => None,
+        };
+        let newer_cursor = match messages.first().map(cursor_for) {
+            Some(newest) => {
+                let newer = MessageQuery {
+                    after: Some(newest),
+                    ..range
+                };
+                self.has_messages(chat_id, &newer)?.then_some(newest)
+            }
+            None => None,
+        };
+
+        Ok(MessagePage {
+            messages,
+            older_cursor,
+            newer_cursor,
+        })
+    }
+
+    fn has_messages(&self, chat_id: i64, query: &MessageQuery) -> Result<bool, rusqlite::Error> {
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM message m
+                INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+                WHERE {PAGE_FILTER}
+                  AND NOT {is_reaction}
+            )
+        "#
+        );
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let p = page_params(chat_id, query);
+        stmt.query_row(params![p.0, p.1, p.2, p.3, p.4, p.5, p.6], |row: &Row| {
+            row.get(0)
+        })
+    }
+}
+
+type PageParams = (
+    i64,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+
+fn page_params(chat_id: i64, query: &MessageQuery) -> PageParams {
+    (
+        chat_id,
+        query.start,
+        query.end,
+        query.before.map(|c| c.date),
+        query.before.map(|c| c.rowid),
+        query.after.map(|c| c.date),
+        query.after.map(|c| c.rowid),
+    )
+}
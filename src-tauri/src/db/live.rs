@@ -0,0 +1,54 @@
+use rusqlite::params;
+
+use super::connection::ChatDb;
+use super::models::NewMessage;
+
+impl ChatDb {
+    /// The newest message's ROWID, or 0 for an empty database
+    pub fn max_message_rowid(&self) -> Result<i64, rusqlite::Error> {
+        self.conn
+            .query_row("SELECT COALESCE(MAX(ROWID), 0) FROM message", [], |row| {
+                row.get(0)
+            })
+    }
+
+    /// Messages added after `after_rowid`, oldest first, and the ROWID to
+    /// continue from. Tapbacks move the ROWID on but aren't returned.
+    pub fn get_new_messages(
+        &self,
+        after_rowid: i64,
+        limit: i64,
+    ) -> Result<(Vec<NewMessage>, i64), rusqlite::Error> {
+        let mut stmt = self.conn.prepare_cached(
+            r#"
+            SELECT m.ROWID, cmj.chat_id
+            FROM message m
+            LEFT JOIN chat_message_join cmj ON cmj.message_id = m.ROWID
+            WHERE m.ROWID > ?1
+            GROUP BY m.ROWID
+            ORDER BY m.ROWID
+            LIMIT ?2
+            "#,
+        )?;
+        let rows = stmt
+            .query_map(params![after_rowid, limit], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_rowid = rows.last().map_or(after_rowid, |(id, _)| *id);
+
+        let ids: Vec<i64> = rows.iter().map(|(id, _)| *id).collect();
+        let messages = self
+            .get_messages_by_ids(&ids)?
+            .into_iter()
+            .map(|message| NewMessage {
+                chat_id: rows
+                    .iter()
+                    .find(|(id, _)| *id == message.id)
+                    .and_then(|(_, chat_id)| *chat_id),
+                message,
+            })
+            .collect();
+        Ok((messages, last_rowid))
+    }
+}
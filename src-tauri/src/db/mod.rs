@@ -1,7 +1,31 @@
+mod backup;
 pub mod connection;
+mod edits;
+#[cfg(any(test, feature = "fixture"))]
+pub mod fixture;
+mod history;
+mod live;
 mod models;
 mod parser;
+mod people;
+mod pool;
 mod queries;
+mod reactions;
+mod schema;
+mod search;
+mod snapshot;
+mod source;
+mod store;
+#[cfg(test)]
+mod tests;
+mod typedstream;
 
-pub use connection::ChatDb;
 pub use models::*;
+pub use history::{ContextDirection, ContextOptions, MessageQuery};
+pub use pool::{DbPool, DbTarget};
+pub use queries::ConversationQuery;
+pub use schema::Diagnostics;
+pub use search::parse_query;
+pub use snapshot::Snapshot;
+pub use source::DataSource;
+pub use store::{ChatDbBackend, MessageStore, StoreBackend};
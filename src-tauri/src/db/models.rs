@@ -8,10 +8,121 @@ pub struct Message {
     pub text: Option<String>,
     pub handle_id: i64,
     pub date: DateTime<Utc>,
+    /// `message.date` in nanoseconds, for cursors
+    #[serde(skip)]
+    pub date_raw: i64,
+    /// When it was read: by the recipient for messages I sent, by me for
+    /// the rest
+    pub date_read: Option<DateTime<Utc>>,
+    pub date_delivered: Option<DateTime<Utc>>,
     pub is_from_me: bool,
     pub service: String,
     pub contact_name: Option<String>,
     pub contact_id: Option<String>,
+    pub attachments: Vec<Attachment>,
+    pub reactions: Vec<Reaction>,
+    /// Set when this message is an inline reply
+    pub reply_to: Option<ReplyTo>,
+    pub date_edited: Option<DateTime<Utc>>,
+    pub date_retracted: Option<DateTime<Utc>>,
+    /// The whole message was unsent
+    pub is_unsent: bool,
+    /// Parts of the message that were unsent
+    pub retracted_parts: Vec<i64>,
+    /// Every version of each edited part, oldest first
+    pub edit_history: Vec<MessageVersion>,
+    /// Attribute runs from `attributedBody`, covering the whole text
+    pub runs: Vec<TextRun>,
+}
+
+/// A span of message text sharing the same attributes. Offsets are in
+/// UTF-16 code units, as stored by Messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextRun {
+    pub start: usize,
+    pub length: usize,
+    pub text: String,
+    pub part_index: Option<i64>,
+    pub attributes: Vec<TextAttribute>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum TextAttribute {
+    /// The handle of the mentioned person
+    Mention(String),
+    Link(String),
+    /// The guid of the attachment shown at this position
+    FileTransfer(String),
+    Effect(TextEffect),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextEffect {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    /// An animated text effect, by its Messages id
+    Animation(i64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageVersion {
+    pub part_index: i64,
+    pub text: Option<String>,
+    pub date: Option<DateTime<Utc>>,
+}
+
+/// The message an inline reply answers (`thread_originator_guid`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplyTo {
+    pub guid: String,
+    pub part_index: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub message_id: i64,
+    pub guid: String,
+    /// Path on disk, with `~` expanded (or resolved through the backup manifest)
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    pub uti: Option<String>,
+    pub total_bytes: i64,
+    pub transfer_name: Option<String>,
+    pub is_sticker: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReactionKind {
+    Loved,
+    Liked,
+    Disliked,
+    Laughed,
+    Emphasized,
+    Questioned,
+    Emoji,
+}
+
+/// A tapback on a message, folded in from its own `message` row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub kind: ReactionKind,
+    /// Set for custom emoji tapbacks
+    pub emoji: Option<String>,
+    pub handle_id: i64,
+    pub contact_id: Option<String>,
+    pub contact_name: Option<String>,
+    pub is_from_me: bool,
+    pub date: DateTime<Utc>,
+    /// Which part of the target message was reacted to (`p:N/` prefix)
+    pub part_index: i64,
+    /// The latest tapback from this person was a removal
+    pub is_removed: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +131,19 @@ pub struct Handle {
     pub identifier: String,
     pub service: String,
     pub uncanonicalized_id: Option<String>,
+    pub contact_name: Option<String>,
+}
+
+/// Everyone behind a set of handles, across phone numbers, emails and
+/// services
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Person {
+    /// The person's lowest normalized handle, e.g. `+15551234567`
+    pub id: String,
+    pub name: Option<String>,
+    pub handles: Vec<Handle>,
+    /// 1:1 chats with any of the handles
+    pub chat_ids: Vec<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +163,84 @@ pub struct Conversation {
     pub message_count: i64,
 }
 
+/// A message's searchable text, as fed to the search index
+#[derive(Debug, Clone)]
+pub struct MessageText {
+    pub id: i64,
+    pub chat_id: Option<i64>,
+    /// Current text; empty once unsent
+    pub text: String,
+    /// Earlier versions of an edited message
+    pub edits: Option<String>,
+    /// Latest edit or unsend, in `message.date` units
+    pub changed_at: i64,
+}
+
+/// Where a chat is up to: its newest row (messages and tapbacks) and its
+/// latest edit or unsend. Anything derived from the chat is stale once
+/// this changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChatVersion {
+    pub last_rowid: i64,
+    pub last_changed: i64,
+}
+
+/// A page of a chat's messages, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    /// Pass as `before` for the previous page; `None` at the start of the chat
+    pub older_cursor: Option<Cursor>,
+    /// Pass as `after` for the next page; `None` at the latest message
+    pub newer_cursor: Option<Cursor>,
+}
+
+/// A page of `get_conversations`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationPage {
+    pub conversations: Vec<Conversation>,
+    /// Pass back to get the next page; `None` on the last page
+    pub next_cursor: Option<Cursor>,
+}
+
+/// A position in a list ordered by date, then ROWID. Sent to the frontend
+/// as `"date:rowid"` because nanosecond timestamps don't fit in a
+/// JavaScript number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Cursor {
+    /// Raw `message.date` value
+    pub date: i64,
+    pub rowid: i64,
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        format!("{}:{}", cursor.date, cursor.rowid)
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid cursor: {value:?}");
+        let (date, rowid) = value.split_once(':').ok_or_else(invalid)?;
+        Ok(Cursor {
+            date: date.parse().map_err(|_| invalid())?,
+            rowid: rowid.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A message that arrived while the app was open, pushed to the frontend
+/// as a `new-message` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewMessage {
+    pub chat_id: Option<i64>,
+    pub message: Message,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub message: Message,
@@ -1,125 +1,256 @@
-use plist::Value;
-use regex::Regex;
+use super::models::{TextAttribute, TextEffect, TextRun};
+use super::typedstream::{self, Object, Value};
 
-/// Decode the attributedBody blob (NSAttributedString in binary plist format)
-/// Used for rich text messages in macOS Ventura+
-pub fn decode_attributed_body(data: &[u8]) -> Option<String> {
-    // Method 1: Parse as binary plist (handles UTF-8/emoji correctly)
-    if let Ok(plist) = plist::from_bytes::<Value>(data) {
-        if let Some(text) = extract_from_plist(&plist) {
-            return Some(text);
-        }
-    }
+const PART_KEY: &str = "__kIMMessagePartAttributeName";
+const MENTION_KEY: &str = "__kIMMentionConfirmedMention";
+const LINK_KEY: &str = "__kIMLinkAttributeName";
+const FILE_TRANSFER_KEY: &str = "__kIMFileTransferGUIDAttributeName";
+const ANIMATION_KEY: &str = "__kIMTextEffectAttributeName";
+const BOLD_KEY: &str = "__kIMTextBoldAttributeName";
+const ITALIC_KEY: &str = "__kIMTextItalicAttributeName";
+const UNDERLINE_KEY: &str = "__kIMTextUnderlineAttributeName";
+const STRIKETHROUGH_KEY: &str = "__kIMTextStrikethroughAttributeName";
 
-    // Method 2: Fallback to regex extraction
-    if let Some(text) = extract_text_regex(data) {
-        return Some(text);
-    }
-
-    None
+/// The text of an attributedBody together with its attribute runs
+#[derive(Debug, Clone, Default)]
+pub struct AttributedBody {
+    pub text: String,
+    pub runs: Vec<TextRun>,
 }
 
-fn extract_text_regex(data: &[u8]) -> Option<String> {
-    // The text is often stored after "NSString" marker
-    let text = String::from_utf8_lossy(data);
-
-    // Look for text between specific markers - the actual message content
-    // is typically stored in a specific location in the binary plist
-    let re = Regex::new(r"(?s)NSString.*?\x01(.+?)\x00").ok()?;
-
-    if let Some(caps) = re.captures(&text) {
-        let extracted = caps.get(1)?.as_str();
-        // Filter out plist-related keywords
-        let cleaned = extracted
-            .lines()
-            .filter(|l| {
-                !l.contains("NSDictionary")
-                    && !l.contains("NSAttributed")
-                    && !l.contains("NSValue")
-                    && !l.contains("NSNumber")
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        if !cleaned.is_empty() && cleaned.len() > 1 {
-            return Some(cleaned);
-        }
+/// Decode the attributedBody blob, an `NSAttributedString` written by
+/// `NSArchiver` (see `typedstream`)
+pub fn parse_attributed_body(data: &[u8]) -> Option<AttributedBody> {
+    let groups = typedstream::decode(data).ok()?;
+    let root = groups.first()?.first()?.as_object()?;
+    if !root.class.contains("AttributedString") {
+        return None;
     }
 
-    // Try alternative pattern for newer macOS versions
-    let alt_re = Regex::new(r"\+(.+?)\x00").ok()?;
-    if let Some(caps) = alt_re.captures(&text) {
-        let extracted = caps.get(1)?.as_str().trim();
-        if extracted.len() > 1
-            && !extracted.contains("bplist")
-            && !extracted.contains("NSKeyedArchiver")
-        {
-            return Some(extracted.to_string());
+    let mut body_groups = root.groups.iter();
+    let text = body_groups
+        .next()?
+        .first()?
+        .as_object()?
+        .as_string()?
+        .to_string();
+
+    // Runs are written as (attribute dictionary id, length in UTF-16 code
+    // units). The first run to use an id is followed by its dictionary.
+    let mut spans: Vec<(i64, usize)> = Vec::new();
+    let mut dictionaries: Vec<(i64, Vec<TextAttribute>, Option<i64>)> = Vec::new();
+    for group in body_groups {
+        match group.as_slice() {
+            [id, length] => {
+                let (Some(id), Some(length)) = (id.as_int(), length.as_int()) else {
+                    continue;
+                };
+                spans.push((id, usize::try_from(length).unwrap_or(0)));
+            }
+            [Value::Object(dictionary)] => {
+                if let Some(&(id, _)) = spans.last() {
+                    let (attributes, part_index) = read_attributes(dictionary);
+                    dictionaries.push((id, attributes, part_index));
+                }
+            }
+            _ => {}
         }
     }
 
-    None
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let mut start = 0;
+    let runs = spans
+        .into_iter()
+        .map(|(id, length)| {
+            let end = (start + length).min(utf16.len());
+            let (attributes, part_index) = dictionaries
+                .iter()
+                .find(|(dict_id, ..)| *dict_id == id)
+                .map(|(_, attributes, part)| (attributes.clone(), *part))
+                .unwrap_or_default();
+            let run = TextRun {
+                start,
+                length,
+                text: String::from_utf16_lossy(&utf16[start.min(end)..end]),
+                part_index,
+                attributes,
+            };
+            start = end;
+            run
+        })
+        .collect();
+
+    Some(AttributedBody { text, runs })
 }
 
-fn extract_from_plist(value: &Value) -> Option<String> {
-    match value {
-        Value::String(s) => {
-            // Filter out metadata strings
-            if !s.contains("NSAttributed")
-                && !s.contains("NSDictionary")
-                && !s.contains("NSKeyedArchiver")
-                && s.len() > 1
-            {
-                Some(s.clone())
-            } else {
-                None
-            }
+/// Decode just the text of an attributedBody
+pub fn decode_attributed_body(data: &[u8]) -> Option<String> {
+    parse_attributed_body(data)
+        .map(|body| body.text)
+        .or_else(|| fallback_text(data))
+}
+
+/// Last resort for streams the decoder can't follow: the message text is
+/// the first `+` string written after the `NSString` class
+fn fallback_text(data: &[u8]) -> Option<String> {
+    let marker = data.windows(8).position(|w| w == b"NSString")? + 8;
+    let header = data[marker..]
+        .windows(3)
+        .take(8)
+        .position(|w| w == [0x84, 0x01, b'+'])?;
+    let mut pos = marker + header + 3;
+
+    let len = match *data.get(pos)? {
+        0x81 => {
+            let bytes = data.get(pos + 1..pos + 3)?;
+            pos += 3;
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize
         }
-        Value::Dictionary(dict) => {
-            // Look for NSString key or $objects array
-            if let Some(Value::String(s)) = dict.get("NSString") {
-                return Some(s.clone());
-            }
-            if let Some(Value::Array(objects)) = dict.get("$objects") {
-                // In NSKeyedArchiver format, strings are in $objects array
-                for obj in objects {
-                    if let Value::String(s) = obj {
-                        if s.len() > 1
-                            && !s.starts_with("NS")
-                            && !s.starts_with("$")
-                            && !s.contains("bplist")
-                        {
-                            return Some(s.clone());
-                        }
-                    }
-                }
-            }
-            // Recursively search
-            for (_, v) in dict.iter() {
-                if let Some(text) = extract_from_plist(v) {
-                    return Some(text);
-                }
-            }
-            None
+        0x82 => {
+            let bytes = data.get(pos + 1..pos + 5)?;
+            pos += 5;
+            u32::from_le_bytes(bytes.try_into().ok()?) as usize
         }
-        Value::Array(arr) => {
-            for item in arr {
-                if let Some(text) = extract_from_plist(item) {
-                    return Some(text);
-                }
-            }
-            None
+        byte => {
+            pos += 1;
+            byte as usize
         }
-        _ => None,
+    };
+    let text = String::from_utf8_lossy(data.get(pos..pos + len)?).into_owned();
+    Some(text).filter(|t| !t.is_empty())
+}
+
+fn read_attributes(dictionary: &Object) -> (Vec<TextAttribute>, Option<i64>) {
+    let mut attributes = Vec::new();
+    let mut part_index = None;
+
+    for (key, value) in dictionary.dictionary_entries() {
+        let Some(key) = key.as_object().and_then(Object::as_string) else {
+            continue;
+        };
+        let value = value.as_object();
+        let string = || value.and_then(Object::find_string).map(str::to_string);
+        let flag = || value.and_then(Object::as_number).unwrap_or(0) != 0;
+
+        let attribute = match key {
+            PART_KEY => {
+                part_index = value.and_then(Object::as_number);
+                None
+            }
+            MENTION_KEY => string().map(TextAttribute::Mention),
+            LINK_KEY => string().map(TextAttribute::Link),
+            FILE_TRANSFER_KEY => string().map(TextAttribute::FileTransfer),
+            ANIMATION_KEY => value
+                .and_then(Object::as_number)
+                .map(|id| TextAttribute::Effect(TextEffect::Animation(id))),
+            BOLD_KEY if flag() => Some(TextAttribute::Effect(TextEffect::Bold)),
+            ITALIC_KEY if flag() => Some(TextAttribute::Effect(TextEffect::Italic)),
+            UNDERLINE_KEY if flag() => Some(TextAttribute::Effect(TextEffect::Underline)),
+            STRIKETHROUGH_KEY if flag() => Some(TextAttribute::Effect(TextEffect::Strikethrough)),
+            _ => None,
+        };
+        attributes.extend(attribute);
     }
+
+    (attributes, part_index)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_bytes!(concat!(
+                "../../tests/fixtures/attributed_body/",
+                $name,
+                ".bin"
+            ))
+        };
+    }
+
+    fn parse(data: &[u8]) -> AttributedBody {
+        parse_attributed_body(data).expect("fixture should decode")
+    }
+
     #[test]
-    fn test_decode_empty() {
+    fn test_decode_rejects_other_formats() {
         assert_eq!(decode_attributed_body(&[]), None);
+        assert!(parse_attributed_body(b"bplist00").is_none());
+    }
+
+    #[test]
+    fn test_plain_and_long_text() {
+        let body = parse(fixture!("plain"));
+        assert_eq!(body.text, "Hello");
+        assert_eq!(body.runs.len(), 1);
+        assert_eq!(body.runs[0].part_index, Some(0));
+
+        // Longer than 127 bytes, so the length needs a two-byte integer
+        let body = parse(fixture!("long"));
+        assert!(body.text.len() > 127);
+        assert!(body.text.ends_with("length limit "));
+    }
+
+    #[test]
+    fn test_multibyte_text_survives() {
+        let body = parse(fixture!("emoji"));
+        assert_eq!(body.text, "caf\u{e9} \u{1F600} \u{65E5}\u{672C}\0tail");
+        assert_eq!(body.runs[0].length, body.text.encode_utf16().count());
+    }
+
+    #[test]
+    fn test_mention_run() {
+        let body = parse(fixture!("mention"));
+        assert_eq!(body.text, "Hey Sam look");
+        let texts: Vec<&str> = body.runs.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, ["Hey ", "Sam", " look"]);
+        assert_eq!(
+            body.runs[1].attributes,
+            [TextAttribute::Mention("+15551234567".to_string())]
+        );
+        // The third run reuses the first run's attributes by id
+        assert_eq!(body.runs[2].part_index, Some(0));
+        assert!(body.runs[2].attributes.is_empty());
+    }
+
+    #[test]
+    fn test_link_file_transfer_and_effects() {
+        let body = parse(fixture!("link"));
+        assert_eq!(
+            body.runs[1].attributes,
+            [TextAttribute::Link("https://example.com".to_string())]
+        );
+
+        let body = parse(fixture!("file"));
+        assert_eq!(
+            body.runs[0].attributes,
+            [TextAttribute::FileTransfer(
+                "at_0_1A2B3C4D-5E6F".to_string()
+            )]
+        );
+        assert_eq!(body.runs[1].part_index, Some(1));
+        assert_eq!(body.runs[1].text, "nice");
+
+        let body = parse(fixture!("effects"));
+        assert_eq!(
+            body.runs[0].attributes,
+            [TextAttribute::Effect(TextEffect::Animation(5))]
+        );
+        assert_eq!(
+            body.runs[1].attributes,
+            [TextAttribute::Effect(TextEffect::Bold)]
+        );
+    }
+
+    #[test]
+    fn test_fallback_reads_text_from_damaged_stream() {
+        let mut data = fixture!("emoji").to_vec();
+        data.truncate(data.len() - 20);
+        assert!(parse_attributed_body(&data).is_none());
+        assert_eq!(
+            decode_attributed_body(&data).as_deref(),
+            Some("caf\u{e9} \u{1F600} \u{65E5}\u{672C}\0tail")
+        );
     }
 }
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use rusqlite::params;
+
+use super::connection::ChatDb;
+use super::models::{Handle, Message, Person};
+use crate::contacts::{normalize_handle, ContactBook};
+
+impl ChatDb {
+    /// Everyone the user has a handle for, with handles that belong to the
+    /// same person merged: SMS and iMessage rows for one number, differently
+    /// formatted numbers, and phone numbers and emails on one contact card.
+    pub fn get_people(&self) -> Result<Vec<Person>, rusqlite::Error> {
+        let handles = self.get_all_handles()?;
+        let mut direct_chats = self.get_direct_chats(None)?;
+
+        let mut people: Vec<Person> = group_handles(&handles, &self.contacts)
+            .into_iter()
+            .map(|members| {
+                let handles = members.iter().map(|&i| handles[i].clone()).collect();
+                build_person(handles, &mut direct_chats)
+            })
+            .collect();
+
+        people.sort_by(|a, b| {
+            let key = |p: &Person| p.name.clone().unwrap_or_else(|| p.id.clone());
+            key(a).to_lowercase().cmp(&key(b).to_lowercase())
+        });
+        Ok(people)
+    }
+
+    /// One person, looking up chats for their handles only
+    pub fn get_person(&self, id: &str) -> Result<Option<Person>, rusqlite::Error> {
+        let handles = self.get_all_handles()?;
+        let members: Vec<Handle> = person_handles(&handles, &self.contacts, id)
+            .into_iter()
+            .map(|i| handles[i].clone())
+            .collect();
+        if members.is_empty() || person_id(&members) != id {
+            return Ok(None);
+        }
+
+        let handle_ids: Vec<i64> = members.iter().map(|h| h.id).collect();
+        let mut direct_chats = self.get_direct_chats(Some(&handle_ids))?;
+        Ok(Some(build_person(members, &mut direct_chats)))
+    }
+
+    /// Messages from all of a person's 1:1 chats, newest first
+    pub fn get_person_timeline(
+        &self,
+        person: &Person,
+        limit: i64,
+    ) -> Result<Vec<Message>, rusqlite::Error> {
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM message m
+            INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            WHERE cmj.chat_id IN (SELECT value FROM json_each(?1))
+              AND NOT {is_reaction}
+            ORDER BY mac_timestamp_nanos(m.date) DESC
+            LIMIT ?2
+        "#
+        );
+
+        let chat_ids = serde_json::to_string(&person.chat_ids).unwrap_or_else(|_| "[]".into());
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let mut results = stmt
+            .query_map(params![chat_ids, limit], Self::static_row_to_message)?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.hydrate_messages(&mut results)?;
+        Ok(results)
+    }
+
+    pub(super) fn get_all_handles(&self) -> Result<Vec<Handle>, rusqlite::Error> {
+        let sql = format!(
+            "SELECT h.ROWID, h.id, h.service, {} FROM handle h ORDER BY h.ROWID",
+            self.schema.column("h", "handle", "uncanonicalized_id")
+        );
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let handles = stmt
+            .query_map([], |row| {
+                let identifier: String = row.get(1)?;
+                Ok(Handle {
+                    id: row.get(0)?,
+                    contact_name: self.contacts.resolve(&identifier).map(str::to_string),
+                    identifier,
+                    service: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    uncanonicalized_id: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(handles)
+    }
+
+    /// Handle ROWID -> the 1:1 chats with that handle, for every handle or
+    /// just `handle_ids`
+    fn get_direct_chats(
+        &self,
+        handle_ids: Option<&[i64]>,
+    ) -> Result<HashMap<i64, Vec<i64>>, rusqlite::Error> {
+        let sql = r#"
+            SELECT chj.chat_id, MIN(chj.handle_id)
+            FROM chat_handle_join chj
+            INNER JOIN chat c ON c.ROWID = chj.chat_id
+            WHERE COALESCE(c.style, 45) != 43
+            GROUP BY chj.chat_id
+            HAVING COUNT(*) = 1
+               AND (?1 IS NULL OR MIN(chj.handle_id) IN (SELECT value FROM json_each(?1)))
+        "#;
+        let handle_ids = handle_ids.and_then(|ids| serde_json::to_string(ids).ok());
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let mut chats: HashMap<i64, Vec<i64>> = HashMap::new();
+        let rows = stmt.query_map(params![handle_ids], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        for row in rows {
+            let (chat_id, handle_id) = row?;
+            chats.entry(handle_id).or_default().push(chat_id);
+        }
+        Ok(chats)
+    }
+}
+
+fn build_person(handles: Vec<Handle>, direct_chats: &mut HashMap<i64, Vec<i64>>) -> Person {
+    let mut chat_ids: Vec<i64> = handles
+        .iter()
+        .flat_map(|h| direct_chats.remove(&h.id).unwrap_or_default())
+        .collect();
+    chat_ids.sort_unstable();
+    chat_ids.dedup();
+
+    Person {
+        id: person_id(&handles),
+        name: handles.iter().find_map(|h| h.contact_name.clone()),
+        handles,
+        chat_ids,
+    }
+}
+
+/// What ties a handle to the other handles of the same person: its id and
+/// uncanonicalized id normalized to an E.164 number or email, and the
+/// contact card either is on
+fn handle_keys(handle: &Handle, contacts: &ContactBook) -> Vec<String> {
+    let raw_ids =
+        std::iter::once(handle.identifier.as_str()).chain(handle.uncanonicalized_id.as_deref());
+    let mut keys: Vec<String> = raw_ids.clone().filter_map(normalize_handle).collect();
+    keys.extend(
+        raw_ids
+            .filter_map(|id| contacts.contact_key(id))
+            .map(|contact| format!("contact:{contact}")),
+    );
+    keys
+}
+
+/// Group handles (by index) that share a key from `handle_keys`
+fn group_handles(handles: &[Handle], contacts: &ContactBook) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..handles.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+
+    let mut first_with_key: HashMap<String, usize> = HashMap::new();
+    for (i, handle) in handles.iter().enumerate() {
+        for key in handle_keys(handle, contacts) {
+            match first_with_key.get(&key) {
+                Some(&other) => {
+                    let (a, b) = (find(&mut parent, i), find(&mut parent, other));
+                    parent[a] = b;
+                }
+                None => {
+                    first_with_key.insert(key, i);
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..handles.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort();
+    groups
+}
+
+/// The handles (by index) in the group `group_handles` puts the handle
+/// `id` names in. Callers check the group's `person_id` is still `id`.
+fn person_handles(handles: &[Handle], contacts: &ContactBook, id: &str) -> Vec<usize> {
+    let Some(named) = handles.iter().position(|h| {
+        format!("handle:{}", h.id) == id || normalize_handle(&h.identifier).as_deref() == Some(id)
+    }) else {
+        return Vec::new();
+    };
+    group_handles(handles, contacts)
+        .into_iter()
+        .find(|group| group.contains(&named))
+        .unwrap_or_default()
+}
+
+/// A stable id for a person: their lowest normalized handle
+fn person_id(handles: &[Handle]) -> String {
+    handles
+        .iter()
+        .filter_map(|h| normalize_handle(&h.identifier))
+        .min()
+        .unwrap_or_else(|| format!("handle:{}", handles[0].id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(id: i64, identifier: &str, service: &str) -> Handle {
+        Handle {
+            id,
+            identifier: identifier.to_string(),
+            service: service.to_string(),
+            uncanonicalized_id: None,
+            contact_name: None,
+        }
+    }
+
+    #[test]
+    fn test_group_handles() {
+        let handles = vec![
+            handle(1, "+15551234567", "iMessage"),
+            handle(2, "+15551234567", "SMS"),
+            handle(3, "sam@example.com", "iMessage"),
+            handle(4, "+15550001111", "iMessage"),
+            Handle {
+                uncanonicalized_id: Some("(555) 000-1111".to_string()),
+                ..handle(5, "5550001111", "SMS")
+            },
+        ];
+        let mut contacts = ContactBook::default();
+        contacts.insert("card:sam", "555-123-4567", "Sam Lee");
+        contacts.insert("card:sam", "Sam@Example.com", "Sam Lee");
+
+        assert_eq!(
+            group_handles(&handles, &contacts),
+            vec![vec![0, 1, 2], vec![3, 4]]
+        );
+        assert_eq!(person_id(&handles[3..]), "+15550001111");
+        assert_eq!(
+            person_handles(&handles, &contacts, "+15551234567"),
+            [0, 1, 2]
+        );
+        assert!(person_handles(&handles, &contacts, "+15559999999").is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::connection::{ChatDb, DbError};
+use super::snapshot::Snapshot;
+use super::source::DataSource;
+
+/// Idle connections kept around for reuse. Searches run one at a time as
+/// you type, so a handful is plenty.
+const MAX_IDLE: usize = 4;
+
+/// What a pooled connection reads from
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbTarget {
+    Source(DataSource),
+    Snapshot(Snapshot),
+}
+
+impl DbTarget {
+    fn path(&self) -> PathBuf {
+        match self {
+            DbTarget::Source(source) => source.db_path(),
+            DbTarget::Snapshot(snapshot) => snapshot.path.clone(),
+        }
+    }
+
+    fn open(&self) -> Result<ChatDb, DbError> {
+        match self {
+            DbTarget::Source(source) => source.open(),
+            DbTarget::Snapshot(snapshot) => snapshot.open(),
+        }
+    }
+}
+
+/// Identifies the file behind a target, so connections to a file that has
+/// since been replaced (restored backup, refreshed snapshot) are not reused
+#[derive(Debug, Clone, PartialEq)]
+struct PoolKey {
+    target: DbTarget,
+    file_id: (u64, u64),
+}
+
+impl PoolKey {
+    fn for_target(target: &DbTarget) -> Result<Self, DbError> {
+        let path = target.path();
+        let metadata = std::fs::metadata(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => DbError::NotFound(path),
+            _ => DbError::PermissionDenied,
+        })?;
+
+        Ok(Self {
+            target: target.clone(),
+            file_id: file_id(&metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> (u64, u64) {
+    // No cheap file identity available; only target changes reset the pool
+    (0, 0)
+}
+
+#[derive(Default)]
+struct PoolState {
+    key: Option<PoolKey>,
+    idle: Vec<ChatDb>,
+}
+
+/// Long-lived read-only connections shared by all commands. Each connection
+/// keeps its own prepared-statement cache, so repeated queries skip both the
+/// open and the prepare.
+#[derive(Clone, Default)]
+pub struct DbPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl DbPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check out a connection to `target`, reusing an idle one when it still
+    /// points at the same file. Failed opens are never cached, so access
+    /// granted after a permission error is picked up on the next call.
+    pub fn get(&self, target: &DbTarget) -> Result<PooledDb, DbError> {
+        let key = PoolKey::for_target(target)?;
+
+        {
+            let mut state = self.lock();
+            if state.key.as_ref() != Some(&key) {
+                state.idle.clear();
+                state.key = Some(key.clone());
+            }
+            if let Some(db) = state.idle.pop() {
+                return Ok(PooledDb {
+                    db: Some(db),
+                    key,
+                    pool: self.clone(),
+                });
+            }
+        }
+
+        let db = target.open()?;
+        Ok(PooledDb {
+            db: Some(db),
+            key,
+            pool: self.clone(),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        // A panic while holding the lock can't leave the idle list in a bad
+        // state, so recover from poisoning instead of failing every query
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn release(&self, key: &PoolKey, db: ChatDb) {
+        let mut state = self.lock();
+        if state.key.as_ref() == Some(key) && state.idle.len() < MAX_IDLE {
+            state.idle.push(db);
+        }
+    }
+}
+
+/// A checked-out connection, returned to the pool on drop
+pub struct PooledDb {
+    db: Option<ChatDb>,
+    key: PoolKey,
+    pool: DbPool,
+}
+
+impl Deref for PooledDb {
+    type Target = ChatDb;
+
+    fn deref(&self) -> &ChatDb {
+        self.db
+            .as_ref()
+            .expect("connection already returned to pool")
+    }
+}
+
+impl DerefMut for PooledDb {
+    fn deref_mut(&mut self) -> &mut ChatDb {
+        self.db
+            .as_mut()
+            .expect("connection already returned to pool")
+    }
+}
+
+impl Drop for PooledDb {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.pool.release(&self.key, db);
+        }
+    }
+}
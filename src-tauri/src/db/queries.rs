@@ -1,75 +1,208 @@
 use super::connection::ChatDb;
 use super::models::*;
-use super::parser::decode_attributed_body;
-use crate::utils::mac_timestamp_to_datetime;
+use super::edits::parse_summary_info;
+use super::parser::{decode_attributed_body, parse_attributed_body, AttributedBody};
+use super::reactions::{
+    candidate_associated_guids, fold_reactions, parse_associated_guid, reaction_from_parts,
+    ReactionEvent,
+};
+use crate::utils::{mac_timestamp_to_datetime, optional_mac_timestamp_to_datetime};
 use rusqlite::{params, Row};
+use std::collections::HashMap;
 
-impl ChatDb {
-    /// Get all conversations (chats) with their last message
-    pub fn get_conversations(&self) -> Result<Vec<Conversation>, rusqlite::Error> {
-        let sql = r#"
-            SELECT
-                c.ROWID, c.guid, c.display_name,
-                CASE WHEN c.style = 43 THEN 1 ELSE 0 END as is_group,
-                (SELECT COUNT(*) FROM chat_handle_join WHERE chat_id = c.ROWID) as participant_count
-            FROM chat c
-            ORDER BY c.ROWID DESC
-        "#;
-
-        let mut stmt = self.conn.prepare(sql)?;
-        let chats = stmt
-            .query_map([], |row| {
-                Ok(Chat {
-                    id: row.get(0)?,
-                    guid: row.get(1)?,
-                    display_name: row.get(2)?,
-                    is_group: row.get::<_, i32>(3)? == 1,
-                    participant_count: row.get(4)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+/// Options for `get_conversations`
+#[derive(Debug, Clone)]
+pub struct ConversationQuery {
+    /// Matches the chat name or any participant's handle or contact name
+    pub filter: Option<String>,
+    /// Continue after the last conversation of the previous page
+    pub cursor: Option<Cursor>,
+    pub limit: i64,
+    /// Also list chats with no messages
+    pub include_empty: bool,
+}
 
-        // Get participants and last message for each chat
-        let mut conversations = Vec::new();
-        for chat in chats {
-            let participants = self.get_chat_participants(chat.id)?;
-            let last_message = self.get_last_message_for_chat(chat.id)?;
-            let message_count = self.get_message_count_for_chat(chat.id)?;
-
-            conversations.push(Conversation {
-                chat,
-                participants,
-                last_message,
-                message_count,
-            });
+impl Default for ConversationQuery {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            cursor: None,
+            limit: 100,
+            include_empty: true,
         }
-
-        Ok(conversations)
     }
+}
 
-    /// Search messages by text content
-    pub fn search_messages(&self, query: &str, limit: i64) -> Result<Vec<Message>, rusqlite::Error> {
-        let sql = r#"
-            SELECT
-                m.ROWID, m.guid, m.text, m.attributedBody,
-                m.handle_id, m.date, m.is_from_me, m.service,
-                h.id as handle_identifier
-            FROM message m
-            LEFT JOIN handle h ON m.handle_id = h.ROWID
-            WHERE m.text LIKE ?1
-            ORDER BY m.date DESC
-            LIMIT ?2
-        "#;
+impl ChatDb {
+    /// One page of conversations, most recently active first
+    pub fn get_conversations(
+        &self,
+        query: &ConversationQuery,
+    ) -> Result<ConversationPage, rusqlite::Error> {
+        // Aggregate per chat in one pass, then look up the last message only
+        // for the chats on this page
+        let is_reaction = &self.schema.is_reaction;
+        let uncanonicalized_id = self.schema.column("h", "handle", "uncanonicalized_id");
+        let sql = format!(
+            r#"
+            WITH stats AS (
+                SELECT cmj.chat_id,
+                    MAX(mac_timestamp_nanos(m.date)) AS last_date,
+                    COUNT(*) FILTER (WHERE NOT {is_reaction}) AS message_count
+                FROM chat_message_join cmj
+                INNER JOIN message m ON m.ROWID = cmj.message_id
+                GROUP BY cmj.chat_id
+            ),
+            participants AS (
+                SELECT chj.chat_id,
+                    COUNT(*) AS participant_count,
+                    json_group_array(json_object(
+                        'id', h.ROWID,
+                        'identifier', h.id,
+                        'service', COALESCE(h.service, ''),
+                        'uncanonicalized_id', {uncanonicalized_id}
+                    )) AS handles
+                FROM chat_handle_join chj
+                INNER JOIN handle h ON h.ROWID = chj.handle_id
+                GROUP BY chj.chat_id
+            ),
+            page AS (
+                SELECT
+                    c.ROWID AS chat_id, c.guid, c.display_name,
+                    CASE WHEN c.style = 43 THEN 1 ELSE 0 END AS is_group,
+                    COALESCE(p.participant_count, 0) AS participant_count,
+                    COALESCE(p.handles, '[]') AS handles,
+                    COALESCE(s.message_count, 0) AS message_count,
+                    COALESCE(s.last_date, 0) AS sort_date
+                FROM chat c
+                LEFT JOIN stats s ON s.chat_id = c.ROWID
+                LEFT JOIN participants p ON p.chat_id = c.ROWID
+                WHERE (?1 OR s.message_count > 0)
+                  AND (?2 IS NULL
+                       OR c.display_name LIKE ?2
+                       OR c.chat_identifier LIKE ?2
+                       OR EXISTS (
+                           SELECT 1 FROM chat_handle_join chj
+                           WHERE chj.chat_id = c.ROWID
+                             AND chj.handle_id IN (SELECT value FROM json_each(?3))))
+                  AND (?4 IS NULL
+                       OR COALESCE(s.last_date, 0) < ?4
+                       OR (COALESCE(s.last_date, 0) = ?4 AND c.ROWID < ?5))
+                ORDER BY sort_date DESC, c.ROWID DESC
+                LIMIT ?6
+            )
+            SELECT page.*,
+                (SELECT m.ROWID
+                 FROM chat_message_join cmj
+                 INNER JOIN message m ON m.ROWID = cmj.message_id
+                 WHERE cmj.chat_id = page.chat_id
+                   AND NOT {is_reaction}
+                 ORDER BY mac_timestamp_nanos(m.date) DESC
+                 LIMIT 1) AS last_message_id
+            FROM page
+            ORDER BY sort_date DESC, chat_id DESC
+        "#
+        );
 
-        let search_pattern = format!("%{}%", query);
-        let mut stmt = self.conn.prepare(sql)?;
+        let filter = query
+            .filter
+            .as_deref()
+            .map(str::trim)
+            .filter(|f| !f.is_empty());
+        let pattern = filter.map(|f| format!("%{f}%"));
+        let matching_handles = match filter {
+            Some(filter) => self.handles_matching(filter)?,
+            None => Vec::new(),
+        };
+        let handles_json =
+            serde_json::to_string(&matching_handles).unwrap_or_else(|_| "[]".to_string());
 
-        let results = stmt
-            .query_map(params![&search_pattern, limit], |row| {
-                Self::static_row_to_message(row)
-            })?
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt
+            .query_map(
+                params![
+                    query.include_empty,
+                    pattern,
+                    handles_json,
+                    query.cursor.map(|c| c.date),
+                    query.cursor.map(|c| c.rowid),
+                    query.limit,
+                ],
+                |row| {
+                    let chat = Chat {
+                        id: row.get(0)?,
+                        guid: row.get(1)?,
+                        display_name: row.get(2)?,
+                        is_group: row.get::<_, i32>(3)? == 1,
+                        participant_count: row.get(4)?,
+                    };
+                    let handles: String = row.get(5)?;
+                    let participants: Vec<Handle> =
+                        serde_json::from_str(&handles).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                5,
+                                rusqlite::types::Type::Text,
+                                Box::new(e),
+                            )
+                        })?;
+                    let message_count: i64 = row.get(6)?;
+                    let sort_date: i64 = row.get(7)?;
+                    let last_message_id: Option<i64> = row.get(8)?;
+                    Ok((chat, participants, message_count, sort_date, last_message_id))
+                },
+            )?
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(results)
+
+        let last_message_ids: Vec<i64> = rows.iter().filter_map(|row| row.4).collect();
+        let mut last_messages: HashMap<i64, Message> = self
+            .get_messages_by_ids(&last_message_ids)?
+            .into_iter()
+            .map(|m| (m.id, m))
+            .collect();
+
+        let next_cursor = match rows.last() {
+            Some((chat, _, _, sort_date, _)) if rows.len() as i64 == query.limit => Some(Cursor {
+                date: *sort_date,
+                rowid: chat.id,
+            }),
+            _ => None,
+        };
+
+        let conversations = rows
+            .into_iter()
+            .map(|(chat, mut participants, message_count, _, last_message_id)| {
+                for handle in &mut participants {
+                    handle.contact_name = self.contact_name(Some(&handle.identifier));
+                }
+                Conversation {
+                    chat,
+                    participants,
+                    last_message: last_message_id.and_then(|id| last_messages.remove(&id)),
+                    message_count,
+                }
+            })
+            .collect();
+
+        Ok(ConversationPage {
+            conversations,
+            next_cursor,
+        })
+    }
+
+    /// ROWIDs of handles whose id or contact name contains `filter`
+    pub(super) fn handles_matching(&self, filter: &str) -> Result<Vec<i64>, rusqlite::Error> {
+        let filter = filter.to_lowercase();
+        let matches = |value: Option<&str>| value.is_some_and(|v| v.to_lowercase().contains(&filter));
+        Ok(self
+            .get_all_handles()?
+            .into_iter()
+            .filter(|h| {
+                matches(Some(&h.identifier))
+                    || matches(h.uncanonicalized_id.as_deref())
+                    || matches(h.contact_name.as_deref())
+            })
+            .map(|h| h.id)
+            .collect())
     }
 
     /// Get messages for a specific chat within a date range
@@ -78,25 +211,28 @@ impl ChatDb {
         chat_id: i64,
         limit: i64,
     ) -> Result<Vec<Message>, rusqlite::Error> {
-        let sql = r#"
-            SELECT
-                m.ROWID, m.guid, m.text, m.attributedBody,
-                m.handle_id, m.date, m.is_from_me, m.service,
-                h.id as handle_identifier
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
             FROM message m
             INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
             LEFT JOIN handle h ON m.handle_id = h.ROWID
             WHERE cmj.chat_id = ?1
-            ORDER BY m.date DESC
+              AND NOT {is_reaction}
+            ORDER BY mac_timestamp_nanos(m.date) DESC
             LIMIT ?2
-        "#;
+        "#
+        );
 
-        let mut stmt = self.conn.prepare(sql)?;
-        let results = stmt
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let mut results = stmt
             .query_map(params![chat_id, limit], |row| {
                 Self::static_row_to_message(row)
             })?
             .collect::<Result<Vec<_>, _>>()?;
+        self.hydrate_messages(&mut results)?;
         Ok(results)
     }
 
@@ -120,123 +256,413 @@ impl ChatDb {
 
         let mut stmt = self.conn.prepare(sql).map_err(|e| e.to_string())?;
 
-        let results = stmt
-            .query_map([], |row| Self::static_row_to_message(row))
+        // Only trust the generated query for which rows match (ROWID comes
+        // first), then load them the same way as every other message query
+        let ids = stmt
+            .query_map([], |row| row.get::<_, i64>(0))
             .map_err(|e| e.to_string())?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| e.to_string())?;
+
+        self.get_messages_by_ids(&ids).map_err(|e| e.to_string())
+    }
+
+    /// Load specific messages, keeping the order of `ids`. Tapbacks are
+    /// skipped since they are folded into the messages they react to.
+    pub fn get_messages_by_ids(&self, ids: &[i64]) -> Result<Vec<Message>, rusqlite::Error> {
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM message m
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            WHERE m.ROWID IN (SELECT value FROM json_each(?1))
+              AND NOT {is_reaction}
+        "#
+        );
+
+        let ids_json = serde_json::to_string(ids).unwrap_or_else(|_| "[]".to_string());
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let mut by_id = stmt
+            .query_map(params![ids_json], Self::static_row_to_message)?
+            .map(|m| m.map(|m| (m.id, m)))
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut results: Vec<Message> = ids.iter().filter_map(|id| by_id.remove(id)).collect();
+        self.hydrate_messages(&mut results)?;
         Ok(results)
     }
 
-    /// Get context messages before a given message
-    pub fn get_context_before(
+    /// Messages after `after_rowid`, oldest first, with their text fully
+    /// decoded for the search index
+    pub fn get_message_texts(
         &self,
-        message_id: i64,
-        count: i64,
-    ) -> Result<Vec<Message>, rusqlite::Error> {
-        let sql = r#"
-            SELECT
-                m.ROWID, m.guid, m.text, m.attributedBody,
-                m.handle_id, m.date, m.is_from_me, m.service,
-                h.id as handle_identifier
+        after_rowid: i64,
+        limit: i64,
+    ) -> Result<Vec<MessageText>, rusqlite::Error> {
+        self.query_message_texts(
+            "m.ROWID > ?1 ORDER BY m.ROWID LIMIT ?2",
+            params![after_rowid, limit],
+        )
+    }
+
+    /// Messages up to `max_rowid` edited or unsent after `since`
+    pub fn get_changed_message_texts(
+        &self,
+        max_rowid: i64,
+        since: i64,
+    ) -> Result<Vec<MessageText>, rusqlite::Error> {
+        let changed_at = self.changed_at_sql();
+        self.query_message_texts(
+            &format!("m.ROWID <= ?1 AND {changed_at} > ?2"),
+            params![max_rowid, since],
+        )
+    }
+
+    /// See `ChatVersion`
+    pub fn get_chat_version(&self, chat_id: i64) -> Result<ChatVersion, rusqlite::Error> {
+        let changed_at = self.changed_at_sql();
+        let sql = format!(
+            r#"
+            SELECT COALESCE(MAX(m.ROWID), 0), COALESCE(MAX({changed_at}), 0)
+            FROM chat_message_join cmj
+            JOIN message m ON m.ROWID = cmj.message_id
+            WHERE cmj.chat_id = ?1
+        "#
+        );
+        self.conn.query_row(&sql, params![chat_id], |row| {
+            Ok(ChatVersion {
+                last_rowid: row.get(0)?,
+                last_changed: row.get(1)?,
+            })
+        })
+    }
+
+    fn query_message_texts(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<MessageText>, rusqlite::Error> {
+        let is_reaction = &self.schema.is_reaction;
+        let attributed_body = self.schema.column("m", "message", "attributedBody");
+        let date_edited = self.schema.column("m", "message", "date_edited");
+        let summary_info = self.schema.column("m", "message", "message_summary_info");
+        let changed_at = self.changed_at_sql();
+        let sql = format!(
+            r#"
+            SELECT m.ROWID, m.text, {attributed_body},
+                   (SELECT chat_id FROM chat_message_join WHERE message_id = m.ROWID LIMIT 1),
+                   CASE WHEN {date_edited} > 0
+                        THEN message_edit_text({summary_info}) END,
+                   {changed_at}
             FROM message m
-            LEFT JOIN handle h ON m.handle_id = h.ROWID
-            WHERE m.ROWID < ?1
-            ORDER BY m.ROWID DESC
-            LIMIT ?2
-        "#;
+            WHERE NOT {is_reaction}
+              AND {filter}
+        "#
+        );
 
-        let mut stmt = self.conn.prepare(sql)?;
-        let mut messages: Vec<Message> = stmt
-            .query_map(params![message_id, count], |row| {
-                Self::static_row_to_message(row)
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt
+            .query_map(params, |row| {
+                let text: Option<String> = row.get(1)?;
+                let attributed_body: Option<Vec<u8>> = row.get(2)?;
+                // Unsent parts are gone from the row, so whatever is left
+                // is what's still visible
+                let text = text
+                    .filter(|t| !t.is_empty())
+                    .or_else(|| attributed_body.as_deref().and_then(decode_attributed_body))
+                    .unwrap_or_default();
+                Ok(MessageText {
+                    id: row.get(0)?,
+                    chat_id: row.get(3)?,
+                    text,
+                    edits: row.get(4)?,
+                    changed_at: row.get(5)?,
+                })
             })?
             .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
 
-        messages.reverse(); // Return in chronological order
-        Ok(messages)
+    /// When a message was last edited or unsent, or 0
+    fn changed_at_sql(&self) -> String {
+        format!(
+            "MAX(COALESCE({}, 0), COALESCE({}, 0))",
+            self.schema.column("m", "message", "date_edited"),
+            self.schema.column("m", "message", "date_retracted"),
+        )
     }
 
-    /// Get context messages after a given message
-    pub fn get_context_after(
-        &self,
-        message_id: i64,
-        count: i64,
-    ) -> Result<Vec<Message>, rusqlite::Error> {
-        let sql = r#"
-            SELECT
-                m.ROWID, m.guid, m.text, m.attributedBody,
-                m.handle_id, m.date, m.is_from_me, m.service,
-                h.id as handle_identifier
+    /// Get a whole inline reply thread in chronological order: the message
+    /// that started it followed by every reply. Works from any message in
+    /// the thread.
+    pub fn get_reply_thread(&self, message_guid: &str) -> Result<Vec<Message>, rusqlite::Error> {
+        let originator = self.schema.column("m", "message", "thread_originator_guid");
+        let root_sql = format!(
+            r#"
+            SELECT COALESCE(NULLIF({originator}, ''), m.guid)
+            FROM message m
+            WHERE m.guid = ?1
+        "#
+        );
+        let root: String = self
+            .conn
+            .query_row(&root_sql, params![message_guid], |row| row.get(0))?;
+
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
             FROM message m
             LEFT JOIN handle h ON m.handle_id = h.ROWID
-            WHERE m.ROWID > ?1
-            ORDER BY m.ROWID ASC
-            LIMIT ?2
-        "#;
+            WHERE (m.guid = ?1 OR {originator} = ?1)
+              AND NOT {is_reaction}
+            ORDER BY mac_timestamp_nanos(m.date) ASC
+        "#
+        );
+
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let mut results = stmt
+            .query_map(params![root], Self::static_row_to_message)?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.hydrate_messages(&mut results)?;
+        Ok(results)
+    }
+
+    /// Get every attachment sent in a chat, newest first
+    pub fn get_attachments_for_chat(&self, chat_id: i64) -> Result<Vec<Attachment>, rusqlite::Error> {
+        if !self.schema.capabilities().attachments {
+            return Ok(Vec::new());
+        }
+        let columns = &self.schema.attachment_columns;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM attachment a
+            INNER JOIN message_attachment_join maj ON a.ROWID = maj.attachment_id
+            INNER JOIN chat_message_join cmj ON maj.message_id = cmj.message_id
+            INNER JOIN message m ON maj.message_id = m.ROWID
+            WHERE cmj.chat_id = ?1
+            ORDER BY mac_timestamp_nanos(m.date) DESC, a.ROWID ASC
+        "#
+        );
 
-        let mut stmt = self.conn.prepare(sql)?;
+        let mut stmt = self.conn.prepare_cached(&sql)?;
         let results = stmt
-            .query_map(params![message_id, count], |row| {
-                Self::static_row_to_message(row)
-            })?
+            .query_map(params![chat_id], |row| self.row_to_attachment(row))?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(results)
     }
 
-    fn static_row_to_message(row: &Row) -> Result<Message, rusqlite::Error> {
+    /// Fill in the data that lives outside the `message` row itself
+    pub(super) fn hydrate_messages(&self, messages: &mut [Message]) -> Result<(), rusqlite::Error> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let mut attachments = self.get_attachments_for_messages(messages)?;
+        for message in messages.iter_mut() {
+            message.attachments = attachments.remove(&message.id).unwrap_or_default();
+        }
+
+        // Reactions are looked up by part, which depends on the attachments
+        let mut reactions = self.get_reactions_for_messages(messages)?;
+        for message in messages.iter_mut() {
+            message.reactions = reactions.remove(&message.guid).unwrap_or_default();
+            message.contact_name = self.contact_name(message.contact_id.as_deref());
+            for reaction in &mut message.reactions {
+                reaction.contact_name = self.contact_name(reaction.contact_id.as_deref());
+            }
+        }
+        Ok(())
+    }
+
+    fn contact_name(&self, handle: Option<&str>) -> Option<String> {
+        self.contacts.resolve(handle?).map(str::to_string)
+    }
+
+    fn get_reactions_for_messages(
+        &self,
+        messages: &[Message],
+    ) -> Result<HashMap<String, Vec<Reaction>>, rusqlite::Error> {
+        if !self.schema.capabilities().reactions {
+            return Ok(HashMap::new());
+        }
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT
+                m.associated_message_guid, m.associated_message_type, m.text,
+                m.handle_id, m.date, m.is_from_me, h.id as handle_identifier
+            FROM message m
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            WHERE m.associated_message_guid IN (SELECT value FROM json_each(?1))
+              AND {is_reaction}
+        "#
+        );
+
+        let candidates: Vec<String> = messages
+            .iter()
+            .flat_map(|m| candidate_associated_guids(&m.guid, m.attachments.len()))
+            .collect();
+        let candidates = serde_json::to_string(&candidates).unwrap_or_else(|_| "[]".to_string());
+
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(params![candidates], |row| {
+            let associated_guid: String = row.get(0)?;
+            let associated_type: i64 = row.get(1)?;
+            let text: Option<String> = row.get(2)?;
+            let (target_guid, part_index) = parse_associated_guid(&associated_guid);
+
+            Ok(reaction_from_parts(
+                associated_type,
+                text.as_deref(),
+                row.get(3)?,
+                row.get(6)?,
+                row.get::<_, i32>(5)? == 1,
+                mac_timestamp_to_datetime(row.get(4)?),
+                part_index,
+            )
+            .map(|reaction| ReactionEvent {
+                target_guid: target_guid.to_string(),
+                reaction,
+            }))
+        })?;
+
+        let mut events = Vec::new();
+        for event in rows {
+            events.extend(event?);
+        }
+        Ok(fold_reactions(events))
+    }
+
+    fn get_attachments_for_messages(
+        &self,
+        messages: &[Message],
+    ) -> Result<HashMap<i64, Vec<Attachment>>, rusqlite::Error> {
+        // Pass the ids as one JSON array so the statement stays cacheable
+        // regardless of how many messages are being loaded
+        if !self.schema.capabilities().attachments {
+            return Ok(HashMap::new());
+        }
+        let columns = &self.schema.attachment_columns;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM message_attachment_join maj
+            INNER JOIN attachment a ON a.ROWID = maj.attachment_id
+            WHERE maj.message_id IN (SELECT value FROM json_each(?1))
+            ORDER BY maj.message_id, a.ROWID
+        "#
+        );
+
+        let ids = message_ids_json(messages);
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(params![ids], |row| self.row_to_attachment(row))?;
+
+        let mut by_message: HashMap<i64, Vec<Attachment>> = HashMap::new();
+        for attachment in rows {
+            let attachment = attachment?;
+            by_message
+                .entry(attachment.message_id)
+                .or_default()
+                .push(attachment);
+        }
+        Ok(by_message)
+    }
+
+    fn row_to_attachment(&self, row: &Row) -> Result<Attachment, rusqlite::Error> {
+        let filename: Option<String> = row.get(3)?;
+
+        Ok(Attachment {
+            message_id: row.get(0)?,
+            id: row.get(1)?,
+            guid: row.get(2)?,
+            filename: filename.map(|f| self.attachment_display_path(&f)),
+            mime_type: row.get(4)?,
+            uti: row.get(5)?,
+            total_bytes: row.get::<_, Option<i64>>(6)?.unwrap_or_default(),
+            transfer_name: row.get(7)?,
+            is_sticker: row.get::<_, Option<i32>>(8)?.unwrap_or_default() == 1,
+        })
+    }
+
+    pub(super) fn static_row_to_message(row: &Row) -> Result<Message, rusqlite::Error> {
         let text: Option<String> = row.get(2)?;
         let attributed_body: Option<Vec<u8>> = row.get(3)?;
 
-        // Prefer text, fall back to decoded attributedBody
-        let final_text =
-            text.or_else(|| attributed_body.as_ref().and_then(|data| decode_attributed_body(data)));
+        // Prefer text, fall back to decoded attributedBody. A body the
+        // decoder can't follow still gives its text, just without runs.
+        let body = attributed_body.as_deref().and_then(|data| {
+            parse_attributed_body(data).or_else(|| {
+                decode_attributed_body(data).map(|text| AttributedBody {
+                    text,
+                    runs: Vec::new(),
+                })
+            })
+        });
+        let body = body.unwrap_or_default();
+        let final_text = text.or_else(|| Some(body.text).filter(|t| !t.is_empty()));
 
         let date_raw: i64 = row.get(5)?;
         let is_from_me: i32 = row.get(6)?;
 
+        let thread_originator_guid: Option<String> = row.get(9)?;
+        let thread_originator_part: Option<String> = row.get(10)?;
+        let reply_to = thread_originator_guid
+            .filter(|guid| !guid.is_empty())
+            .map(|guid| ReplyTo {
+                guid,
+                part_index: parse_originator_part(thread_originator_part.as_deref()),
+            });
+
+        let date_retracted = optional_mac_timestamp_to_datetime(row.get(12)?);
+        let summary_info = row
+            .get::<_, Option<Vec<u8>>>(13)?
+            .and_then(|data| parse_summary_info(&data))
+            .unwrap_or_default();
+        // A fully unsent message keeps its row but loses its text; after a
+        // partial unsend the remaining parts are still there
+        let is_unsent = (date_retracted.is_some() || !summary_info.retracted_parts.is_empty())
+            && final_text.as_deref().is_none_or(str::is_empty);
+
         Ok(Message {
             id: row.get(0)?,
             guid: row.get(1)?,
             text: final_text,
             handle_id: row.get(4)?,
             date: mac_timestamp_to_datetime(date_raw),
+            date_raw,
+            date_read: optional_mac_timestamp_to_datetime(row.get(14)?),
+            date_delivered: optional_mac_timestamp_to_datetime(row.get(15)?),
             is_from_me: is_from_me == 1,
             service: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
             contact_name: None,
             contact_id: row.get(8)?,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            reply_to,
+            date_edited: optional_mac_timestamp_to_datetime(row.get(11)?),
+            date_retracted,
+            is_unsent,
+            retracted_parts: summary_info.retracted_parts,
+            edit_history: summary_info.versions,
+            runs: body.runs,
         })
     }
+}
 
-    fn get_chat_participants(&self, chat_id: i64) -> Result<Vec<Handle>, rusqlite::Error> {
-        let sql = r#"
-            SELECT h.ROWID, h.id, h.service, h.uncanonicalized_id
-            FROM handle h
-            INNER JOIN chat_handle_join chj ON h.ROWID = chj.handle_id
-            WHERE chj.chat_id = ?1
-        "#;
-
-        let mut stmt = self.conn.prepare(sql)?;
-        let results = stmt
-            .query_map(params![chat_id], |row| {
-                Ok(Handle {
-                    id: row.get(0)?,
-                    identifier: row.get(1)?,
-                    service: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
-                    uncanonicalized_id: row.get(3)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(results)
-    }
-
-    fn get_last_message_for_chat(&self, chat_id: i64) -> Result<Option<Message>, rusqlite::Error> {
-        let messages = self.get_messages_for_chat(chat_id, 1)?;
-        Ok(messages.into_iter().next())
-    }
+fn message_ids_json(messages: &[Message]) -> String {
+    let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+    serde_json::to_string(&ids).unwrap_or_else(|_| "[]".to_string())
+}
 
-    fn get_message_count_for_chat(&self, chat_id: i64) -> Result<i64, rusqlite::Error> {
-        let sql = "SELECT COUNT(*) FROM chat_message_join WHERE chat_id = ?1";
-        self.conn.query_row(sql, params![chat_id], |row| row.get(0))
-    }
+/// `thread_originator_part` looks like `1:0:12` (part, then the range of the
+/// part being replied to); only the part index is kept
+fn parse_originator_part(part: Option<&str>) -> i64 {
+    part.and_then(|p| p.split(':').next())
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(0)
 }
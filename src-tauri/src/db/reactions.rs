@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use super::models::{Reaction, ReactionKind};
+
+/// SQL that's true for tapback rows, given the `associated_message_type`
+/// expression: 2000-2006 add a reaction, 3000-3006 remove the matching one
+pub fn is_reaction_sql(associated_type: &str) -> String {
+    format!(
+        "(COALESCE({associated_type}, 0) BETWEEN 2000 AND 2006 \
+         OR COALESCE({associated_type}, 0) BETWEEN 3000 AND 3006)"
+    )
+}
+
+/// Decode an `associated_message_type` into the reaction kind and whether
+/// it removes the reaction
+pub fn reaction_kind(associated_type: i64) -> Option<(ReactionKind, bool)> {
+    let is_removal = match associated_type {
+        2000..=2006 => false,
+        3000..=3006 => true,
+        _ => return None,
+    };
+
+    let kind = match associated_type % 1000 {
+        0 => ReactionKind::Loved,
+        1 => ReactionKind::Liked,
+        2 => ReactionKind::Disliked,
+        3 => ReactionKind::Laughed,
+        4 => ReactionKind::Emphasized,
+        5 => ReactionKind::Questioned,
+        _ => ReactionKind::Emoji,
+    };
+    Some((kind, is_removal))
+}
+
+/// Split an `associated_message_guid` like `p:1/<guid>` or `bp:<guid>` into
+/// the target message guid and the part of that message being reacted to
+pub fn parse_associated_guid(associated: &str) -> (&str, i64) {
+    if let Some(rest) = associated.strip_prefix("p:") {
+        if let Some((part, guid)) = rest.split_once('/') {
+            return (guid, part.parse().unwrap_or(0));
+        }
+    }
+
+    if let Some(guid) = associated.strip_prefix("bp:") {
+        return (guid, 0);
+    }
+
+    (associated, 0)
+}
+
+/// Every `associated_message_guid` a reaction to this message could carry.
+/// Each attachment is its own part, and text around attachments can be
+/// split into further parts, so this over-generates part indexes a little
+/// to keep the lookup an indexed `IN` rather than a table scan.
+pub fn candidate_associated_guids(guid: &str, attachment_count: usize) -> Vec<String> {
+    let max_part = attachment_count * 2 + 1;
+    let mut candidates = Vec::with_capacity(max_part + 3);
+    candidates.push(guid.to_string());
+    candidates.push(format!("bp:{}", guid));
+    for part in 0..=max_part {
+        candidates.push(format!("p:{}/{}", part, guid));
+    }
+    candidates
+}
+
+/// The emoji of a custom emoji tapback, taken from its fallback text
+/// (`Reacted 🎉 to “…”`)
+pub fn emoji_from_text(text: &str) -> Option<String> {
+    let rest = text.strip_prefix("Reacted ")?;
+    let (emoji, _) = rest.split_once(" to ")?;
+    Some(emoji.to_string())
+}
+
+/// A single tapback row, before it is folded into its target message
+pub struct ReactionEvent {
+    pub target_guid: String,
+    pub reaction: Reaction,
+}
+
+/// Collapse raw tapback events into the current reactions per message.
+///
+/// Events are replayed in date order and the latest one per part, reactor
+/// and kind wins, so a removal leaves a single reaction marked removed.
+pub fn fold_reactions(mut events: Vec<ReactionEvent>) -> HashMap<String, Vec<Reaction>> {
+    events.sort_by_key(|e| e.reaction.date);
+
+    type Key = (String, i64, bool, i64, ReactionKind);
+    let mut latest: HashMap<Key, (usize, Reaction)> = HashMap::new();
+
+    for (order, event) in events.into_iter().enumerate() {
+        let mut reaction = event.reaction;
+        let key = (
+            event.target_guid,
+            reaction.part_index,
+            reaction.is_from_me,
+            reaction.handle_id,
+            reaction.kind,
+        );
+
+        // Removals don't repeat the emoji, so keep the one being removed
+        let (first_seen, emoji) = match latest.get(&key) {
+            Some((o, previous)) => (*o, previous.emoji.clone()),
+            None => (order, None),
+        };
+        if reaction.emoji.is_none() {
+            reaction.emoji = emoji;
+        }
+        latest.insert(key, (first_seen, reaction));
+    }
+
+    let mut folded: Vec<(String, usize, Reaction)> = latest
+        .into_iter()
+        .map(|(key, (order, reaction))| (key.0, order, reaction))
+        .collect();
+    folded.sort_by_key(|(_, order, _)| *order);
+
+    let mut by_target: HashMap<String, Vec<Reaction>> = HashMap::new();
+    for (target, _, reaction) in folded {
+        by_target.entry(target).or_default().push(reaction);
+    }
+    by_target
+}
+
+pub fn reaction_from_parts(
+    associated_type: i64,
+    text: Option<&str>,
+    handle_id: i64,
+    contact_id: Option<String>,
+    is_from_me: bool,
+    date: DateTime<Utc>,
+    part_index: i64,
+) -> Option<Reaction> {
+    let (kind, is_removed) = reaction_kind(associated_type)?;
+    let emoji = match kind {
+        ReactionKind::Emoji => text.and_then(emoji_from_text),
+        _ => None,
+    };
+
+    Some(Reaction {
+        kind,
+        emoji,
+        handle_id,
+        contact_id,
+        contact_name: None,
+        is_from_me,
+        date,
+        part_index,
+        is_removed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn event(target: &str, associated_type: i64, handle_id: i64, secs: i64) -> ReactionEvent {
+        ReactionEvent {
+            target_guid: target.to_string(),
+            reaction: reaction_from_parts(
+                associated_type,
+                None,
+                handle_id,
+                None,
+                false,
+                Utc.timestamp_opt(secs, 0).unwrap(),
+                0,
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_associated_guid() {
+        assert_eq!(parse_associated_guid("p:2/ABC-123"), ("ABC-123", 2));
+        assert_eq!(parse_associated_guid("bp:ABC-123"), ("ABC-123", 0));
+        assert_eq!(parse_associated_guid("ABC-123"), ("ABC-123", 0));
+    }
+
+    #[test]
+    fn test_reaction_kind() {
+        assert_eq!(reaction_kind(2003), Some((ReactionKind::Laughed, false)));
+        assert_eq!(reaction_kind(3000), Some((ReactionKind::Loved, true)));
+        assert_eq!(reaction_kind(1000), None);
+        assert_eq!(
+            emoji_from_text("Reacted 🎉 to “party”"),
+            Some("🎉".to_string())
+        );
+    }
+
+    #[test]
+    fn test_removal_replaces_reaction() {
+        let folded = fold_reactions(vec![
+            event("A", 2000, 1, 10),
+            event("A", 2003, 2, 11),
+            event("A", 3000, 1, 12),
+        ]);
+
+        let reactions = &folded["A"];
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].kind, ReactionKind::Loved);
+        assert!(reactions[0].is_removed);
+        assert_eq!(reactions[1].kind, ReactionKind::Laughed);
+        assert!(!reactions[1].is_removed);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::Connection;
+use serde::Serialize;
+
+use super::connection::{ChatDb, DbError};
+use super::reactions::is_reaction_sql;
+
+/// Tables every supported database has; without them it isn't chat.db
+const REQUIRED_TABLES: &[&str] = &[
+    "message",
+    "handle",
+    "chat",
+    "chat_message_join",
+    "chat_handle_join",
+];
+
+const KNOWN_TABLES: &[&str] = &[
+    "message",
+    "handle",
+    "chat",
+    "chat_message_join",
+    "chat_handle_join",
+    "attachment",
+    "message_attachment_join",
+];
+
+/// Columns only some macOS releases have, and the value queries select in
+/// their place so rows decode the same on every version
+const OPTIONAL_COLUMNS: &[(&str, &str, &str)] = &[
+    ("message", "attributedBody", "NULL"),
+    ("message", "associated_message_guid", "NULL"),
+    ("message", "associated_message_type", "0"),
+    ("message", "balloon_bundle_id", "NULL"),
+    ("message", "thread_originator_guid", "NULL"),
+    ("message", "thread_originator_part", "NULL"),
+    ("message", "date_edited", "0"),
+    ("message", "date_retracted", "0"),
+    ("message", "message_summary_info", "NULL"),
+    ("message", "date_read", "0"),
+    ("message", "date_delivered", "0"),
+    ("handle", "uncanonicalized_id", "NULL"),
+    ("attachment", "uti", "NULL"),
+    ("attachment", "total_bytes", "0"),
+    ("attachment", "transfer_name", "NULL"),
+    ("attachment", "is_sticker", "0"),
+];
+
+/// The tables and columns of an open database, read once with
+/// `PRAGMA table_info`, and the query fragments built from them
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    columns: HashMap<String, HashSet<String>>,
+    /// Columns every message query selects, in the order
+    /// `static_row_to_message` reads them
+    pub(super) message_columns: String,
+    /// True for tapback rows, which are folded into their targets
+    pub(super) is_reaction: String,
+    /// Columns attachment queries select, in the order `row_to_attachment`
+    /// reads them
+    pub(super) attachment_columns: String,
+}
+
+/// Features that depend on the macOS release that wrote the database
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Text stored only in `attributedBody` (macOS 10.13+ for most messages)
+    pub rich_text: bool,
+    /// Tapbacks (macOS 10.12+)
+    pub reactions: bool,
+    /// Inline replies (macOS 11+)
+    pub replies: bool,
+    /// Edited messages and their history (macOS 13+)
+    pub edits: bool,
+    /// Unsent messages (macOS 13+)
+    pub unsends: bool,
+    pub read_receipts: bool,
+    /// Link previews and other app messages
+    pub link_previews: bool,
+    pub attachments: bool,
+    pub stickers: bool,
+}
+
+/// What the diagnostics screen shows about the current database
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    pub capabilities: Capabilities,
+    /// `table.column` for each optional column this database lacks
+    pub missing_columns: Vec<String>,
+    pub message_count: i64,
+    pub sqlite_version: String,
+}
+
+impl Schema {
+    pub fn read(conn: &Connection) -> Result<Self, DbError> {
+        let mut columns = HashMap::new();
+        for table in KNOWN_TABLES {
+            let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+            let names = stmt
+                .query_map([table], |row| row.get::<_, String>(0))?
+                .collect::<Result<HashSet<_>, _>>()?;
+            if !names.is_empty() {
+                columns.insert(table.to_string(), names);
+            }
+        }
+
+        if let Some(missing) = REQUIRED_TABLES.iter().find(|t| !columns.contains_key(**t)) {
+            return Err(DbError::UnsupportedSchema(format!(
+                "no {missing} table; is this a Messages database?"
+            )));
+        }
+        Ok(Self::from_columns(columns))
+    }
+
+    fn from_columns(columns: HashMap<String, HashSet<String>>) -> Self {
+        let mut schema = Self {
+            columns,
+            ..Self::default()
+        };
+        let message = |column| schema.column("m", "message", column);
+        let message_columns = format!(
+            "m.ROWID, m.guid, m.text, {}, m.handle_id, mac_timestamp_nanos(m.date), \
+             m.is_from_me, m.service, h.id AS handle_identifier, {}, {}, {}, {}, {}, {}, {}",
+            message("attributedBody"),
+            message("thread_originator_guid"),
+            message("thread_originator_part"),
+            message("date_edited"),
+            message("date_retracted"),
+            message("message_summary_info"),
+            message("date_read"),
+            message("date_delivered"),
+        );
+        let is_reaction = is_reaction_sql(&message("associated_message_type"));
+        let attachment = |column| schema.column("a", "attachment", column);
+        let attachment_columns = format!(
+            "maj.message_id, a.ROWID, a.guid, a.filename, a.mime_type, {}, {}, {}, {}",
+            attachment("uti"),
+            attachment("total_bytes"),
+            attachment("transfer_name"),
+            attachment("is_sticker"),
+        );
+        schema.message_columns = message_columns;
+        schema.is_reaction = is_reaction;
+        schema.attachment_columns = attachment_columns;
+        schema
+    }
+
+    pub fn has_table(&self, table: &str) -> bool {
+        self.columns.contains_key(table)
+    }
+
+    pub fn has_column(&self, table: &str, column: &str) -> bool {
+        self.columns
+            .get(table)
+            .is_some_and(|columns| columns.contains(column))
+    }
+
+    /// `alias.column` in SQL, or the column's stand-in value when this
+    /// database doesn't have it
+    pub fn column(&self, alias: &str, table: &str, column: &str) -> String {
+        if self.has_column(table, column) {
+            return format!("{alias}.{column}");
+        }
+        OPTIONAL_COLUMNS
+            .iter()
+            .find(|(t, c, _)| *t == table && *c == column)
+            .map_or("NULL", |(_, _, stand_in)| stand_in)
+            .to_string()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        let message = |column| self.has_column("message", column);
+        let attachments = self.has_table("attachment") && self.has_table("message_attachment_join");
+        Capabilities {
+            rich_text: message("attributedBody"),
+            reactions: message("associated_message_guid") && message("associated_message_type"),
+            replies: message("thread_originator_guid"),
+            edits: message("date_edited") && message("message_summary_info"),
+            unsends: message("date_retracted"),
+            read_receipts: message("date_read") && message("date_delivered"),
+            link_previews: message("balloon_bundle_id"),
+            attachments,
+            stickers: attachments && self.has_column("attachment", "is_sticker"),
+        }
+    }
+
+    pub fn missing_columns(&self) -> Vec<String> {
+        OPTIONAL_COLUMNS
+            .iter()
+            .filter(|(table, column, _)| !self.has_column(table, column))
+            .map(|(table, column, _)| format!("{table}.{column}"))
+            .collect()
+    }
+}
+
+impl ChatDb {
+    pub fn diagnostics(&self) -> Result<Diagnostics, rusqlite::Error> {
+        let message_count = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM message", [], |row| row.get(0))?;
+        let sqlite_version = self
+            .conn
+            .query_row("SELECT sqlite_version()", [], |row| row.get(0))?;
+        Ok(Diagnostics {
+            capabilities: self.schema.capabilities(),
+            missing_columns: self.schema.missing_columns(),
+            message_count,
+            sqlite_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::edits;
+
+    #[test]
+    fn test_missing_columns_degrade() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE message (ROWID INTEGER PRIMARY KEY, guid TEXT, text TEXT,
+                handle_id INTEGER, service TEXT, date INTEGER, is_from_me INTEGER,
+                associated_message_guid TEXT, associated_message_type INTEGER);
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT, service TEXT);
+            CREATE TABLE chat (ROWID INTEGER PRIMARY KEY, guid TEXT);
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            INSERT INTO message VALUES (1, 'g', 'hi', 0, 'SMS', 5, 0, NULL, 0);
+            "#,
+        )
+        .unwrap();
+
+        let schema = Schema::read(&conn).unwrap();
+        let capabilities = schema.capabilities();
+        assert!(capabilities.reactions);
+        assert!(!capabilities.replies && !capabilities.edits && !capabilities.attachments);
+        assert_eq!(schema.column("m", "message", "date_edited"), "0");
+        assert!(schema
+            .missing_columns()
+            .contains(&"message.thread_originator_guid".to_string()));
+
+        // The stand-ins keep message queries valid
+        edits::register_functions(&conn).unwrap();
+        let sql = format!(
+            "SELECT {} FROM message m LEFT JOIN handle h ON h.ROWID = m.handle_id WHERE NOT {}",
+            schema.message_columns, schema.is_reaction
+        );
+        let count = conn
+            .prepare(&sql)
+            .unwrap()
+            .query_map([], |row| row.get::<_, i64>(0))
+            .unwrap()
+            .count();
+        assert_eq!(count, 1);
+
+        conn.execute_batch("DROP TABLE chat").unwrap();
+        assert!(matches!(
+            Schema::read(&conn),
+            Err(DbError::UnsupportedSchema(_))
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+mod parser;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{Duration, Local, NaiveDate, NaiveTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter};
+
+use super::connection::ChatDb;
+use super::models::Message;
+use crate::index::{fts_term, SearchOptions};
+use crate::utils::datetime_to_mac_timestamp;
+
+use parser::Filter;
+pub use parser::{parse_query, SearchQuery};
+
+/// How far back `get_conversation_affinity` counts my messages
+const AFFINITY_WINDOW_DAYS: i64 = 365;
+
+impl ChatDb {
+    /// Make the app's search index queryable as `search_index.message_fts`
+    pub fn attach_search_index(&self, path: &Path) -> Result<(), rusqlite::Error> {
+        if !self.search_index_attached()? {
+            self.conn.execute(
+                "ATTACH DATABASE ?1 AS search_index",
+                [path.to_string_lossy()],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn search_index_attached(&self) -> Result<bool, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_database_list WHERE name = 'search_index')",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// Run a parsed search. Text terms go through the attached search
+    /// index and rank by BM25; searches without text list newest first.
+    pub fn search_messages(
+        &self,
+        query: &SearchQuery,
+        options: &SearchOptions,
+    ) -> Result<Vec<Message>, rusqlite::Error> {
+        let text_match = |expression: String| {
+            if options.include_edits {
+                expression
+            } else {
+                format!("{{text}} : ({expression})")
+            }
+        };
+
+        let mut conditions = Vec::new();
+        let mut params: Vec<Value> = Vec::new();
+
+        let terms: Vec<String> = query
+            .clauses
+            .iter()
+            .filter(|clause| !clause.negated)
+            .filter_map(|clause| match &clause.filter {
+                Filter::Text { text, prefix } => fts_term(text, *prefix),
+                _ => None,
+            })
+            .collect();
+        let ranked = !terms.is_empty();
+        if ranked {
+            conditions.push("f.message_fts MATCH ?".to_string());
+            params.push(Value::Text(text_match(terms.join(" "))));
+        }
+
+        for clause in &query.clauses {
+            let (condition, values) = match &clause.filter {
+                Filter::Text { text, prefix } => match fts_term(text, *prefix) {
+                    Some(term) if clause.negated => (
+                        "m.ROWID IN (SELECT rowid FROM search_index.message_fts \
+                         WHERE message_fts MATCH ?)"
+                            .to_string(),
+                        vec![Value::Text(text_match(term))],
+                    ),
+                    // Positive terms are in the MATCH above
+                    _ => continue,
+                },
+                filter => self.filter_condition(filter)?,
+            };
+            conditions.push(if clause.negated {
+                format!("NOT COALESCE(({condition}), 0)")
+            } else {
+                format!("({condition})")
+            });
+            params.extend(values);
+        }
+
+        if conditions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if let Some(chat_ids) = &options.chat_ids {
+            conditions.push(
+                "m.ROWID IN (SELECT message_id FROM chat_message_join \
+                 WHERE chat_id IN (SELECT value FROM json_each(?)))"
+                    .to_string(),
+            );
+            params.push(Value::Text(
+                serde_json::to_string(chat_ids).unwrap_or_else(|_| "[]".to_string()),
+            ));
+        }
+        params.push(Value::Integer(options.limit));
+
+        let (join, order) = if ranked {
+            (
+                "INNER JOIN search_index.message_fts f ON f.rowid = m.ROWID",
+                "f.rank",
+            )
+        } else {
+            ("", "mac_timestamp_nanos(m.date) DESC")
+        };
+        let columns = &self.schema.message_columns;
+        let is_reaction = &self.schema.is_reaction;
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM message m
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            {join}
+            WHERE NOT {is_reaction}
+              AND {conditions}
+            ORDER BY {order}
+            LIMIT ?
+        "#,
+            conditions = conditions.join("\n              AND "),
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut results = stmt
+            .query_map(params_from_iter(params), Self::static_row_to_message)?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.hydrate_messages(&mut results)?;
+        Ok(results)
+    }
+
+    /// For each message, how many messages I sent in its chat over the
+    /// past year. Messages in no chat are left out.
+    pub fn get_conversation_affinity(
+        &self,
+        message_ids: &[i64],
+    ) -> Result<HashMap<i64, f64>, rusqlite::Error> {
+        let ids = serde_json::to_string(message_ids).unwrap_or_else(|_| "[]".to_string());
+        let since = datetime_to_mac_timestamp(Utc::now() - Duration::days(AFFINITY_WINDOW_DAYS));
+
+        let mut stmt = self.conn.prepare_cached(
+            r#"
+            WITH hit_chat AS (
+                SELECT cmj.message_id, cmj.chat_id
+                FROM chat_message_join cmj
+                WHERE cmj.message_id IN (SELECT value FROM json_each(?1))
+            ),
+            activity AS (
+                SELECT cmj.chat_id, COUNT(*) AS sent
+                FROM chat_message_join cmj
+                INNER JOIN message m ON m.ROWID = cmj.message_id
+                WHERE cmj.chat_id IN (SELECT chat_id FROM hit_chat)
+                  AND m.is_from_me = 1
+                  AND mac_timestamp_nanos(m.date) >= ?2
+                GROUP BY cmj.chat_id
+            )
+            SELECT hc.message_id, MAX(COALESCE(a.sent, 0))
+            FROM hit_chat hc
+            LEFT JOIN activity a ON a.chat_id = hc.chat_id
+            GROUP BY hc.message_id
+            "#,
+        )?;
+        let affinity = stmt
+            .query_map(params![ids, since], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as f64))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(affinity)
+    }
+
+    /// SQL for one non-text filter and the values for its placeholders
+    fn filter_condition(&self, filter: &Filter) -> Result<(String, Vec<Value>), rusqlite::Error> {
+        let handles = |name: &str| -> Result<Value, rusqlite::Error> {
+            let ids = self.handles_matching(name)?;
+            Ok(Value::Text(
+                serde_json::to_string(&ids).unwrap_or_else(|_| "[]".to_string()),
+            ))
+        };
+
+        Ok(match filter {
+            Filter::From(name) => (
+                "m.is_from_me = 0 AND m.handle_id IN (SELECT value FROM json_each(?))".to_string(),
+                vec![handles(name)?],
+            ),
+            Filter::To(name) => (
+                r#"m.is_from_me = 1 AND m.ROWID IN (
+                    SELECT cmj.message_id
+                    FROM chat_message_join cmj
+                    INNER JOIN chat_handle_join chj ON chj.chat_id = cmj.chat_id
+                    WHERE chj.handle_id IN (SELECT value FROM json_each(?)))"#
+                    .to_string(),
+                vec![handles(name)?],
+            ),
+            Filter::In(name) => {
+                let pattern = Value::Text(format!("%{}%", escape_like(name)));
+                (
+                    r#"m.ROWID IN (
+                        SELECT cmj.message_id
+                        FROM chat_message_join cmj
+                        INNER JOIN chat c ON c.ROWID = cmj.chat_id
+                        WHERE c.display_name LIKE ? ESCAPE '\'
+                           OR c.chat_identifier LIKE ? ESCAPE '\')"#
+                        .to_string(),
+                    vec![pattern.clone(), pattern],
+                )
+            }
+            Filter::Before(date) => (
+                "mac_timestamp_nanos(m.date) < ?".to_string(),
+                vec![Value::Integer(start_of_day(*date))],
+            ),
+            Filter::After(date) => (
+                "mac_timestamp_nanos(m.date) >= ?".to_string(),
+                vec![Value::Integer(start_of_day(*date))],
+            ),
+            Filter::FromMe => ("m.is_from_me = 1".to_string(), Vec::new()),
+            Filter::HasAttachment if !self.schema.capabilities().attachments => {
+                ("0".to_string(), Vec::new())
+            }
+            Filter::HasAttachment => (
+                "EXISTS (SELECT 1 FROM message_attachment_join maj WHERE maj.message_id = m.ROWID)"
+                    .to_string(),
+                Vec::new(),
+            ),
+            // Most messages keep their text only in attributedBody, so look
+            // for URLs in the decoded text the index holds
+            Filter::HasLink => {
+                let indexed = if self.search_index_attached()? {
+                    " OR m.ROWID IN (SELECT rowid FROM search_index.message_fts \
+                     WHERE text LIKE '%://%')"
+                } else {
+                    ""
+                };
+                (
+                    format!(
+                        "m.text LIKE '%://%' OR {} LIKE '%URLBalloonProvider'{indexed}",
+                        self.schema.column("m", "message", "balloon_bundle_id")
+                    ),
+                    Vec::new(),
+                )
+            }
+            Filter::Service(service) => (
+                "m.service = ? COLLATE NOCASE".to_string(),
+                vec![Value::Text(service.clone())],
+            ),
+            Filter::Text { .. } => unreachable!("text is matched through the index"),
+        })
+    }
+}
+
+/// `text` with LIKE's wildcards escaped by `\`, to match it literally
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Local midnight at the start of `date`, as a `message.date` value
+fn start_of_day(date: NaiveDate) -> i64 {
+    let midnight = date.and_time(NaiveTime::MIN);
+    let local = midnight
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc());
+    datetime_to_mac_timestamp(local)
+}
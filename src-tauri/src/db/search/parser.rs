@@ -0,0 +1,273 @@
+use chrono::NaiveDate;
+use thiserror::Error;
+
+/// A parsed search such as `from:alice in:'Family' after:2024-03-01 has:link`.
+/// Every clause must match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub clauses: Vec<Clause>,
+}
+
+impl SearchQuery {
+    /// Whether running it reads the search index: text terms, and links
+    /// that are only in `attributedBody`
+    pub fn uses_index(&self) -> bool {
+        self.clauses
+            .iter()
+            .any(|clause| matches!(clause.filter, Filter::Text { .. } | Filter::HasLink))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    /// Written with a leading `-`
+    pub negated: bool,
+    pub filter: Filter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// A word, `prefix*` or `"quoted phrase"`, matched in the message text
+    Text {
+        text: String,
+        prefix: bool,
+    },
+    /// Sent by a handle or contact matching the name
+    From(String),
+    /// Sent by me into a chat with a handle or contact matching the name
+    To(String),
+    /// In a chat whose name or identifier matches
+    In(String),
+    /// Sent before the start of this (local) day
+    Before(NaiveDate),
+    /// Sent on or after this (local) day
+    After(NaiveDate),
+    FromMe,
+    HasAttachment,
+    HasLink,
+    /// `SMS`, `iMessage` or `RCS`
+    Service(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ParseError {
+    #[error("Unclosed quote at column {0}")]
+    UnclosedQuote(usize),
+    #[error("`{operator}:` needs a value (column {column})")]
+    MissingValue { operator: String, column: usize },
+    #[error("Invalid date {value:?} for `{operator}:` (column {column}); use YYYY-MM-DD")]
+    InvalidDate {
+        operator: String,
+        value: String,
+        column: usize,
+    },
+    #[error("Unknown value {value:?} for `{operator}:` (column {column}); expected {expected}")]
+    UnknownValue {
+        operator: String,
+        value: String,
+        column: usize,
+        expected: &'static str,
+    },
+}
+
+const OPERATORS: &[&str] = &[
+    "from", "to", "in", "before", "after", "is", "has", "service",
+];
+
+/// Parse the search syntax. Words that merely look like operators
+/// (`https://…`, `re:`) are searched as text.
+pub fn parse_query(input: &str) -> Result<SearchQuery, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut clauses = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        if chars[pos].is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos;
+        let negated = chars[pos] == '-' && chars.get(pos + 1).is_some_and(|c| !c.is_whitespace());
+        if negated {
+            pos += 1;
+        }
+
+        let filter = if chars[pos] == '"' {
+            let (text, end) = read_quoted(&chars, pos)?;
+            let prefix = chars.get(end) == Some(&'*');
+            pos = if prefix { end + 1 } else { end };
+            Filter::Text { text, prefix }
+        } else {
+            let word_end = (pos..chars.len())
+                .find(|&i| chars[i].is_whitespace())
+                .unwrap_or(chars.len());
+            let word: String = chars[pos..word_end].iter().collect();
+
+            match word.split_once(':') {
+                Some((name, _)) if OPERATORS.contains(&name.to_lowercase().as_str()) => {
+                    let operator = name.to_lowercase();
+                    let value_start = pos + name.chars().count() + 1;
+                    let (value, end) = match chars.get(value_start) {
+                        Some('"') | Some('\'') => read_quoted(&chars, value_start)?,
+                        _ => (chars[value_start..word_end].iter().collect(), word_end),
+                    };
+                    pos = end;
+                    if value.is_empty() {
+                        return Err(ParseError::MissingValue {
+                            operator,
+                            column: start + 1,
+                        });
+                    }
+                    operator_filter(&operator, value, start + 1)?
+                }
+                _ => {
+                    pos = word_end;
+                    let text = word.trim_end_matches('*');
+                    Filter::Text {
+                        text: text.to_string(),
+                        prefix: text.len() < word.len(),
+                    }
+                }
+            }
+        };
+
+        clauses.push(Clause { negated, filter });
+    }
+
+    Ok(SearchQuery { clauses })
+}
+
+/// Read a `"…"` or `'…'` string starting at `start`; returns its contents
+/// and the position after the closing quote
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), ParseError> {
+    let quote = chars[start];
+    let end = (start + 1..chars.len())
+        .find(|&i| chars[i] == quote)
+        .ok_or(ParseError::UnclosedQuote(start + 1))?;
+    Ok((chars[start + 1..end].iter().collect(), end + 1))
+}
+
+fn operator_filter(operator: &str, value: String, column: usize) -> Result<Filter, ParseError> {
+    let unknown = |expected| ParseError::UnknownValue {
+        operator: operator.to_string(),
+        value: value.clone(),
+        column,
+        expected,
+    };
+
+    Ok(match operator {
+        "from" if value.eq_ignore_ascii_case("me") => Filter::FromMe,
+        "from" => Filter::From(value),
+        "to" => Filter::To(value),
+        "in" => Filter::In(value),
+        "before" | "after" => {
+            let date = parse_date(&value).ok_or_else(|| ParseError::InvalidDate {
+                operator: operator.to_string(),
+                value: value.clone(),
+                column,
+            })?;
+            if operator == "before" {
+                Filter::Before(date)
+            } else {
+                Filter::After(date)
+            }
+        }
+        "is" => match value.to_lowercase().as_str() {
+            "from_me" | "sent" => Filter::FromMe,
+            _ => return Err(unknown("from_me")),
+        },
+        "has" => match value.to_lowercase().as_str() {
+            "attachment" | "attachments" => Filter::HasAttachment,
+            "link" | "links" => Filter::HasLink,
+            _ => return Err(unknown("attachment or link")),
+        },
+        "service" => match value.to_lowercase().as_str() {
+            "sms" => Filter::Service("SMS".to_string()),
+            "imessage" => Filter::Service("iMessage".to_string()),
+            "rcs" => Filter::Service("RCS".to_string()),
+            _ => return Err(unknown("sms, imessage or rcs")),
+        },
+        _ => unreachable!("operator {operator} is in OPERATORS"),
+    })
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clause(negated: bool, filter: Filter) -> Clause {
+        Clause { negated, filter }
+    }
+
+    fn text(text: &str, prefix: bool) -> Filter {
+        Filter::Text {
+            text: text.to_string(),
+            prefix,
+        }
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        let query =
+            parse_query("from:alice in:'Family' after:2024-03-01 has:link -service:sms dinner*")
+                .unwrap();
+        assert_eq!(
+            query.clauses,
+            [
+                clause(false, Filter::From("alice".to_string())),
+                clause(false, Filter::In("Family".to_string())),
+                clause(
+                    false,
+                    Filter::After(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap())
+                ),
+                clause(false, Filter::HasLink),
+                clause(true, Filter::Service("SMS".to_string())),
+                clause(false, text("dinner", true)),
+            ]
+        );
+        assert!(query.uses_index());
+        assert!(!parse_query("from:alice is:from_me").unwrap().uses_index());
+    }
+
+    #[test]
+    fn test_parse_phrases_negation_and_plain_colons() {
+        let query = parse_query("\"see you\" -\"not now\" https://x.com - is:from_me").unwrap();
+        assert_eq!(
+            query.clauses,
+            [
+                clause(false, text("see you", false)),
+                clause(true, text("not now", false)),
+                clause(false, text("https://x.com", false)),
+                clause(false, text("-", false)),
+                clause(false, Filter::FromMe),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_query("hi \"there").unwrap_err(),
+            ParseError::UnclosedQuote(4)
+        );
+        assert!(matches!(
+            parse_query("from:").unwrap_err(),
+            ParseError::MissingValue { column: 1, .. }
+        ));
+        assert_eq!(
+            parse_query("x before:March").unwrap_err().to_string(),
+            "Invalid date \"March\" for `before:` (column 3); use YYYY-MM-DD"
+        );
+        assert!(matches!(
+            parse_query("has:photo").unwrap_err(),
+            ParseError::UnknownValue { .. }
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use super::connection::{ChatDb, DbError};
+use super::source::DataSource;
+use crate::utils::app_data_dir;
+
+/// A point-in-time copy of a data source's database in app-private storage.
+///
+/// Messages.app writes to chat.db continuously and keeps recent messages in
+/// chat.db-wal, so long jobs read from a snapshot to see one consistent view
+/// and never contend with Messages for locks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub source: DataSource,
+    pub path: PathBuf,
+    pub taken_at: DateTime<Utc>,
+}
+
+impl Snapshot {
+    /// Copy the source database, including anything still in its WAL, using
+    /// SQLite's online backup API
+    pub fn take(source: &DataSource) -> Result<Self, DbError> {
+        let dir = app_data_dir().join("snapshots");
+        fs::create_dir_all(&dir).map_err(|e| DbError::Snapshot(e.to_string()))?;
+
+        let path = dir.join("chat.db");
+        let tmp_path = dir.join("chat.db.tmp");
+        let _ = fs::remove_file(&tmp_path);
+
+        let db = source.open()?;
+        db.conn.backup(DatabaseName::Main, &tmp_path, None)?;
+
+        // The copy inherits WAL mode from chat.db. Switch it back to a plain
+        // rollback journal so it can be opened read-only without -wal/-shm.
+        {
+            let copy = Connection::open(&tmp_path)?;
+            copy.pragma_update(None, "journal_mode", "DELETE")?;
+        }
+
+        // Replace atomically; connections still reading the previous
+        // snapshot keep their open file until they are dropped
+        fs::rename(&tmp_path, &path).map_err(|e| DbError::Snapshot(e.to_string()))?;
+
+        Ok(Self {
+            source: source.clone(),
+            path,
+            taken_at: Utc::now(),
+        })
+    }
+
+    pub fn open(&self) -> Result<ChatDb, DbError> {
+        ChatDb::open(&self.path)
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::connection::{ChatDb, DbError};
+
+/// Where messages are read from. Persisted in the app config so the
+/// selection survives restarts.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DataSource {
+    /// The signed-in user's Messages database (~/Library/Messages/chat.db)
+    #[default]
+    Local,
+    /// Any chat.db file on disk (archived copy, another Mac, test fixture)
+    File { path: PathBuf },
+    /// An unencrypted Finder/iTunes backup folder of an iPhone
+    IosBackup { path: PathBuf },
+}
+
+impl DataSource {
+    /// Path of the file that gates access to this source. For backups this
+    /// is the Manifest.db, since sms.db lives under a hashed name.
+    pub fn db_path(&self) -> PathBuf {
+        match self {
+            DataSource::Local => ChatDb::db_path(),
+            DataSource::File { path } => path.clone(),
+            DataSource::IosBackup { path } => path.join("Manifest.db"),
+        }
+    }
+
+    /// Whether Messages may still be writing to this source. Backups never
+    /// change once written.
+    pub fn is_live(&self) -> bool {
+        !matches!(self, DataSource::IosBackup { .. })
+    }
+
+    /// Only live databases benefit from snapshotting
+    pub fn supports_snapshots(&self) -> bool {
+        self.is_live()
+    }
+
+    pub fn open(&self) -> Result<ChatDb, DbError> {
+        match self {
+            DataSource::Local => ChatDb::new(),
+            DataSource::File { path } => ChatDb::open(path),
+            DataSource::IosBackup { path } => ChatDb::open_backup(path),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::connection::{ChatDb, DbError};
+use super::history::{ContextDirection, ContextOptions, MessageQuery};
+use super::models::{Attachment, ChatVersion, ConversationPage, Message, MessagePage, Person};
+use super::pool::{DbPool, DbTarget};
+use super::queries::ConversationQuery;
+use super::search::SearchQuery;
+use crate::contacts::ContactBook;
+use crate::index::SearchOptions;
+
+/// Read access to a message archive: conversations, messages, search and
+/// context. `ChatDb` reads Apple's schema; imported data, merged archives
+/// or test data only need to answer the same questions.
+pub trait MessageStore: Send {
+    /// One page of conversations, most recently active first
+    fn get_conversations(&self, query: &ConversationQuery) -> Result<ConversationPage, DbError>;
+
+    /// A page of a chat's messages, newest first
+    fn get_chat_messages(&self, chat_id: i64, query: &MessageQuery)
+        -> Result<MessagePage, DbError>;
+
+    /// The page of messages around `date`, a raw `Cursor` date
+    fn get_chat_messages_around(
+        &self,
+        chat_id: i64,
+        date: i64,
+        limit: i64,
+    ) -> Result<MessagePage, DbError>;
+
+    /// A chat's latest messages, newest first
+    fn get_messages_for_chat(&self, chat_id: i64, limit: i64) -> Result<Vec<Message>, DbError>;
+
+    /// Where a chat is up to, to tell whether what was derived from it is
+    /// stale
+    fn get_chat_version(&self, chat_id: i64) -> Result<ChatVersion, DbError>;
+
+    /// Specific messages in the order of `ids`, skipping unknown ones
+    fn get_messages_by_ids(&self, ids: &[i64]) -> Result<Vec<Message>, DbError>;
+
+    /// An inline reply thread from any message in it, oldest first
+    fn get_reply_thread(&self, message_guid: &str) -> Result<Vec<Message>, DbError>;
+
+    fn get_attachments_for_chat(&self, chat_id: i64) -> Result<Vec<Attachment>, DbError>;
+
+    /// Where an attachment's file is, if it can be found
+    fn attachment_path(&self, filename: &str) -> Result<Option<PathBuf>, DbError>;
+
+    /// Messages from the same chat either side of `message_id`, oldest first
+    fn get_context(
+        &self,
+        message_id: i64,
+        direction: ContextDirection,
+        options: &ContextOptions,
+    ) -> Result<Vec<Message>, DbError>;
+
+    /// Let `search_messages` match text through the app's search index at
+    /// `path`. Stores that search text themselves can ignore it.
+    fn attach_search_index(&self, _path: &Path) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn search_messages(
+        &self,
+        query: &SearchQuery,
+        options: &SearchOptions,
+    ) -> Result<Vec<Message>, DbError>;
+
+    /// For each message, how active I've been in its chat lately
+    fn get_conversation_affinity(&self, message_ids: &[i64]) -> Result<HashMap<i64, f64>, DbError>;
+
+    fn get_people(&self) -> Result<Vec<Person>, DbError>;
+
+    fn get_person(&self, person_id: &str) -> Result<Option<Person>, DbError>;
+
+    /// Messages from all of a person's 1:1 chats, newest first
+    fn get_person_timeline(&self, person: &Person, limit: i64) -> Result<Vec<Message>, DbError>;
+}
+
+impl MessageStore for ChatDb {
+    fn get_conversations(&self, query: &ConversationQuery) -> Result<ConversationPage, DbError> {
+        Ok(ChatDb::get_conversations(self, query)?)
+    }
+
+    fn get_chat_messages(
+        &self,
+        chat_id: i64,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, DbError> {
+        Ok(ChatDb::get_chat_messages(self, chat_id, query)?)
+    }
+
+    fn get_chat_messages_around(
+        &self,
+        chat_id: i64,
+        date: i64,
+        limit: i64,
+    ) -> Result<MessagePage, DbError> {
+        Ok(ChatDb::get_chat_messages_around(
+            self, chat_id, date, limit,
+        )?)
+    }
+
+    fn get_messages_for_chat(&self, chat_id: i64, limit: i64) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::get_messages_for_chat(self, chat_id, limit)?)
+    }
+
+    fn get_chat_version(&self, chat_id: i64) -> Result<ChatVersion, DbError> {
+        Ok(ChatDb::get_chat_version(self, chat_id)?)
+    }
+
+    fn get_messages_by_ids(&self, ids: &[i64]) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::get_messages_by_ids(self, ids)?)
+    }
+
+    fn get_reply_thread(&self, message_guid: &str) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::get_reply_thread(self, message_guid)?)
+    }
+
+    fn get_attachments_for_chat(&self, chat_id: i64) -> Result<Vec<Attachment>, DbError> {
+        Ok(ChatDb::get_attachments_for_chat(self, chat_id)?)
+    }
+
+    fn attachment_path(&self, filename: &str) -> Result<Option<PathBuf>, DbError> {
+        ChatDb::attachment_path(self, filename)
+    }
+
+    fn get_context(
+        &self,
+        message_id: i64,
+        direction: ContextDirection,
+        options: &ContextOptions,
+    ) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::get_context(self, message_id, direction, options)?)
+    }
+
+    fn attach_search_index(&self, path: &Path) -> Result<(), DbError> {
+        Ok(ChatDb::attach_search_index(self, path)?)
+    }
+
+    /// Text terms need the search index attached first
+    fn search_messages(
+        &self,
+        query: &SearchQuery,
+        options: &SearchOptions,
+    ) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::search_messages(self, query, options)?)
+    }
+
+    fn get_conversation_affinity(&self, message_ids: &[i64]) -> Result<HashMap<i64, f64>, DbError> {
+        Ok(ChatDb::get_conversation_affinity(self, message_ids)?)
+    }
+
+    fn get_people(&self) -> Result<Vec<Person>, DbError> {
+        Ok(ChatDb::get_people(self)?)
+    }
+
+    fn get_person(&self, person_id: &str) -> Result<Option<Person>, DbError> {
+        Ok(ChatDb::get_person(self, person_id)?)
+    }
+
+    fn get_person_timeline(&self, person: &Person, limit: i64) -> Result<Vec<Message>, DbError> {
+        Ok(ChatDb::get_person_timeline(self, person, limit)?)
+    }
+}
+
+/// Hands out a `MessageStore` for each call. Stores hold connections that
+/// can't be shared between threads, so callers get one for the length of
+/// `f` rather than a store of their own.
+pub trait StoreBackend: Send + Sync {
+    fn with_store<T>(&self, f: impl FnOnce(&dyn MessageStore) -> T) -> Result<T, DbError>;
+}
+
+/// Serves a chat.db, or a snapshot of it, through the connection pool
+pub struct ChatDbBackend {
+    pub pool: DbPool,
+    pub target: DbTarget,
+    pub contacts: Arc<ContactBook>,
+}
+
+impl StoreBackend for ChatDbBackend {
+    fn with_store<T>(&self, f: impl FnOnce(&dyn MessageStore) -> T) -> Result<T, DbError> {
+        let mut db = self.pool.get(&self.target)?;
+        db.contacts = Arc::clone(&self.contacts);
+        Ok(f(&*db))
+    }
+}
@@ -0,0 +1,676 @@
+//! `ChatDb` against a synthetic database built with `fixture`
+
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Local, Utc};
+
+use super::connection::ChatDb;
+use super::fixture::{AttachmentSpec, Fixture, FixtureMessage, MessageSpec};
+use super::models::{Cursor, ReactionKind};
+use super::{
+    parse_query, ChatDbBackend, ContextDirection, ContextOptions, ConversationQuery, DataSource,
+    DbPool, DbTarget, MessageQuery, StoreBackend,
+};
+use crate::contacts::ContactBook;
+use crate::index::{SearchIndex, SearchOptions};
+use crate::utils::datetime_to_mac_timestamp;
+
+struct Sample {
+    direct: i64,
+    family: i64,
+    sms: i64,
+    /// The direct chat's messages, oldest first
+    messages: Vec<FixtureMessage>,
+    tapback: FixtureMessage,
+    sticker: FixtureMessage,
+}
+
+fn t0() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+        .unwrap()
+        .to_utc()
+}
+
+fn at(minutes: i64) -> DateTime<Utc> {
+    t0() + Duration::minutes(minutes)
+}
+
+/// A 1:1 chat covering every kind of message, a named group chat and an
+/// SMS chat with the same person
+fn sample() -> (Fixture, Sample) {
+    let mut fixture = Fixture::new();
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let alice_sms = fixture.handle("+15551234567", "SMS");
+    let sam = fixture.handle("sam@example.com", "iMessage");
+    let direct = fixture.chat(alice);
+    let family = fixture.group(Some("Family"), &[alice, sam]);
+    let sms = fixture.chat(alice_sms);
+
+    fixture.message(
+        sms,
+        MessageSpec {
+            text: "Running late",
+            from: Some(alice_sms),
+            date: t0() - Duration::days(1),
+            legacy_seconds: true,
+            ..MessageSpec::default()
+        },
+    );
+
+    let question = fixture.message(
+        direct,
+        MessageSpec {
+            text: "Dinner tonight?",
+            from: Some(alice),
+            date: at(0),
+            legacy_seconds: true,
+            ..MessageSpec::default()
+        },
+    );
+    let answer = fixture.message(
+        direct,
+        MessageSpec {
+            text: "Sure, where?",
+            date: at(1),
+            attributed_only: true,
+            read: Some(at(2)),
+            ..MessageSpec::default()
+        },
+    );
+    let place = fixture.message(
+        direct,
+        MessageSpec {
+            text: "The usual place",
+            from: Some(alice),
+            date: at(3),
+            edits: vec!["The new place"],
+            ..MessageSpec::default()
+        },
+    );
+    let reply = fixture.message(
+        direct,
+        MessageSpec {
+            text: "7pm works",
+            date: at(4),
+            reply_to: Some(&question.guid),
+            ..MessageSpec::default()
+        },
+    );
+    let tapback = fixture.tapback(direct, &place, 2000, None, at(5));
+    let photo = fixture.message(
+        direct,
+        MessageSpec {
+            text: "\u{FFFC}",
+            from: Some(alice),
+            date: at(6),
+            attachments: vec![AttachmentSpec {
+                filename: "/tmp/Attachments/photo.jpg",
+                mime_type: "image/jpeg",
+                total_bytes: 2048,
+                ..AttachmentSpec::default()
+            }],
+            ..MessageSpec::default()
+        },
+    );
+    let unsent = fixture.message(
+        direct,
+        MessageSpec {
+            text: "oops",
+            date: at(7),
+            unsent: true,
+            ..MessageSpec::default()
+        },
+    );
+
+    let sticker = fixture.message(
+        family,
+        MessageSpec {
+            text: "\u{FFFC}",
+            from: Some(sam),
+            date: at(10),
+            attachments: vec![AttachmentSpec {
+                filename: "/tmp/Attachments/sticker.heic",
+                mime_type: "image/heic",
+                is_sticker: true,
+                ..AttachmentSpec::default()
+            }],
+            ..MessageSpec::default()
+        },
+    );
+    fixture.message(
+        family,
+        MessageSpec {
+            text: "See you all at dinner",
+            from: Some(alice),
+            date: at(11),
+            ..MessageSpec::default()
+        },
+    );
+
+    let sample = Sample {
+        direct,
+        family,
+        sms,
+        messages: vec![question, answer, place, reply, photo, unsent],
+        tapback,
+        sticker,
+    };
+    (fixture, sample)
+}
+
+fn sample_db() -> (ChatDb, Sample) {
+    let (fixture, sample) = sample();
+    let mut db = fixture.into_db().unwrap();
+    let mut contacts = ContactBook::default();
+    contacts.insert("alice", "+15551234567", "Alice");
+    db.contacts = Arc::new(contacts);
+    (db, sample)
+}
+
+fn ids<'a>(messages: impl IntoIterator<Item = &'a super::Message>) -> Vec<i64> {
+    messages.into_iter().map(|m| m.id).collect()
+}
+
+#[test]
+fn test_conversations() {
+    let (db, sample) = sample_db();
+
+    let page = db.get_conversations(&ConversationQuery::default()).unwrap();
+    let chats: Vec<i64> = page.conversations.iter().map(|c| c.chat.id).collect();
+    assert_eq!(chats, [sample.family, sample.direct, sample.sms]);
+    let family = &page.conversations[0];
+    assert!(family.chat.is_group);
+    assert_eq!(family.chat.display_name.as_deref(), Some("Family"));
+    assert_eq!(family.participants.len(), 2);
+    assert_eq!(
+        family.last_message.as_ref().and_then(|m| m.text.as_deref()),
+        Some("See you all at dinner")
+    );
+    assert_eq!(page.conversations[1].message_count, 6);
+
+    let first = db
+        .get_conversations(&ConversationQuery {
+            limit: 1,
+            ..ConversationQuery::default()
+        })
+        .unwrap();
+    let rest = db
+        .get_conversations(&ConversationQuery {
+            cursor: first.next_cursor,
+            ..ConversationQuery::default()
+        })
+        .unwrap();
+    assert_eq!(rest.conversations.len(), 2);
+    assert_eq!(rest.conversations[0].chat.id, sample.direct);
+
+    let filtered = db
+        .get_conversations(&ConversationQuery {
+            filter: Some("sam@".to_string()),
+            ..ConversationQuery::default()
+        })
+        .unwrap();
+    assert_eq!(filtered.conversations.len(), 1);
+    assert_eq!(filtered.conversations[0].chat.id, sample.family);
+}
+
+#[test]
+fn test_message_pages_and_context() {
+    let (db, sample) = sample_db();
+    let m = |i: usize| sample.messages[i].id;
+
+    let newest = db
+        .get_chat_messages(
+            sample.direct,
+            &MessageQuery {
+                limit: 4,
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(ids(&newest.messages), [m(5), m(4), m(3), m(2)]);
+    assert_eq!(newest.newer_cursor, None);
+
+    let older = db
+        .get_chat_messages(
+            sample.direct,
+            &MessageQuery {
+                before: newest.older_cursor,
+                limit: 4,
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(ids(&older.messages), [m(1), m(0)]);
+    assert_eq!(older.older_cursor, None);
+
+    let newer = db
+        .get_chat_messages(
+            sample.direct,
+            &MessageQuery {
+                after: Some(Cursor {
+                    date: older.messages[0].date_raw,
+                    rowid: m(1),
+                }),
+                limit: 2,
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(ids(&newer.messages), [m(3), m(2)]);
+
+    let place = &sample.messages[2];
+    let date = db.get_messages_by_ids(&[place.id]).unwrap()[0].date_raw;
+    let around = db.get_chat_messages_around(sample.direct, date, 4).unwrap();
+    assert_eq!(ids(&around.messages), [m(4), m(3), m(2), m(1)]);
+
+    let options = ContextOptions::default();
+    let before = db
+        .get_context(place.id, ContextDirection::Before, &options)
+        .unwrap();
+    let after = db
+        .get_context(place.id, ContextDirection::After, &options)
+        .unwrap();
+    assert_eq!(ids(&before), [m(0), m(1)]);
+    assert_eq!(ids(&after), [m(3), m(4)]);
+
+    assert_eq!(
+        db.get_messages_for_chat(sample.direct, 100).unwrap().len(),
+        6
+    );
+}
+
+#[test]
+fn test_message_decoding() {
+    let (db, sample) = sample_db();
+    let mut ids: Vec<i64> = sample.messages.iter().map(|m| m.id).collect();
+    ids.push(sample.tapback.id);
+    let messages = db.get_messages_by_ids(&ids).unwrap();
+    assert_eq!(messages.len(), 6, "tapbacks are folded, not listed");
+    let [question, answer, place, reply, photo, unsent] = &messages[..] else {
+        unreachable!();
+    };
+
+    // Seconds and nanoseconds land on the same clock
+    assert_eq!(question.date, at(0));
+    assert_eq!(answer.date, at(1));
+    assert_eq!(question.contact_name.as_deref(), Some("Alice"));
+
+    assert_eq!(answer.text.as_deref(), Some("Sure, where?"));
+    assert_eq!(answer.runs.len(), 1);
+    assert_eq!(answer.date_read, Some(at(2)));
+
+    assert_eq!(place.date_edited, Some(at(4)));
+    let versions: Vec<_> = place
+        .edit_history
+        .iter()
+        .map(|v| v.text.as_deref())
+        .collect();
+    assert_eq!(versions, [Some("The new place"), Some("The usual place")]);
+    assert_eq!(place.reactions.len(), 1);
+    assert_eq!(place.reactions[0].kind, ReactionKind::Loved);
+    assert!(place.reactions[0].is_from_me);
+
+    assert_eq!(
+        reply.reply_to.as_ref().map(|r| r.guid.as_str()),
+        Some(sample.messages[0].guid.as_str())
+    );
+    let thread = db.get_reply_thread(&reply.guid).unwrap();
+    assert_eq!(
+        thread.iter().map(|m| m.id).collect::<Vec<_>>(),
+        [question.id, reply.id]
+    );
+
+    let attachment = &photo.attachments[0];
+    assert_eq!(
+        attachment.filename.as_deref(),
+        Some("/tmp/Attachments/photo.jpg")
+    );
+    assert_eq!(attachment.uti.as_deref(), Some("public.jpeg"));
+    assert_eq!(attachment.transfer_name.as_deref(), Some("photo.jpg"));
+    assert_eq!(attachment.total_bytes, 2048);
+
+    assert!(unsent.is_unsent);
+    assert_eq!(unsent.text, None);
+
+    let attachments = db.get_attachments_for_chat(sample.family).unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].message_id, sample.sticker.id);
+    assert!(attachments[0].is_sticker);
+}
+
+#[test]
+fn test_partial_unsend_keeps_the_rest() {
+    let mut fixture = Fixture::new();
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let chat = fixture.chat(alice);
+    let message = fixture.message(
+        chat,
+        MessageSpec {
+            text: "Still here",
+            from: Some(alice),
+            date: at(0),
+            unsent_parts: vec![1],
+            ..MessageSpec::default()
+        },
+    );
+    let db = fixture.into_db().unwrap();
+
+    let messages = db.get_messages_by_ids(&[message.id]).unwrap();
+    assert!(!messages[0].is_unsent);
+    assert_eq!(messages[0].retracted_parts, [1]);
+    assert_eq!(messages[0].text.as_deref(), Some("Still here"));
+    let texts = db.get_message_texts(0, 10).unwrap();
+    assert_eq!(texts[0].text, "Still here");
+}
+
+#[test]
+fn test_chat_version_follows_tapbacks_and_edits() {
+    let (db, sample) = sample_db();
+    let version = db.get_chat_version(sample.direct).unwrap();
+    assert_eq!(version.last_rowid, sample.messages[5].id);
+    assert!(version.last_changed > 0);
+    assert_eq!(db.get_chat_version(sample.sms).unwrap().last_changed, 0);
+
+    db.conn
+        .execute(
+            "UPDATE message SET date_edited = ?2 WHERE ROWID = ?1",
+            [sample.messages[0].id, version.last_changed + 1],
+        )
+        .unwrap();
+    let edited = db.get_chat_version(sample.direct).unwrap();
+    assert_eq!(edited.last_rowid, version.last_rowid);
+    assert_ne!(edited, version);
+}
+
+#[test]
+fn test_damaged_attributed_body_keeps_text() {
+    let mut fixture = Fixture::new();
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let chat = fixture.chat(alice);
+    let message = fixture.message(
+        chat,
+        MessageSpec {
+            text: "Cut short",
+            from: Some(alice),
+            date: at(0),
+            attributed_only: true,
+            ..MessageSpec::default()
+        },
+    );
+    let path = std::env::temp_dir().join(format!("backchannel-damaged-{}.db", std::process::id()));
+    fixture.save(&path).unwrap();
+    // Lose the attribute runs, leaving only the string
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute(
+            "UPDATE message SET attributedBody = substr(attributedBody, 1, length(attributedBody) - 20)",
+            [],
+        )
+        .unwrap();
+
+    let db = ChatDb::open(&path).unwrap();
+    let messages = db.get_messages_by_ids(&[message.id]).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(messages[0].text.as_deref(), Some("Cut short"));
+    assert!(messages[0].runs.is_empty());
+}
+
+#[test]
+fn test_index_feeds_and_new_messages() {
+    let (db, sample) = sample_db();
+
+    let texts = db.get_message_texts(0, 100).unwrap();
+    assert_eq!(texts.len(), 9);
+    assert!(texts.iter().all(|t| t.id != sample.tapback.id));
+    let text = |id: i64| texts.iter().find(|t| t.id == id).unwrap();
+    assert_eq!(text(sample.messages[1].id).text, "Sure, where?");
+    assert!(text(sample.messages[2].id)
+        .edits
+        .as_deref()
+        .is_some_and(|edits| edits.contains("The new place")));
+    assert_eq!(text(sample.messages[5].id).text, "");
+
+    let max_rowid = db.max_message_rowid().unwrap();
+    let changed = db.get_changed_message_texts(max_rowid, 0).unwrap();
+    let changed: Vec<i64> = changed.iter().map(|t| t.id).collect();
+    assert_eq!(changed, [sample.messages[2].id, sample.messages[5].id]);
+
+    let (new, last_rowid) = db.get_new_messages(sample.messages[4].id, 10).unwrap();
+    assert_eq!(last_rowid, max_rowid);
+    let chats: Vec<Option<i64>> = new.iter().map(|n| n.chat_id).collect();
+    assert_eq!(
+        chats,
+        [
+            Some(sample.direct),
+            Some(sample.family),
+            Some(sample.family)
+        ]
+    );
+}
+
+#[test]
+fn test_people() {
+    let (db, sample) = sample_db();
+
+    let people = db.get_people().unwrap();
+    assert_eq!(people.len(), 2);
+    let alice = &people[0];
+    assert_eq!(alice.name.as_deref(), Some("Alice"));
+    assert_eq!(alice.handles.len(), 2, "SMS and iMessage handles merge");
+    assert_eq!(alice.chat_ids, [sample.direct, sample.sms]);
+    assert_eq!(
+        db.get_person(&alice.id).unwrap().map(|p| p.chat_ids),
+        Some(alice.chat_ids.clone())
+    );
+
+    let timeline = db.get_person_timeline(alice, 100).unwrap();
+    assert_eq!(timeline.len(), 7);
+    assert_eq!(timeline[0].id, sample.messages[5].id);
+    assert_eq!(timeline[6].text.as_deref(), Some("Running late"));
+}
+
+#[test]
+fn test_search_and_diagnostics() {
+    let (db, sample) = sample_db();
+    let search = |query: &str| {
+        let query = parse_query(query).unwrap();
+        let results = db
+            .search_messages(&query, &SearchOptions::default())
+            .unwrap();
+        ids(&results)
+    };
+
+    assert_eq!(
+        search("has:attachment"),
+        [sample.sticker.id, sample.messages[4].id]
+    );
+    assert_eq!(search("from:me -has:attachment").len(), 3);
+    assert_eq!(search("in:Family from:sam"), [sample.sticker.id]);
+
+    // Text goes through the app's index
+    let path = std::env::temp_dir().join(format!("backchannel-fixture-{}.db", std::process::id()));
+    let mut index = SearchIndex::open_at(&path).unwrap();
+    assert!(!index.is_built("fixture").unwrap());
+    index.sync("fixture", &db).unwrap();
+    assert!(index.is_built("fixture").unwrap());
+    assert!(!index.is_built("other").unwrap());
+    db.attach_search_index(index.path()).unwrap();
+    assert_eq!(search("dinner").len(), 2);
+    assert_eq!(search("place"), [sample.messages[2].id]);
+    drop(index);
+    let _ = std::fs::remove_file(&path);
+
+    let found = db
+        .execute_search_query("SELECT ROWID FROM message WHERE text LIKE '%works%'")
+        .unwrap();
+    assert_eq!(ids(&found), [sample.messages[3].id]);
+    assert!(db.execute_search_query("DELETE FROM message").is_err());
+
+    let affinity = db
+        .get_conversation_affinity(&[sample.messages[0].id, sample.sticker.id])
+        .unwrap();
+    assert_eq!(affinity.get(&sample.sticker.id), Some(&0.0));
+
+    let diagnostics = db.diagnostics().unwrap();
+    assert!(diagnostics.missing_columns.is_empty());
+    assert!(diagnostics.capabilities.edits && diagnostics.capabilities.stickers);
+    assert_eq!(diagnostics.message_count, 10);
+}
+
+#[test]
+fn test_newer_dates_stored_in_seconds() {
+    let now = Utc::now();
+    let days_ago = |days: i64| now - Duration::days(days);
+    let mut fixture = Fixture::new();
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let sam = fixture.handle("sam@example.com", "iMessage");
+    let chat = fixture.chat(alice);
+    let other = fixture.chat(sam);
+    let mut message = |chat_id, from, days, legacy_seconds| {
+        fixture.message(
+            chat_id,
+            MessageSpec {
+                text: "Hi",
+                from,
+                date: days_ago(days),
+                legacy_seconds,
+                ..MessageSpec::default()
+            },
+        )
+    };
+    let oldest = message(chat, Some(alice), 30, false);
+    // Migrated rows can keep seconds next to newer nanosecond ones
+    let newest = message(chat, None, 10, true);
+    let middle = message(chat, Some(alice), 20, false);
+    message(other, Some(sam), 15, false);
+    let db = fixture.into_db().unwrap();
+
+    let page = db
+        .get_chat_messages(chat, &MessageQuery::default())
+        .unwrap();
+    assert_eq!(ids(&page.messages), [newest.id, middle.id, oldest.id]);
+    let first = db
+        .get_chat_messages(
+            chat,
+            &MessageQuery {
+                limit: 1,
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    let rest = db
+        .get_chat_messages(
+            chat,
+            &MessageQuery {
+                before: first.older_cursor,
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(ids(&rest.messages), [middle.id, oldest.id]);
+
+    let conversations = db.get_conversations(&ConversationQuery::default()).unwrap();
+    assert_eq!(conversations.conversations[0].chat.id, chat);
+
+    let window = db
+        .get_chat_messages(
+            chat,
+            &MessageQuery {
+                start: Some(datetime_to_mac_timestamp(days_ago(12))),
+                ..MessageQuery::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(ids(&window.messages), [newest.id]);
+    let after = days_ago(12).with_timezone(&Local).format("%Y-%m-%d");
+    let query = parse_query(&format!("after:{after}")).unwrap();
+    let found = db
+        .search_messages(&query, &SearchOptions::default())
+        .unwrap();
+    assert_eq!(ids(&found), [newest.id]);
+
+    let affinity = db.get_conversation_affinity(&[oldest.id]).unwrap();
+    assert_eq!(affinity.get(&oldest.id), Some(&1.0));
+}
+
+#[test]
+fn test_link_and_chat_name_filters() {
+    let mut fixture = Fixture::new();
+    let alice = fixture.handle("+15551234567", "iMessage");
+    let literal = fixture.group(Some("100% Fam_ly"), &[alice]);
+    let similar = fixture.group(Some("100 Family"), &[alice]);
+    let link = fixture.message(
+        literal,
+        MessageSpec {
+            text: "Menu is at https://example.com/menu",
+            from: Some(alice),
+            date: at(0),
+            attributed_only: true,
+            ..MessageSpec::default()
+        },
+    );
+    let plain = fixture.message(
+        similar,
+        MessageSpec {
+            text: "No link here",
+            date: at(1),
+            ..MessageSpec::default()
+        },
+    );
+    let db = fixture.into_db().unwrap();
+    let search = |query: &str| {
+        let query = parse_query(query).unwrap();
+        let results = db
+            .search_messages(&query, &SearchOptions::default())
+            .unwrap();
+        ids(&results)
+    };
+
+    assert_eq!(search("in:'100% Fam_ly'"), [link.id]);
+    assert_eq!(search("in:family"), [plain.id]);
+
+    // The link is only in attributedBody, so it's found through the index
+    let path = std::env::temp_dir().join(format!("backchannel-links-{}.db", std::process::id()));
+    let mut index = SearchIndex::open_at(&path).unwrap();
+    index.sync("fixture", &db).unwrap();
+    db.attach_search_index(index.path()).unwrap();
+    assert_eq!(search("has:link"), [link.id]);
+    assert_eq!(search("-has:link"), [plain.id]);
+    drop(index);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_pool_serves_message_store() {
+    let (fixture, sample) = sample();
+    let path = std::env::temp_dir().join(format!("backchannel-store-{}.db", std::process::id()));
+    fixture.save(&path).unwrap();
+
+    let backend = ChatDbBackend {
+        pool: DbPool::new(),
+        target: DbTarget::Source(DataSource::File { path: path.clone() }),
+        contacts: Arc::default(),
+    };
+    let chats: Vec<i64> = backend
+        .with_store(|store| {
+            let page = store
+                .get_conversations(&ConversationQuery::default())
+                .unwrap();
+            page.conversations.iter().map(|c| c.chat.id).collect()
+        })
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(chats, [sample.family, sample.direct, sample.sms]);
+}
+
+/// Write the sample database for a demo or manual testing:
+/// `FIXTURE_OUT=/tmp/chat.db cargo test write_sample_database -- --ignored`
+#[test]
+#[ignore]
+fn write_sample_database() {
+    let out = std::env::var("FIXTURE_OUT").unwrap_or_else(|_| "chat.db".to_string());
+    let (fixture, _) = sample();
+    fixture.save(Path::new(&out)).unwrap();
+    assert!(ChatDb::open(&out).is_ok());
+}
@@ -0,0 +1,493 @@
+//! Decoder for the `streamtyped` format written by `NSArchiver`, which
+//! Messages uses for `message.attributedBody`.
+//!
+//! A stream is a header followed by groups. Each group starts with an
+//! Objective-C type encoding (`@`, `i`, `iI`, `+`, ...) and is followed by
+//! one value per type. Strings, classes and objects are written once and
+//! then referred to by index, so the decoder keeps the same tables the
+//! encoder did.
+
+use std::rc::Rc;
+
+const SIGNATURE: &[u8] = b"streamtyped";
+
+const TAG_INTEGER_2: i8 = -127;
+const TAG_INTEGER_4: i8 = -126;
+const TAG_FLOAT: i8 = -125;
+const TAG_NEW: i8 = -124;
+const TAG_NIL: i8 = -123;
+const TAG_END: i8 = -122;
+/// Integers from here up are table references; everything between
+/// `TAG_INTEGER_2` and this is reserved for tags
+const FIRST_REFERENCE: i64 = -110;
+/// How deeply objects, arrays, structs and superclasses may nest. Real
+/// message bodies stay in single digits; blobs come from other people's
+/// devices, so anything deeper is rejected rather than recursed into.
+const MAX_DEPTH: usize = 64;
+/// Most array elements read from one stream. Byte arrays are bounded by
+/// the data itself, but an element that takes no bytes (an empty struct)
+/// could otherwise be repeated without end.
+const MAX_ARRAY_ELEMENTS: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Int(i64),
+    Float(f64),
+    /// `+` byte strings and `*` C strings
+    String(String),
+    /// `[Nc]` byte arrays, e.g. the contents of an `NSData`
+    Bytes(Vec<u8>),
+    Object(Rc<Object>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub class: String,
+    /// The groups the object wrote while encoding itself
+    pub groups: Vec<Vec<Value>>,
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Float(f) => Some(*f as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Object> {
+        match self {
+            Value::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+impl Object {
+    /// The value of an `NSString`/`NSMutableString`
+    pub fn as_string(&self) -> Option<&str> {
+        match self.groups.first()?.first()? {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of an `NSNumber`, which writes its type as a C string
+    /// and then the number itself
+    pub fn as_number(&self) -> Option<i64> {
+        self.groups.last()?.first()?.as_int()
+    }
+
+    /// The first string anywhere inside this object, for wrappers such as
+    /// `NSURL` that hold an `NSString`
+    pub fn find_string(&self) -> Option<&str> {
+        self.groups.iter().flatten().find_map(|value| match value {
+            Value::String(s) => Some(s.as_str()),
+            Value::Object(object) => object.find_string(),
+            _ => None,
+        })
+    }
+
+    /// Key/value pairs of an `NSDictionary`: a count, then alternating
+    /// key and value objects
+    pub fn dictionary_entries(&self) -> Vec<(&Value, &Value)> {
+        let values: Vec<&Value> = self.groups.iter().skip(1).flatten().collect();
+        values
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Shared {
+    Class(String),
+    CString(String),
+    Object(Rc<Object>),
+    /// An object whose contents are still being read
+    Pending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    NotTypedStream,
+    UnexpectedEnd,
+    BadReference,
+    BadEncoding,
+    TooDeep,
+    TooLarge,
+}
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+/// Decode every top-level group in the stream
+pub fn decode(data: &[u8]) -> Result<Vec<Vec<Value>>> {
+    let mut reader = Reader {
+        data,
+        pos: 0,
+        strings: Vec::new(),
+        objects: Vec::new(),
+        depth: 0,
+        array_elements: 0,
+    };
+    reader.read_header()?;
+
+    let mut groups = Vec::new();
+    while reader.pos < data.len() {
+        groups.push(reader.read_group()?);
+    }
+    Ok(groups)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Type encodings and class names
+    strings: Vec<String>,
+    /// Classes, C strings and objects, numbered in the order they appear
+    objects: Vec<Shared>,
+    /// How many nested values are being read
+    depth: usize,
+    /// Array elements read so far, see `MAX_ARRAY_ELEMENTS`
+    array_elements: usize,
+}
+
+impl Reader<'_> {
+    fn read_header(&mut self) -> Result<()> {
+        let _version = self.read_byte()?;
+        let signature = self
+            .read_unshared_bytes()
+            .map_err(|_| DecodeError::NotTypedStream)?;
+        if signature != SIGNATURE {
+            return Err(DecodeError::NotTypedStream);
+        }
+        let _system_version = self.read_int()?;
+        Ok(())
+    }
+
+    /// Run `read` one level deeper, failing past `MAX_DEPTH`
+    fn nested<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_exact(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_head(&mut self) -> Result<i8> {
+        self.read_byte().map(|b| b as i8)
+    }
+
+    fn read_int(&mut self) -> Result<i64> {
+        let head = self.read_head()?;
+        self.read_int_with_head(head)
+    }
+
+    fn read_int_with_head(&mut self, head: i8) -> Result<i64> {
+        match head {
+            TAG_INTEGER_2 => {
+                let bytes = self.read_exact(2)?;
+                Ok(i16::from_le_bytes([bytes[0], bytes[1]]) as i64)
+            }
+            TAG_INTEGER_4 => {
+                let bytes = self.read_exact(4)?;
+                Ok(i32::from_le_bytes(bytes.try_into().unwrap()) as i64)
+            }
+            _ => Ok(head as i64),
+        }
+    }
+
+    fn read_float(&mut self, double: bool) -> Result<f64> {
+        let head = self.read_head()?;
+        if head != TAG_FLOAT {
+            return self.read_int_with_head(head).map(|i| i as f64);
+        }
+        if double {
+            let bytes = self.read_exact(8)?;
+            Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+        } else {
+            let bytes = self.read_exact(4)?;
+            Ok(f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+        }
+    }
+
+    fn read_reference(&mut self, head: i8) -> Result<usize> {
+        let encoded = self.read_int_with_head(head)?;
+        usize::try_from(encoded - FIRST_REFERENCE).map_err(|_| DecodeError::BadReference)
+    }
+
+    /// A length-prefixed run of bytes
+    fn read_unshared_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = usize::try_from(self.read_int()?).map_err(|_| DecodeError::BadEncoding)?;
+        self.read_exact(len).map(<[u8]>::to_vec)
+    }
+
+    /// A string written once and referenced by index afterwards
+    fn read_shared_string(&mut self) -> Result<Option<String>> {
+        match self.read_head()? {
+            TAG_NIL => Ok(None),
+            TAG_NEW => {
+                let bytes = self.read_unshared_bytes()?;
+                let string = String::from_utf8_lossy(&bytes).into_owned();
+                self.strings.push(string.clone());
+                Ok(Some(string))
+            }
+            head => {
+                let index = self.read_reference(head)?;
+                self.strings
+                    .get(index)
+                    .cloned()
+                    .map(Some)
+                    .ok_or(DecodeError::BadReference)
+            }
+        }
+    }
+
+    fn read_group(&mut self) -> Result<Vec<Value>> {
+        let encoding = self.read_shared_string()?.ok_or(DecodeError::BadEncoding)?;
+        let mut values = Vec::new();
+        let mut types = encoding.as_bytes();
+        while !types.is_empty() {
+            types = self.read_value(types, &mut values)?;
+        }
+        Ok(values)
+    }
+
+    /// Read the value for the first type in `types`, returning the rest
+    fn read_value<'t>(&mut self, types: &'t [u8], out: &mut Vec<Value>) -> Result<&'t [u8]> {
+        let (&kind, rest) = types.split_first().ok_or(DecodeError::BadEncoding)?;
+        let value = match kind {
+            b'@' | b'#' => self.nested(Self::read_object)?,
+            b'+' => {
+                let bytes = self.read_unshared_bytes()?;
+                Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            b'*' => self.read_c_string()?,
+            b'c' | b'C' | b's' | b'S' | b'i' | b'I' | b'l' | b'L' | b'q' | b'Q' | b'B' => {
+                Value::Int(self.read_int()?)
+            }
+            b'f' => Value::Float(self.read_float(false)?),
+            b'd' => Value::Float(self.read_float(true)?),
+            b'[' => return self.nested(|reader| reader.read_array(rest, out)),
+            b'{' => return self.nested(|reader| reader.read_struct(rest, out)),
+            _ => return Err(DecodeError::BadEncoding),
+        };
+        out.push(value);
+        Ok(rest)
+    }
+
+    /// `[12c]`: a fixed number of elements of one type. Byte arrays are
+    /// written raw rather than as individual integers.
+    fn read_array<'t>(&mut self, types: &'t [u8], out: &mut Vec<Value>) -> Result<&'t [u8]> {
+        let digits = types.iter().take_while(|b| b.is_ascii_digit()).count();
+        let count: usize = std::str::from_utf8(&types[..digits])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or(DecodeError::BadEncoding)?;
+        let element = &types[digits..];
+        match element {
+            [b'c' | b'C', b']', rest @ ..] => {
+                out.push(Value::Bytes(self.read_exact(count)?.to_vec()));
+                Ok(rest)
+            }
+            _ if count == 0 => {
+                let end = element
+                    .iter()
+                    .position(|&b| b == b']')
+                    .ok_or(DecodeError::BadEncoding)?;
+                Ok(&element[end + 1..])
+            }
+            _ => {
+                self.array_elements = self
+                    .array_elements
+                    .checked_add(count)
+                    .filter(|&total| total <= MAX_ARRAY_ELEMENTS)
+                    .ok_or(DecodeError::TooLarge)?;
+                let mut rest = element;
+                for _ in 0..count {
+                    rest = self.read_value(element, out)?;
+                }
+                match rest {
+                    [b']', rest @ ..] => Ok(rest),
+                    _ => Err(DecodeError::BadEncoding),
+                }
+            }
+        }
+    }
+
+    /// `{_NSRange=QQ}`: the fields are written one after another
+    fn read_struct<'t>(&mut self, types: &'t [u8], out: &mut Vec<Value>) -> Result<&'t [u8]> {
+        let name_end = types
+            .iter()
+            .position(|&b| b == b'=')
+            .ok_or(DecodeError::BadEncoding)?;
+        let mut rest = &types[name_end + 1..];
+        loop {
+            match rest {
+                [b'}', after @ ..] => return Ok(after),
+                [] => return Err(DecodeError::BadEncoding),
+                _ => rest = self.read_value(rest, out)?,
+            }
+        }
+    }
+
+    fn read_c_string(&mut self) -> Result<Value> {
+        match self.read_head()? {
+            TAG_NIL => Ok(Value::Nil),
+            TAG_NEW => {
+                let string = self.read_shared_string()?.ok_or(DecodeError::BadEncoding)?;
+                self.objects.push(Shared::CString(string.clone()));
+                Ok(Value::String(string))
+            }
+            head => match self.lookup(head)? {
+                Shared::CString(string) => Ok(Value::String(string)),
+                _ => Err(DecodeError::BadReference),
+            },
+        }
+    }
+
+    /// Read a class and its superclasses, returning the class name. The
+    /// chain ends at nil or at a class that was already written.
+    fn read_class(&mut self) -> Result<Option<String>> {
+        match self.read_head()? {
+            TAG_NIL => Ok(None),
+            TAG_NEW => {
+                let name = self.read_shared_string()?.ok_or(DecodeError::BadEncoding)?;
+                let _version = self.read_int()?;
+                self.objects.push(Shared::Class(name.clone()));
+                self.nested(Self::read_class)?;
+                Ok(Some(name))
+            }
+            head => match self.lookup(head)? {
+                Shared::Class(name) => Ok(Some(name)),
+                _ => Err(DecodeError::BadReference),
+            },
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Value> {
+        match self.read_head()? {
+            TAG_NIL => Ok(Value::Nil),
+            TAG_NEW => {
+                let class = self.read_class()?.ok_or(DecodeError::BadEncoding)?;
+                let index = self.objects.len();
+                self.objects.push(Shared::Pending);
+
+                let mut groups = Vec::new();
+                loop {
+                    if self.data.get(self.pos).map(|&b| b as i8) == Some(TAG_END) {
+                        self.pos += 1;
+                        break;
+                    }
+                    groups.push(self.read_group()?);
+                }
+
+                let object = Rc::new(Object { class, groups });
+                self.objects[index] = Shared::Object(Rc::clone(&object));
+                Ok(Value::Object(object))
+            }
+            head => match self.lookup(head)? {
+                Shared::Object(object) => Ok(Value::Object(object)),
+                Shared::Class(class) => Ok(Value::Object(Rc::new(Object {
+                    class,
+                    groups: Vec::new(),
+                }))),
+                Shared::CString(string) => Ok(Value::String(string)),
+                Shared::Pending => Ok(Value::Nil),
+            },
+        }
+    }
+
+    fn lookup(&mut self, head: i8) -> Result<Shared> {
+        let index = self.read_reference(head)?;
+        self.objects
+            .get(index)
+            .cloned()
+            .ok_or(DecodeError::BadReference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream of `depth` objects of class `A`, each holding the next
+    fn nested_objects(depth: usize) -> Vec<u8> {
+        let mut data = b"\x04\x0bstreamtyped\x81\xe8\x03\x84\x01@".to_vec();
+        // The first object writes its class and the `@` encoding is already
+        // shared, so every later one is a new object of a referenced class
+        data.extend_from_slice(b"\x84\x84\x84\x01A\x00\x85");
+        for _ in 1..depth {
+            data.extend_from_slice(b"\x92\x84\x92");
+        }
+        data.extend_from_slice(b"\x92\x85");
+        data.extend(std::iter::repeat_n(0x86, depth));
+        data
+    }
+
+    fn depth(value: &Value) -> usize {
+        match value.as_object() {
+            Some(object) => 1 + object.groups.iter().flatten().map(depth).max().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// A stream with one group of the given type encoding and no values
+    fn encoding_only(encoding: &[u8]) -> Vec<u8> {
+        let mut data = b"\x04\x0bstreamtyped\x81\xe8\x03\x84".to_vec();
+        data.push(encoding.len() as u8);
+        data.extend_from_slice(encoding);
+        data
+    }
+
+    #[test]
+    fn test_hostile_arrays() {
+        // No element type
+        assert_eq!(decode(&encoding_only(b"[3")), Err(DecodeError::BadEncoding));
+        // Elements that take no bytes, repeated far past any real message
+        assert_eq!(
+            decode(&encoding_only(b"[1000000000{a=}]")),
+            Err(DecodeError::TooLarge)
+        );
+        assert_eq!(decode(&encoding_only(b"[3{a=}]")), Ok(vec![Vec::new()]));
+    }
+
+    #[test]
+    fn test_nested_objects() {
+        let groups = decode(&nested_objects(10)).unwrap();
+        assert_eq!(depth(&groups[0][0]), 10);
+
+        assert_eq!(
+            decode(&nested_objects(MAX_DEPTH + 1)),
+            Err(DecodeError::TooDeep)
+        );
+        // Deep enough to overflow the stack without the limit
+        assert_eq!(
+            decode(&nested_objects(1_000_000)),
+            Err(DecodeError::TooDeep)
+        );
+    }
+}
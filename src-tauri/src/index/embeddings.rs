@@ -0,0 +1,546 @@
+use std::collections::BTreeMap;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use super::{meta_i64, set_meta, IndexError, SearchIndex};
+
+/// Messages per chunk. Single texts are too short to embed well, so runs
+/// of a chat's messages are embedded together. A chat's latest chunk stays
+/// open until it's full, so messages that arrive one at a time still end
+/// up in full chunks.
+const CHUNK_MESSAGES: usize = 8;
+const CHUNK_CHARS: usize = 1000;
+
+/// Meta key bumped whenever chunks are removed, so other connections
+/// reload the vectors they hold
+pub(super) const CHUNK_GENERATION: &str = "chunk_generation";
+
+pub(super) const EMBEDDING_SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS embedding_chunk (
+        id INTEGER PRIMARY KEY,
+        chat_id INTEGER NOT NULL,
+        message_ids TEXT NOT NULL,
+        text TEXT NOT NULL,
+        model TEXT,
+        vector BLOB,
+        is_open INTEGER NOT NULL DEFAULT 0
+    );
+"#;
+
+/// Bring an `embedding_chunk` from an older version up to `EMBEDDING_SCHEMA`
+pub(super) fn upgrade_embedding_schema(conn: &Connection) -> Result<(), IndexError> {
+    let has_open: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('embedding_chunk') WHERE name = 'is_open'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_open {
+        conn.execute(
+            "ALTER TABLE embedding_chunk ADD COLUMN is_open INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// A chunk still waiting for a vector
+#[derive(Debug, Clone)]
+pub struct PendingChunk {
+    pub id: i64,
+    pub text: String,
+}
+
+/// A chunk near the query; `score` is the cosine similarity
+#[derive(Debug, Clone)]
+pub struct ChunkHit {
+    /// In chronological order
+    pub message_ids: Vec<i64>,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EmbeddingStatus {
+    pub model: String,
+    pub embedded: i64,
+    pub pending: i64,
+}
+
+/// The current model's vectors, loaded once for nearest-neighbour scans
+pub(super) struct VectorCache {
+    model: String,
+    generation: i64,
+    vectors: Vec<(i64, Vec<f32>)>,
+}
+
+/// A chunk being added to; `id` is set once it's been written
+#[derive(Default)]
+struct OpenChunk {
+    id: Option<i64>,
+    message_ids: Vec<i64>,
+    text: String,
+    changed: bool,
+}
+
+impl OpenChunk {
+    fn push(&mut self, message_id: i64, text: &str) {
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        self.text.push_str(text);
+        self.message_ids.push(message_id);
+        self.changed = true;
+    }
+
+    fn is_full(&self) -> bool {
+        self.message_ids.len() >= CHUNK_MESSAGES || self.text.len() >= CHUNK_CHARS
+    }
+}
+
+impl SearchIndex {
+    /// Add messages indexed since the last call to their chats' open
+    /// chunks, closing each one that fills up. Returns how many chunks were
+    /// written; any that changed need embedding again.
+    pub(super) fn chunk_new_messages(&mut self) -> Result<usize, IndexError> {
+        let after = meta_i64(&self.conn, "last_chunked_rowid")?;
+        let tx = self.conn.transaction()?;
+        let mut last = after;
+        let mut count = 0;
+        {
+            let mut open: BTreeMap<i64, OpenChunk> = BTreeMap::new();
+            let mut select_open = tx.prepare(
+                "SELECT id, chat_id, message_ids, text FROM embedding_chunk WHERE is_open = 1",
+            )?;
+            let rows = select_open.query_map([], |row| {
+                let ids: String = row.get(2)?;
+                Ok((
+                    row.get::<_, i64>(1)?,
+                    OpenChunk {
+                        id: Some(row.get(0)?),
+                        message_ids: serde_json::from_str(&ids).unwrap_or_default(),
+                        text: row.get(3)?,
+                        changed: false,
+                    },
+                ))
+            })?;
+            for row in rows {
+                let (chat_id, chunk) = row?;
+                open.insert(chat_id, chunk);
+            }
+
+            let mut select = tx.prepare(
+                "SELECT rowid, chat_id, text FROM message_fts WHERE rowid > ?1 ORDER BY rowid",
+            )?;
+            let mut insert = tx.prepare(
+                "INSERT INTO embedding_chunk (chat_id, message_ids, text, is_open)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut update = tx.prepare(
+                "UPDATE embedding_chunk
+                 SET message_ids = ?2, text = ?3, is_open = ?4, model = NULL, vector = NULL
+                 WHERE id = ?1",
+            )?;
+            let mut write = |chat_id: i64, chunk: OpenChunk| -> Result<(), IndexError> {
+                let ids = serde_json::to_string(&chunk.message_ids).unwrap_or_default();
+                let is_open = !chunk.is_full();
+                match chunk.id {
+                    Some(id) => update.execute(params![id, ids, chunk.text, is_open])?,
+                    None => insert.execute(params![chat_id, ids, chunk.text, is_open])?,
+                };
+                count += 1;
+                Ok(())
+            };
+
+            let mut rows = select.query(params![after])?;
+            while let Some(row) = rows.next()? {
+                let id: i64 = row.get(0)?;
+                let chat_id: Option<i64> = row.get(1)?;
+                let text: Option<String> = row.get(2)?;
+                last = id;
+                let (Some(chat_id), Some(text)) = (chat_id, text.filter(|t| !t.trim().is_empty()))
+                else {
+                    continue;
+                };
+
+                let chunk = open.entry(chat_id).or_default();
+                chunk.push(id, &text);
+                if chunk.is_full() {
+                    if let Some(full) = open.remove(&chat_id) {
+                        write(chat_id, full)?;
+                    }
+                }
+            }
+            // Unfinished chunks are kept, and embedded as they are for now
+            for (chat_id, chunk) in open {
+                if chunk.changed {
+                    write(chat_id, chunk)?;
+                }
+            }
+        }
+        set_meta(&tx, "last_chunked_rowid", &last.to_string())?;
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Rebuild chunks holding any of `message_ids` from their current text,
+    /// after edits or unsends, dropping chunks with nothing left
+    pub(super) fn rechunk_messages(&mut self, message_ids: &[i64]) -> Result<usize, IndexError> {
+        if message_ids.is_empty() {
+            return Ok(0);
+        }
+        let ids = serde_json::to_string(message_ids).unwrap_or_else(|_| "[]".to_string());
+        let tx = self.conn.transaction()?;
+        let mut count = 0;
+        {
+            let mut select = tx.prepare(
+                r#"
+                SELECT c.id, c.message_ids
+                FROM embedding_chunk c
+                WHERE EXISTS (
+                    SELECT 1 FROM json_each(c.message_ids) chunk_message
+                    WHERE chunk_message.value IN (SELECT value FROM json_each(?1)))
+                "#,
+            )?;
+            let chunks = select
+                .query_map(params![ids], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut texts = tx.prepare(
+                "SELECT rowid, text FROM message_fts
+                 WHERE rowid IN (SELECT value FROM json_each(?1))
+                 ORDER BY rowid",
+            )?;
+            for (id, message_ids) in chunks {
+                let mut chunk = OpenChunk::default();
+                let mut rows = texts.query(params![message_ids])?;
+                while let Some(row) = rows.next()? {
+                    let text: Option<String> = row.get(1)?;
+                    if let Some(text) = text.filter(|t| !t.trim().is_empty()) {
+                        chunk.push(row.get(0)?, &text);
+                    }
+                }
+
+                if chunk.message_ids.is_empty() {
+                    tx.execute("DELETE FROM embedding_chunk WHERE id = ?1", params![id])?;
+                } else {
+                    let message_ids = serde_json::to_string(&chunk.message_ids).unwrap_or_default();
+                    tx.execute(
+                        "UPDATE embedding_chunk
+                         SET message_ids = ?2, text = ?3, model = NULL, vector = NULL
+                         WHERE id = ?1",
+                        params![id, message_ids, chunk.text],
+                    )?;
+                }
+                count += 1;
+            }
+        }
+        // Vectors loaded elsewhere may still carry the old text
+        if count > 0 {
+            let generation = meta_i64(&tx, CHUNK_GENERATION)?;
+            set_meta(&tx, CHUNK_GENERATION, &(generation + 1).to_string())?;
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Chunks without a vector from `model`, oldest first
+    pub fn pending_chunks(&self, model: &str, limit: i64) -> Result<Vec<PendingChunk>, IndexError> {
+        let mut stmt = self.conn.prepare_cached(
+            r#"
+            SELECT id, text
+            FROM embedding_chunk
+            WHERE vector IS NULL OR model IS NOT ?1
+            ORDER BY id
+            LIMIT ?2
+            "#,
+        )?;
+        let chunks = stmt
+            .query_map(params![model, limit], |row| {
+                Ok(PendingChunk {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(chunks)
+    }
+
+    /// Save vectors for chunks from `pending_chunks`. A chunk whose text
+    /// changed since is left pending, to be embedded again.
+    pub fn store_embeddings(
+        &mut self,
+        model: &str,
+        embeddings: &[(PendingChunk, Vec<f32>)],
+    ) -> Result<(), IndexError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut update = tx.prepare_cached(
+                "UPDATE embedding_chunk SET model = ?1, vector = ?2 WHERE id = ?3 AND text = ?4",
+            )?;
+            for (chunk, vector) in embeddings {
+                update.execute(params![
+                    model,
+                    to_blob(&normalized(vector)),
+                    chunk.id,
+                    chunk.text
+                ])?;
+            }
+        }
+        tx.commit()?;
+        self.vectors = None;
+        Ok(())
+    }
+
+    /// The `limit` chunks closest to `query`, by cosine similarity over
+    /// every chunk `model` has embedded
+    pub fn nearest_chunks(
+        &mut self,
+        model: &str,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<ChunkHit>, IndexError> {
+        let generation = meta_i64(&self.conn, CHUNK_GENERATION)?;
+        if self
+            .vectors
+            .as_ref()
+            .is_none_or(|cache| cache.model != model || cache.generation != generation)
+        {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, vector FROM embedding_chunk WHERE model = ?1 AND vector IS NOT NULL",
+            )?;
+            let vectors = stmt
+                .query_map(params![model], |row| {
+                    Ok((row.get(0)?, from_blob(&row.get::<_, Vec<u8>>(1)?)))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            self.vectors = Some(VectorCache {
+                model: model.to_string(),
+                generation,
+                vectors,
+            });
+        }
+        let Some(cache) = &self.vectors else {
+            return Ok(Vec::new());
+        };
+
+        // Stored vectors are unit length, so the dot product is the cosine
+        let query = normalized(query);
+        let mut scored: Vec<(i64, f32)> = cache
+            .vectors
+            .iter()
+            .filter(|(_, vector)| vector.len() == query.len())
+            .map(|(id, vector)| (*id, dot(vector, &query)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(limit);
+
+        // A sync may have replaced chunks since the cache was loaded
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT message_ids FROM embedding_chunk WHERE id = ?1")?;
+        let mut hits = Vec::new();
+        for (id, score) in scored {
+            let ids: Option<String> = stmt.query_row(params![id], |row| row.get(0)).optional()?;
+            if let Some(ids) = ids {
+                hits.push(ChunkHit {
+                    message_ids: serde_json::from_str(&ids).unwrap_or_default(),
+                    score: f64::from(score),
+                });
+            }
+        }
+        Ok(hits)
+    }
+
+    pub fn embedding_status(&self, model: &str) -> Result<EmbeddingStatus, IndexError> {
+        let (embedded, total): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*) FILTER (WHERE model = ?1 AND vector IS NOT NULL), COUNT(*)
+             FROM embedding_chunk",
+            params![model],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(EmbeddingStatus {
+            model: model.to_string(),
+            embedded,
+            pending: total - embedded,
+        })
+    }
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|v| v / norm).collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::db::MessageText;
+    use crate::llm::Embedder;
+
+    /// Embeds by topic words, standing in for Ollama
+    struct TopicEmbedder;
+
+    impl Embedder for TopicEmbedder {
+        fn model(&self) -> &str {
+            "topics"
+        }
+
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+            let topics = [
+                ["eat", "tacos", "dinner"],
+                ["meeting", "deadline", "report"],
+            ];
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    topics
+                        .iter()
+                        .map(|words| words.iter().filter(|w| text.contains(*w)).count() as f32)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    fn text(id: i64, chat_id: i64, text: &str) -> MessageText {
+        MessageText {
+            id,
+            chat_id: Some(chat_id),
+            text: text.to_string(),
+            edits: None,
+            changed_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_embed_chunks_and_find_nearest() {
+        let mut index = SearchIndex::open_at(Path::new(":memory:")).unwrap();
+        index
+            .write_batch(
+                &[
+                    text(1, 1, "the report deadline moved"),
+                    text(2, 2, "let's do tacos"),
+                    text(3, 1, "meeting at 3"),
+                    text(4, 2, "sounds good"),
+                ],
+                4,
+            )
+            .unwrap();
+        assert_eq!(index.chunk_new_messages().unwrap(), 2);
+
+        let embedder = TopicEmbedder;
+        let pending = index.pending_chunks(embedder.model(), 10).unwrap();
+        let texts: Vec<String> = pending.iter().map(|c| c.text.clone()).collect();
+        let vectors = futures::executor::block_on(embedder.embed(&texts)).unwrap();
+        let embeddings: Vec<(PendingChunk, Vec<f32>)> = pending.into_iter().zip(vectors).collect();
+        index
+            .store_embeddings(embedder.model(), &embeddings)
+            .unwrap();
+        assert_eq!(index.embedding_status("topics").unwrap().pending, 0);
+        assert_eq!(index.embedding_status("other").unwrap().pending, 2);
+
+        let query = futures::executor::block_on(
+            embedder.embed(&["where did we decide to eat".to_string()]),
+        )
+        .unwrap();
+        let hits = index.nearest_chunks("topics", &query[0], 1).unwrap();
+        assert_eq!(hits[0].message_ids, [2, 4]);
+        assert!((hits[0].score - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_messages_arriving_one_at_a_time_share_a_chunk() {
+        let mut index = SearchIndex::open_at(Path::new(":memory:")).unwrap();
+        for id in 1..=3 {
+            index.write_batch(&[text(id, 1, "tacos?")], id).unwrap();
+            assert_eq!(index.chunk_new_messages().unwrap(), 1);
+        }
+        assert_eq!(index.chunk_new_messages().unwrap(), 0);
+
+        let pending = index.pending_chunks("topics", 10).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].text, "tacos?\ntacos?\ntacos?");
+    }
+
+    #[test]
+    fn test_edited_message_rebuilds_its_chunk() {
+        let mut index = SearchIndex::open_at(Path::new(":memory:")).unwrap();
+        index
+            .write_batch(&[text(1, 1, "tacos tonight"), text(2, 1, "sure")], 2)
+            .unwrap();
+        index.chunk_new_messages().unwrap();
+        let stale = index.pending_chunks("topics", 10).unwrap();
+
+        index
+            .write_batch(&[text(1, 1, "the report is due")], 2)
+            .unwrap();
+        assert_eq!(index.rechunk_messages(&[1]).unwrap(), 1);
+        // Embedded before the edit, so it's not kept
+        let stale = stale.into_iter().next().unwrap();
+        index
+            .store_embeddings("topics", &[(stale, vec![1.0, 0.0])])
+            .unwrap();
+
+        let pending = index.pending_chunks("topics", 10).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].text, "the report is due\nsure");
+
+        index
+            .write_batch(&[text(1, 1, ""), text(2, 1, "")], 2)
+            .unwrap();
+        index.rechunk_messages(&[1, 2]).unwrap();
+        assert!(index.pending_chunks("topics", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_vectors_reload_after_another_connection_resets() {
+        let path =
+            std::env::temp_dir().join(format!("backchannel-vectors-{}.db", std::process::id()));
+        let mut searcher = SearchIndex::open_at(&path).unwrap();
+        searcher
+            .write_batch(&[text(1, 1, "tacos tonight")], 1)
+            .unwrap();
+        searcher.chunk_new_messages().unwrap();
+        let pending = searcher.pending_chunks("topics", 10).unwrap();
+        let chunk = pending.into_iter().next().unwrap();
+        searcher
+            .store_embeddings("topics", &[(chunk, vec![1.0, 0.0])])
+            .unwrap();
+        assert_eq!(
+            searcher
+                .nearest_chunks("topics", &[1.0, 0.0], 5)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // A sync switching data sources, through its own connection
+        SearchIndex::open_at(&path).unwrap().reset("other").unwrap();
+        let hits = searcher.nearest_chunks("topics", &[1.0, 0.0], 5).unwrap();
+        drop(searcher);
+        let _ = std::fs::remove_file(&path);
+        assert!(hits.is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+/// Turn what the user typed into an FTS5 query. `"Quoted text"` is a
+/// phrase and a trailing `*` makes a prefix term; everything else is quoted
+/// so FTS5 operators and punctuation in messages can't break the query.
+/// Returns `None` when there is nothing to search for.
+pub fn fts_query(input: &str, match_any: bool) -> Option<String> {
+    let mut terms = Vec::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let (term, is_prefix, remaining) = if let Some(quoted) = rest.strip_prefix('"') {
+            // An unclosed quote runs to the end
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let remaining = quoted.get(end + 1..).unwrap_or("");
+            let is_prefix = remaining.starts_with('*');
+            let remaining = remaining.strip_prefix('*').unwrap_or(remaining);
+            (&quoted[..end], is_prefix, remaining)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let stripped = word.trim_end_matches('*');
+            (stripped, stripped.len() < word.len(), &rest[end..])
+        };
+        rest = remaining;
+
+        terms.extend(fts_term(term, is_prefix));
+    }
+
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.join(if match_any { " OR " } else { " " }))
+}
+
+/// One quoted FTS5 phrase, or `None` if `term` has nothing to match
+pub fn fts_term(term: &str, prefix: bool) -> Option<String> {
+    if !term.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    let phrase = format!("\"{}\"", term.replace('"', "\"\""));
+    Some(if prefix { phrase + "*" } else { phrase })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(
+            fts_query("dinner rest* \"see you\"", false).as_deref(),
+            Some("\"dinner\" \"rest\"* \"see you\"")
+        );
+        assert_eq!(
+            fts_query("NOT (a b) it's", true).as_deref(),
+            Some("\"NOT\" OR \"(a\" OR \"b)\" OR \"it's\"")
+        );
+        assert_eq!(
+            fts_query("\"see yo\"* \"unclosed", false).as_deref(),
+            Some("\"see yo\"* \"unclosed\"")
+        );
+        assert_eq!(fts_query("  * \"\" ?! ", false), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use super::{ChunkHit, SearchHit};
+
+/// Merge keyword and vector results into one score per message, best
+/// first. BM25 scores are scaled by the best one so both sides run 0..1;
+/// `keyword_weight` is the share given to BM25.
+///
+/// A chunk's similarity goes to its messages that also matched the
+/// keywords, or else to its first message, so one chunk doesn't flood the
+/// results with every message in it.
+pub fn hybrid_scores(
+    keyword: &[SearchHit],
+    chunks: &[ChunkHit],
+    keyword_weight: f64,
+) -> Vec<SearchHit> {
+    let best = keyword.iter().map(|hit| hit.score).fold(0.0, f64::max);
+    let keyword_scores: HashMap<i64, f64> = keyword
+        .iter()
+        .map(|hit| {
+            let scaled = if best > 0.0 { hit.score / best } else { 0.0 };
+            (hit.message_id, scaled)
+        })
+        .collect();
+
+    let mut semantic_scores: HashMap<i64, f64> = HashMap::new();
+    for chunk in chunks {
+        let matched: Vec<i64> = chunk
+            .message_ids
+            .iter()
+            .copied()
+            .filter(|id| keyword_scores.contains_key(id))
+            .collect();
+        let targets = if matched.is_empty() {
+            chunk.message_ids.first().copied().into_iter().collect()
+        } else {
+            matched
+        };
+        for id in targets {
+            let score = semantic_scores.entry(id).or_default();
+            *score = score.max(chunk.score.max(0.0));
+        }
+    }
+
+    let mut ids: Vec<i64> = keyword_scores.keys().copied().collect();
+    ids.extend(
+        semantic_scores
+            .keys()
+            .filter(|id| !keyword_scores.contains_key(id)),
+    );
+
+    let mut merged: Vec<SearchHit> = ids
+        .into_iter()
+        .map(|id| {
+            let keyword = keyword_scores.get(&id).copied().unwrap_or(0.0);
+            let semantic = semantic_scores.get(&id).copied().unwrap_or(0.0);
+            SearchHit {
+                message_id: id,
+                score: keyword_weight * keyword + (1.0 - keyword_weight) * semantic,
+            }
+        })
+        .collect();
+    merged.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.message_id.cmp(&b.message_id))
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(message_id: i64, score: f64) -> SearchHit {
+        SearchHit { message_id, score }
+    }
+
+    #[test]
+    fn test_hybrid_scores() {
+        let keyword = [hit(5, 4.0), hit(9, 2.0)];
+        let chunks = [
+            ChunkHit {
+                message_ids: vec![1, 2, 3],
+                score: 0.9,
+            },
+            ChunkHit {
+                message_ids: vec![8, 9],
+                score: 0.6,
+            },
+        ];
+
+        let merged = hybrid_scores(&keyword, &chunks, 0.5);
+        let ids: Vec<i64> = merged.iter().map(|hit| hit.message_id).collect();
+        // 9: (0.5 + 0.6) / 2, 5: 1.0 / 2, 1: 0.9 / 2 for the unmatched chunk
+        assert_eq!(ids, [9, 5, 1]);
+        assert!((merged[0].score - 0.55).abs() < 1e-9);
+
+        let semantic_only = hybrid_scores(&[], &chunks, 0.0);
+        assert_eq!(semantic_only[0].message_id, 1);
+        assert!((semantic_only[0].score - 0.9).abs() < 1e-9);
+    }
+}
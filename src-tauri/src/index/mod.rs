@@ -0,0 +1,333 @@
+mod embeddings;
+mod fts_query;
+mod hybrid;
+mod ranking;
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::db::connection::ChatDb;
+use crate::db::MessageText;
+use crate::utils::app_data_dir;
+
+pub use embeddings::{ChunkHit, EmbeddingStatus};
+pub use fts_query::{fts_query, fts_term};
+pub use hybrid::hybrid_scores;
+pub use ranking::{apply_rerank, parse_rerank_order, rank, Candidate, RankingConfig};
+
+use embeddings::{upgrade_embedding_schema, VectorCache, CHUNK_GENERATION, EMBEDDING_SCHEMA};
+
+/// Messages read from chat.db per transaction while indexing
+const BATCH_SIZE: i64 = 5000;
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+    #[error("Search index error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+}
+
+/// Options for `SearchIndex::search`
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub limit: i64,
+    /// Also match earlier versions of edited messages
+    pub include_edits: bool,
+    /// Only search these chats, e.g. a person's 1:1 chats
+    pub chat_ids: Option<Vec<i64>>,
+    /// Match messages with any of the terms rather than all of them
+    pub match_any: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 50,
+            include_edits: false,
+            chat_ids: None,
+            match_any: false,
+        }
+    }
+}
+
+/// A matching message and its BM25 score; higher is better
+#[derive(Debug, Clone, Copy)]
+pub struct SearchHit {
+    pub message_id: i64,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexStatus {
+    pub indexed: i64,
+    pub last_rowid: i64,
+}
+
+/// Full-text index over decoded message text, kept in `search_index.db`
+/// in the app data directory. chat.db stays read-only; the index catches
+/// up from the last `ROWID` it saw.
+pub struct SearchIndex {
+    conn: Connection,
+    path: PathBuf,
+    vectors: Option<VectorCache>,
+}
+
+impl SearchIndex {
+    pub fn open() -> Result<Self, IndexError> {
+        Self::open_at(&app_data_dir().join("search_index.db"))
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self, IndexError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            r#"
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS index_meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE VIRTUAL TABLE IF NOT EXISTS message_fts USING fts5(
+                text,
+                edits,
+                chat_id UNINDEXED,
+                tokenize = 'unicode61 remove_diacritics 2',
+                prefix = '2 3'
+            );
+            "#,
+        )?;
+        conn.execute_batch(EMBEDDING_SCHEMA)?;
+        upgrade_embedding_schema(&conn)?;
+        // Rank with BM25, counting earlier versions for less than the
+        // current text
+        conn.execute(
+            "INSERT INTO message_fts (message_fts, rank) VALUES ('rank', 'bm25(1.0, 0.5)')",
+            [],
+        )?;
+        Ok(Self {
+            conn,
+            path: path.to_path_buf(),
+            vectors: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Index messages added, edited or unsent since the last sync, and
+    /// chunk them for embedding.
+    /// `source` identifies the database `db` reads; switching sources
+    /// starts the index over. Returns how many messages were (re)indexed.
+    pub fn sync(&mut self, source: &str, db: &ChatDb) -> Result<usize, IndexError> {
+        if get_meta(&self.conn, "source")?.as_deref() != Some(source) {
+            self.reset(source)?;
+        }
+
+        let mut last_rowid = meta_i64(&self.conn, "last_rowid")?;
+        let last_changed = meta_i64(&self.conn, "last_changed")?;
+        let mut count = 0;
+
+        // Edits and unsends since the last sync, before moving past them
+        let changed = db.get_changed_message_texts(last_rowid, last_changed)?;
+        count += changed.len();
+        self.write_batch(&changed, last_rowid)?;
+        let changed_ids: Vec<i64> = changed.iter().map(|message| message.id).collect();
+        self.rechunk_messages(&changed_ids)?;
+
+        loop {
+            let batch = db.get_message_texts(last_rowid, BATCH_SIZE)?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            last_rowid = last.id;
+            count += batch.len();
+            self.write_batch(&batch, last_rowid)?;
+            if (batch.len() as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+
+        self.chunk_new_messages()?;
+        set_meta(&self.conn, "built", "1")?;
+        Ok(count)
+    }
+
+    /// Whether a sync from `source` has run to the end, so searches see
+    /// all of its messages and not just the batches written so far
+    pub fn is_built(&self, source: &str) -> Result<bool, IndexError> {
+        Ok(get_meta(&self.conn, "source")?.as_deref() == Some(source)
+            && get_meta(&self.conn, "built")?.is_some())
+    }
+
+    /// Empty the index to start over from `source`
+    fn reset(&mut self, source: &str) -> Result<(), IndexError> {
+        let tx = self.conn.transaction()?;
+        let generation = meta_i64(&tx, CHUNK_GENERATION)?;
+        tx.execute("DELETE FROM message_fts", [])?;
+        tx.execute("DELETE FROM embedding_chunk", [])?;
+        tx.execute("DELETE FROM index_meta", [])?;
+        set_meta(&tx, "source", source)?;
+        set_meta(&tx, CHUNK_GENERATION, &(generation + 1).to_string())?;
+        tx.commit()?;
+        self.vectors = None;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &[MessageText], last_rowid: i64) -> Result<(), IndexError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        let mut last_changed = meta_i64(&tx, "last_changed")?;
+        {
+            let mut delete = tx.prepare_cached("DELETE FROM message_fts WHERE rowid = ?1")?;
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO message_fts (rowid, text, edits, chat_id) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for message in batch {
+                delete.execute(params![message.id])?;
+                insert.execute(params![
+                    message.id,
+                    message.text,
+                    message.edits,
+                    message.chat_id
+                ])?;
+                last_changed = last_changed.max(message.changed_at);
+            }
+        }
+        set_meta(&tx, "last_rowid", &last_rowid.to_string())?;
+        set_meta(&tx, "last_changed", &last_changed.to_string())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Messages matching `query`, best first. Supports `"quoted phrases"`
+    /// and `prefix*` terms.
+    pub fn search(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchHit>, IndexError> {
+        let Some(expression) = fts_query(query, options.match_any) else {
+            return Ok(Vec::new());
+        };
+        let expression = if options.include_edits {
+            expression
+        } else {
+            format!("{{text}} : ({expression})")
+        };
+        let chat_ids = options
+            .chat_ids
+            .as_ref()
+            .map(|ids| serde_json::to_string(ids).unwrap_or_else(|_| "[]".to_string()));
+
+        let mut stmt = self.conn.prepare_cached(
+            r#"
+            SELECT rowid, -rank
+            FROM message_fts
+            WHERE message_fts MATCH ?1
+              AND (?2 IS NULL OR chat_id IN (SELECT value FROM json_each(?2)))
+            ORDER BY rank
+            LIMIT ?3
+            "#,
+        )?;
+        let hits = stmt
+            .query_map(params![expression, chat_ids, options.limit], |row| {
+                Ok(SearchHit {
+                    message_id: row.get(0)?,
+                    score: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+
+    pub fn status(&self) -> Result<IndexStatus, IndexError> {
+        Ok(IndexStatus {
+            indexed: self
+                .conn
+                .query_row("SELECT COUNT(*) FROM message_fts", [], |row| row.get(0))?,
+            last_rowid: meta_i64(&self.conn, "last_rowid")?,
+        })
+    }
+}
+
+fn get_meta(conn: &Connection, key: &str) -> Result<Option<String>, IndexError> {
+    Ok(conn
+        .query_row(
+            "SELECT value FROM index_meta WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn meta_i64(conn: &Connection, key: &str) -> Result<i64, IndexError> {
+    Ok(get_meta(conn, key)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0))
+}
+
+fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<(), IndexError> {
+    conn.execute(
+        "INSERT INTO index_meta (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(id: i64, chat_id: i64, text: &str, edits: Option<&str>) -> MessageText {
+        MessageText {
+            id,
+            chat_id: Some(chat_id),
+            text: text.to_string(),
+            edits: edits.map(str::to_string),
+            changed_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_search_ranks_prefixes_phrases_and_edits() {
+        let mut index = SearchIndex::open_at(Path::new(":memory:")).unwrap();
+        index
+            .write_batch(
+                &[
+                    text(1, 1, "See you at dinner", None),
+                    text(2, 2, "Café tomorrow?", Some("dinner tomorrow?")),
+                    text(3, 1, "Restaurant is booked for dinner, dinner!", None),
+                ],
+                3,
+            )
+            .unwrap();
+        let ids = |query: &str, options: &SearchOptions| -> Vec<i64> {
+            let hits = index.search(query, options).unwrap();
+            hits.iter().map(|hit| hit.message_id).collect()
+        };
+        let defaults = SearchOptions::default();
+
+        assert_eq!(ids("dinner", &defaults), [3, 1]);
+        assert_eq!(ids("rest*", &defaults), [3]);
+        assert_eq!(ids("\"you at dinner\"", &defaults), [1]);
+        assert_eq!(ids("cafe", &defaults), [2]);
+        let with_edits = SearchOptions {
+            include_edits: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(ids("dinner", &with_edits).len(), 3);
+        let in_chat = SearchOptions {
+            chat_ids: Some(vec![2]),
+            include_edits: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(ids("dinner", &in_chat), [2]);
+        assert_eq!(index.status().unwrap().last_rowid, 3);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Message;
+
+/// How search hits are ordered. Weights are relative to each other.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankingConfig {
+    /// How well the message matches: the retriever's score, or the share
+    /// of query terms it contains
+    pub term_match: f64,
+    /// Newer messages first
+    pub recency: f64,
+    /// Conversations you take part in most
+    pub affinity: f64,
+    /// Days for the recency score to halve
+    pub recency_half_life_days: f64,
+    /// Have the LLM reorder this many of the top results; 0 turns it off
+    pub rerank_top_n: usize,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            term_match: 0.6,
+            recency: 0.25,
+            affinity: 0.15,
+            recency_half_life_days: 180.0,
+            rerank_top_n: 0,
+        }
+    }
+}
+
+/// A hit to rank, with the score whatever found it gave it (BM25, vector
+/// similarity), if any
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub message: Message,
+    pub retrieval_score: Option<f64>,
+}
+
+/// Score and order candidates, best first. `affinity` holds each
+/// message's conversation activity as returned by
+/// `ChatDb::get_conversation_affinity`.
+pub fn rank(
+    candidates: Vec<Candidate>,
+    terms: &[String],
+    affinity: &HashMap<i64, f64>,
+    config: &RankingConfig,
+    now: DateTime<Utc>,
+) -> Vec<(Message, f64)> {
+    let best_retrieval = candidates
+        .iter()
+        .filter_map(|c| c.retrieval_score)
+        .fold(0.0, f64::max);
+    let best_affinity = affinity.values().copied().fold(0.0, f64::max);
+    let total_weight = config.term_match + config.recency + config.affinity;
+    let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+
+    let mut ranked: Vec<(Message, f64)> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let message = candidate.message;
+            let term_match = match candidate.retrieval_score {
+                Some(score) if best_retrieval > 0.0 => score / best_retrieval,
+                _ => term_coverage(message.text.as_deref().unwrap_or(""), &terms),
+            };
+
+            let age_days = (now - message.date).num_seconds().max(0) as f64 / 86_400.0;
+            let recency = if config.recency_half_life_days > 0.0 {
+                0.5_f64.powf(age_days / config.recency_half_life_days)
+            } else {
+                0.0
+            };
+
+            let activity = affinity.get(&message.id).copied().unwrap_or(0.0);
+            let affinity = if best_affinity > 0.0 {
+                activity.ln_1p() / best_affinity.ln_1p()
+            } else {
+                0.0
+            };
+
+            let score = config.term_match * term_match
+                + config.recency * recency
+                + config.affinity * affinity;
+            let score = if total_weight > 0.0 {
+                score / total_weight
+            } else {
+                0.0
+            };
+            (message, score)
+        })
+        .collect();
+
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.date.cmp(&a.0.date)));
+    ranked
+}
+
+/// Share of `terms` found in `text`
+fn term_coverage(text: &str, terms: &[String]) -> f64 {
+    if terms.is_empty() {
+        return 0.0;
+    }
+    let text = text.to_lowercase();
+    let found = terms
+        .iter()
+        .filter(|term| text.contains(term.as_str()))
+        .count();
+    found as f64 / terms.len() as f64
+}
+
+/// Read the LLM's reranking: a JSON array of result numbers, best first.
+/// Unknown and repeated numbers are dropped and anything left out keeps
+/// its place after the ones listed.
+pub fn parse_rerank_order(response: &str, count: usize) -> Option<Vec<usize>> {
+    let start = response.find('[')?;
+    let end = start + response[start..].find(']')?;
+    let listed: Vec<usize> = serde_json::from_str(&response[start..=end]).ok()?;
+
+    let mut order = Vec::with_capacity(count);
+    for index in listed {
+        if index < count && !order.contains(&index) {
+            order.push(index);
+        }
+    }
+    let unlisted: Vec<usize> = (0..count).filter(|i| !order.contains(i)).collect();
+    order.extend(unlisted);
+    Some(order)
+}
+
+/// Blend the LLM's order for the top results into their scores and
+/// reorder just those; results past the top keep their place
+pub fn apply_rerank(mut ranked: Vec<(Message, f64)>, order: &[usize]) -> Vec<(Message, f64)> {
+    let count = order.len().min(ranked.len());
+    for (position, &index) in order.iter().take(count).enumerate() {
+        let llm_score = 1.0 - position as f64 / count as f64;
+        let (_, score) = &mut ranked[index];
+        *score = (*score + llm_score) / 2.0;
+    }
+    ranked[..count].sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn message(id: i64, text: &str, age_days: i64, now: DateTime<Utc>) -> Message {
+        Message {
+            id,
+            guid: format!("guid-{id}"),
+            text: Some(text.to_string()),
+            handle_id: 1,
+            date: now - Duration::days(age_days),
+            date_raw: 0,
+            date_read: None,
+            date_delivered: None,
+            is_from_me: false,
+            service: "iMessage".to_string(),
+            contact_name: None,
+            contact_id: None,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            reply_to: None,
+            date_edited: None,
+            date_retracted: None,
+            is_unsent: false,
+            retracted_parts: Vec::new(),
+            edit_history: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    fn candidate(message: Message) -> Candidate {
+        Candidate {
+            message,
+            retrieval_score: None,
+        }
+    }
+
+    #[test]
+    fn test_rank_weighs_matches_recency_and_affinity() {
+        let now = Utc::now();
+        let terms = ["tacos".to_string(), "friday".to_string()];
+        let candidates = vec![
+            candidate(message(1, "tacos on friday?", 400, now)),
+            candidate(message(2, "tacos", 1, now)),
+            candidate(message(3, "tacos", 1, now)),
+        ];
+        let affinity = HashMap::from([(1, 0.0), (2, 2.0), (3, 50.0)]);
+
+        let config = RankingConfig::default();
+        let ranked = rank(candidates.clone(), &terms, &affinity, &config, now);
+        let ids: Vec<i64> = ranked.iter().map(|(message, _)| message.id).collect();
+        // A busy chat lifts a partial match over the old full one; recency
+        // alone doesn't
+        assert_eq!(ids, [3, 1, 2]);
+
+        let matches_only = RankingConfig {
+            recency: 0.0,
+            affinity: 0.0,
+            ..RankingConfig::default()
+        };
+        let ranked = rank(candidates, &terms, &affinity, &matches_only, now);
+        assert_eq!(ranked[0].0.id, 1);
+        assert!((ranked[0].1 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rerank_order() {
+        assert_eq!(
+            parse_rerank_order("Order: [2, 0, 2, 9]", 4),
+            Some(vec![2, 0, 1, 3])
+        );
+        assert_eq!(parse_rerank_order("no idea", 3), None);
+    }
+
+    #[test]
+    fn test_rerank_only_moves_the_top() {
+        let now = Utc::now();
+        let ranked: Vec<(Message, f64)> = [(1, 0.9), (2, 0.8), (3, 0.3), (4, 0.2)]
+            .into_iter()
+            .map(|(id, score)| (message(id, "tacos", 1, now), score))
+            .collect();
+
+        let reranked = apply_rerank(ranked, &[1, 0]);
+        let ids: Vec<i64> = reranked.iter().map(|(message, _)| message.id).collect();
+        assert_eq!(ids, [2, 1, 3, 4]);
+    }
+}
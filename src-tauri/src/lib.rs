@@ -1,5 +1,7 @@
 mod commands;
+mod contacts;
 mod db;
+mod index;
 mod llm;
 mod state;
 mod utils;
@@ -7,12 +9,35 @@ mod utils;
 use state::AppState;
 use tauri::Manager;
 
+#[cfg(feature = "fixture")]
+pub use db::fixture;
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .setup(|app| {
             let state = AppState::new();
+            let config = commands::settings::load_config();
+            if let Some(source) = config.data_source {
+                state.update_data_source(source)?;
+            }
+            if let Some(enabled) = config.snapshot_mode {
+                state.update_snapshot_mode(enabled)?;
+            }
+            if let Some(path) = config.address_book_path {
+                state.update_address_book_path(Some(path))?;
+            }
+            if let Some(model) = config.embedding_model {
+                state.update_embedding_model(model)?;
+            }
+            if let Some(ranking) = config.ranking {
+                state.update_ranking_config(ranking)?;
+            }
+            // Searches use whatever is indexed while this catches up
+            state.sync_search_index_in_background()?;
+            // Live updates are a convenience; the app works without them
+            let _ = state.watch_messages(app.handle().clone());
             app.manage(state);
             Ok(())
         })
@@ -20,13 +45,33 @@ pub fn run() {
             // Search commands
             commands::search::natural_language_search,
             commands::search::simple_search,
+            commands::search::get_message_context,
+            commands::search::refresh_search_index,
+            commands::search::semantic_search,
+            commands::search::update_embeddings,
             commands::search::ask_question,
             // Conversation commands
             commands::conversations::get_conversations,
             commands::conversations::get_conversation_messages,
+            commands::conversations::jump_to_date,
+            commands::conversations::get_reply_thread,
+            commands::conversations::get_chat_attachments,
+            commands::conversations::get_attachment_path,
             commands::conversations::summarize_conversation,
             commands::conversations::summarize_conversation_streaming,
             commands::conversations::analyze_conversation,
+            // People commands
+            commands::people::get_people,
+            commands::people::get_person_timeline,
+            // Contact commands
+            commands::contacts::get_address_book_path,
+            commands::contacts::set_address_book_path,
+            commands::contacts::reload_contacts,
+            commands::contacts::import_vcards,
+            commands::contacts::list_aliases,
+            commands::contacts::add_alias,
+            commands::contacts::update_alias,
+            commands::contacts::delete_alias,
             // Settings commands
             commands::settings::save_api_key,
             commands::settings::get_api_key,
@@ -37,6 +82,17 @@ pub fn run() {
             commands::settings::save_provider_settings,
             commands::settings::fetch_ollama_models,
             commands::settings::check_ollama_status,
+            commands::settings::get_embedding_model,
+            commands::settings::set_embedding_model,
+            commands::settings::get_ranking_config,
+            commands::settings::set_ranking_config,
+            commands::settings::get_data_source,
+            commands::settings::set_data_source,
+            commands::settings::get_database_diagnostics,
+            commands::settings::get_snapshot_mode,
+            commands::settings::set_snapshot_mode,
+            commands::settings::get_snapshot_info,
+            commands::settings::refresh_snapshot,
             commands::settings::restart_app,
         ])
         .run(tauri::generate_context!())
@@ -17,6 +17,8 @@ pub struct LlmConfig {
     pub api_key: String,
     pub model: String,
     pub ollama_url: String,
+    /// Ollama model used for semantic search embeddings
+    pub embedding_model: String,
     pub temperature: f32,
     pub max_tokens: u32,
 }
@@ -28,6 +30,7 @@ impl Default for LlmConfig {
             api_key: String::new(),
             model: "llama3.1:8b".to_string(),
             ollama_url: "http://localhost:11434".to_string(),
+            embedding_model: "nomic-embed-text".to_string(),
             temperature: 0.7,
             max_tokens: 4096,
         }
@@ -0,0 +1,81 @@
+use std::future::Future;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Turns text into vectors for semantic search. Ollama in the app; tests
+/// substitute their own.
+pub trait Embedder {
+    /// Vectors from different models can't be compared, so stored ones are
+    /// tagged with this
+    fn model(&self) -> &str;
+
+    /// One vector per text, in order
+    fn embed(&self, texts: &[String])
+        -> impl Future<Output = Result<Vec<Vec<f32>>, String>> + Send;
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Embeddings from the configured Ollama server's `/api/embed`
+pub struct OllamaEmbedder {
+    client: Client,
+    url: String,
+    model: String,
+}
+
+impl OllamaEmbedder {
+    pub fn new(url: String, model: String) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+            model,
+        }
+    }
+}
+
+impl Embedder for OllamaEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let request = EmbedRequest {
+            model: &self.model,
+            input: texts,
+        };
+        let response = self
+            .client
+            .post(format!("{}/api/embed", self.url))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| format!("Ollama connection error: {}. Is Ollama running?", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!("Embedding error ({}): {}", status, text));
+        }
+
+        let response: EmbedResponse = response.json().await.map_err(|e| e.to_string())?;
+        if response.embeddings.len() != texts.len() {
+            return Err(format!(
+                "Expected {} embeddings from {}, got {}",
+                texts.len(),
+                self.model,
+                response.embeddings.len()
+            ));
+        }
+        Ok(response.embeddings)
+    }
+}
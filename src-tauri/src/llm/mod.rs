@@ -1,7 +1,9 @@
 mod client;
+mod embeddings;
 mod nl2sql;
 mod prompts;
 
 pub use client::{LlmClient, LlmConfig, LlmProvider};
+pub use embeddings::{Embedder, OllamaEmbedder};
 pub use nl2sql::Nl2SqlEngine;
 pub use prompts::*;
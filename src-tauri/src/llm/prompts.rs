@@ -1,21 +1,31 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::{Message, Reaction, ReactionKind};
+
 pub const SCHEMA_CONTEXT: &str = r#"
 You are a SQL query generator for an iMessage database on macOS. The database schema is:
 
 Tables:
-- message: ROWID, guid, text, attributedBody, handle_id, date (nanoseconds since 2001-01-01), is_from_me (0 or 1), service
+- message: ROWID, guid, text, attributedBody, handle_id, date (nanoseconds since 2001-01-01), date_read, date_delivered (0 if not yet), is_from_me (0 or 1), service, thread_originator_guid (guid of the message an inline reply answers), date_edited (0 if never edited), date_retracted (0 unless unsent)
 - handle: ROWID, id (phone number or email), service, uncanonicalized_id
 - chat: ROWID, guid, display_name, style (43=group chat)
 - chat_message_join: chat_id, message_id
 - chat_handle_join: chat_id, handle_id
-- attachment: ROWID, guid, filename, mime_type, total_bytes
+- attachment: ROWID, guid, filename, mime_type, uti, total_bytes, transfer_name, is_sticker
+- message_attachment_join: message_id, attachment_id
 
 Key relationships:
 - message.handle_id -> handle.ROWID
 - chat_message_join links chats to messages
 - chat_handle_join links chats to participants
+- message_attachment_join links messages to attachments
 
 Date handling: Timestamps are in nanoseconds since 2001-01-01 (Mac epoch).
 To convert from a date like "2024-01-15", you need to calculate nanoseconds from 2001-01-01.
+Older rows store seconds instead, so compare and order dates through mac_timestamp_nanos(), e.g. mac_timestamp_nanos(m.date) >= 726969600000000000.
 
 Important: Only generate SELECT queries. Never modify data.
 "#;
@@ -31,7 +41,7 @@ Rules:
 1. Always SELECT these columns from message: m.ROWID, m.guid, m.text, m.attributedBody, m.handle_id, m.date, m.is_from_me, m.service
 2. Always LEFT JOIN with handle h ON m.handle_id = h.ROWID to get h.id as handle_identifier
 3. Use LIKE with wildcards for text searches (e.g., WHERE m.text LIKE '%keyword%')
-4. Order by m.date DESC for most recent first
+4. Order by mac_timestamp_nanos(m.date) DESC for most recent first
 5. Always LIMIT results (default to 50 if not specified)
 6. For searching by contact, use WHERE h.id LIKE '%contact%'
 7. For group chats, join with chat_message_join and chat tables
@@ -41,6 +51,114 @@ Return ONLY the SQL query, no explanation or markdown formatting."#,
     )
 }
 
+/// Explains the reply and edit structure carried in the messages JSON
+const MESSAGE_NOTES: &str = r#"Some messages are inline replies: their "reply_to" is the "id" of the earlier message they answer. Use this to tell which message each reply responds to, even when other messages were sent in between.
+Edited messages list their earlier versions in "edits"; "text" is the final version. Messages with "unsent" set were taken back by the sender.
+"sender" is the sender's name from the user's contacts, or their phone number or email; "me" is the user. "reactions" lists the tapbacks a message got."#;
+
+/// The parts of a message a prompt needs. The full `Message` carries
+/// attribute runs, attachments and per-part history that only cost tokens.
+#[derive(Serialize)]
+struct PromptMessage<'a> {
+    id: i64,
+    sender: &'a str,
+    date: DateTime<Utc>,
+    text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reactions: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    edits: Vec<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    unsent: bool,
+}
+
+/// Messages as JSON for the summarize, analyze and answer prompts
+pub fn prompt_messages_json(messages: &[Message]) -> serde_json::Result<String> {
+    let ids: HashMap<&str, i64> = messages.iter().map(|m| (m.guid.as_str(), m.id)).collect();
+    let trimmed: Vec<PromptMessage> = messages
+        .iter()
+        .map(|message| PromptMessage {
+            id: message.id,
+            sender: sender_name(
+                message.is_from_me,
+                &message.contact_name,
+                &message.contact_id,
+            ),
+            date: message.date,
+            text: message.text.as_deref(),
+            // Only replies to messages in the prompt can be followed
+            reply_to: message
+                .reply_to
+                .as_ref()
+                .and_then(|reply| ids.get(reply.guid.as_str()).copied()),
+            reactions: reaction_summary(&message.reactions),
+            edits: earlier_versions(message),
+            unsent: message.is_unsent,
+        })
+        .collect();
+    serde_json::to_string(&trimmed)
+}
+
+fn sender_name<'a>(
+    is_from_me: bool,
+    contact_name: &'a Option<String>,
+    contact_id: &'a Option<String>,
+) -> &'a str {
+    if is_from_me {
+        return "me";
+    }
+    contact_name
+        .as_deref()
+        .or(contact_id.as_deref())
+        .unwrap_or("unknown")
+}
+
+/// e.g. "loved by Alice, me; 👍 by Sam"
+fn reaction_summary(reactions: &[Reaction]) -> Option<String> {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for reaction in reactions.iter().filter(|r| !r.is_removed) {
+        let label = match reaction.kind {
+            ReactionKind::Loved => "loved",
+            ReactionKind::Liked => "liked",
+            ReactionKind::Disliked => "disliked",
+            ReactionKind::Laughed => "laughed at",
+            ReactionKind::Emphasized => "emphasized",
+            ReactionKind::Questioned => "questioned",
+            ReactionKind::Emoji => reaction.emoji.as_deref().unwrap_or("reacted"),
+        };
+        let who = sender_name(
+            reaction.is_from_me,
+            &reaction.contact_name,
+            &reaction.contact_id,
+        );
+        match groups.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, people)) => people.push(who),
+            None => groups.push((label, vec![who])),
+        }
+    }
+    if groups.is_empty() {
+        return None;
+    }
+    let parts: Vec<String> = groups
+        .iter()
+        .map(|(label, people)| format!("{label} by {}", people.join(", ")))
+        .collect();
+    Some(parts.join("; "))
+}
+
+/// Every version but the last of each edited part, oldest first
+fn earlier_versions(message: &Message) -> Vec<&str> {
+    let history = &message.edit_history;
+    history
+        .iter()
+        .zip(history.iter().skip(1))
+        .filter(|(version, next)| version.part_index == next.part_index)
+        .filter_map(|(version, _)| version.text.as_deref())
+        .collect()
+}
+
 pub fn summarize_prompt(messages_json: &str, contact_name: &str) -> String {
     format!(
         r#"Summarize this conversation with {}. Focus on:
@@ -52,8 +170,10 @@ pub fn summarize_prompt(messages_json: &str, contact_name: &str) -> String {
 Messages (JSON format, ordered chronologically):
 {}
 
+{}
+
 Provide a concise summary in 2-3 paragraphs. Be specific about what was discussed."#,
-        contact_name, messages_json
+        contact_name, messages_json, MESSAGE_NOTES
     )
 }
 
@@ -64,6 +184,8 @@ pub fn analyze_prompt(messages_json: &str) -> String {
 Messages (JSON format):
 {}
 
+{}
+
 Provide:
 1. **Conversation Summary** (2-3 sentences)
 2. **Key Topics/Themes** (bullet points)
@@ -72,7 +194,7 @@ Provide:
 5. **Suggested Follow-ups or Action Items**
 
 Format the response with clear markdown headers."#,
-        messages_json
+        messages_json, MESSAGE_NOTES
     )
 }
 
@@ -85,6 +207,8 @@ The user asked: "{}"
 Here are relevant messages from their iMessage history (JSON format):
 {}
 
+{}
+
 Instructions:
 1. Answer the user's question directly and concisely based on the messages provided
 2. If the messages contain the answer, provide specific details (dates, times, names, places)
@@ -94,6 +218,122 @@ Instructions:
 6. Keep your response brief - 1-3 sentences for simple questions, a short paragraph for complex ones
 
 Respond naturally as if you're a helpful assistant who has access to the user's messages."#,
-        question, messages_json
+        question, messages_json, MESSAGE_NOTES
     )
 }
+
+/// Ask for the results in `numbered_messages` ("[0] …" per line) ordered
+/// by how well they answer `query`
+pub fn rerank_prompt(query: &str, numbered_messages: &str) -> String {
+    format!(
+        r#"The user searched their iMessage history for: "{}"
+
+Here are the top results, numbered:
+{}
+
+Order the results from most to least relevant to the search. Judge by what the user is looking for, not just shared words.
+
+Return ONLY a JSON array of the result numbers, best first, e.g. [2, 0, 1]. No explanation."#,
+        query, numbered_messages
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{MessageVersion, ReplyTo};
+
+    fn message(id: i64, text: &str) -> Message {
+        Message {
+            id,
+            guid: format!("guid-{id}"),
+            text: Some(text.to_string()),
+            handle_id: 1,
+            date: DateTime::from_timestamp(1_700_000_000 + id, 0).unwrap(),
+            date_raw: 0,
+            date_read: None,
+            date_delivered: None,
+            is_from_me: false,
+            service: "iMessage".to_string(),
+            contact_name: Some("Alice".to_string()),
+            contact_id: Some("+15551234567".to_string()),
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            reply_to: None,
+            date_edited: None,
+            date_retracted: None,
+            is_unsent: false,
+            retracted_parts: Vec::new(),
+            edit_history: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    fn reaction(kind: ReactionKind, name: Option<&str>, is_removed: bool) -> Reaction {
+        Reaction {
+            kind,
+            emoji: None,
+            handle_id: 2,
+            contact_id: Some("sam@example.com".to_string()),
+            contact_name: name.map(String::from),
+            is_from_me: name.is_none(),
+            date: DateTime::from_timestamp(1_700_000_100, 0).unwrap(),
+            part_index: 0,
+            is_removed,
+        }
+    }
+
+    #[test]
+    fn test_prompt_messages_are_trimmed() {
+        let question = message(1, "Dinner Friday?");
+        let mut place = message(2, "Elm St at 7");
+        place.edit_history = ["Main St at 7", "Elm St at 7"]
+            .iter()
+            .map(|text| MessageVersion {
+                part_index: 0,
+                text: Some(text.to_string()),
+                date: None,
+            })
+            .collect();
+        place.reactions = vec![
+            reaction(ReactionKind::Loved, Some("Sam"), false),
+            reaction(ReactionKind::Loved, None, false),
+            reaction(ReactionKind::Liked, Some("Sam"), true),
+        ];
+        let mut reply = message(3, "Perfect");
+        reply.is_from_me = true;
+        reply.reply_to = Some(ReplyTo {
+            guid: question.guid.clone(),
+            part_index: 0,
+        });
+
+        let json = prompt_messages_json(&[question, place, reply]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {
+                    "id": 1,
+                    "sender": "Alice",
+                    "date": "2023-11-14T22:13:21Z",
+                    "text": "Dinner Friday?"
+                },
+                {
+                    "id": 2,
+                    "sender": "Alice",
+                    "date": "2023-11-14T22:13:22Z",
+                    "text": "Elm St at 7",
+                    "reactions": "loved by Sam, me",
+                    "edits": ["Main St at 7"]
+                },
+                {
+                    "id": 3,
+                    "sender": "me",
+                    "date": "2023-11-14T22:13:23Z",
+                    "text": "Perfect",
+                    "reply_to": 1
+                }
+            ])
+        );
+    }
+}
@@ -1,21 +1,360 @@
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-use crate::db::ChatDb;
-use crate::llm::{LlmClient, LlmConfig, LlmProvider, Nl2SqlEngine};
+use tauri::AppHandle;
+
+use crate::contacts::{ContactBook, ContactsStatus};
+use crate::db::connection::ChatDb;
+use crate::db::{
+    ChatDbBackend, ChatVersion, DataSource, DbPool, DbTarget, MessageStore, Snapshot,
+    StoreBackend,
+};
+use crate::index::{IndexStatus, RankingConfig, SearchIndex};
+use crate::llm::{LlmClient, LlmConfig, LlmProvider, Nl2SqlEngine, OllamaEmbedder};
+
+use super::watcher::{MessageWatcher, WatchTargets};
+
+/// The error searches return until the search index has been built
+const INDEX_BUILDING: &str =
+    "The search index is still being built. Search again in a few minutes.";
 
 pub struct AppState {
     pub llm_config: Mutex<LlmConfig>,
+    pub data_source: Mutex<DataSource>,
+    pub snapshot_mode: Mutex<bool>,
+    pub snapshot: Arc<Mutex<Option<Snapshot>>>,
+    pub db_pool: DbPool,
+    /// Overrides the default AddressBook location
+    pub address_book_path: Arc<Mutex<Option<PathBuf>>>,
+    /// Loaded on first use and kept until the contact settings change
+    pub contacts: Arc<Mutex<Option<Arc<ContactBook>>>>,
+    /// Opened on first search. Syncs write through their own connection,
+    /// so searches read whatever is indexed while one runs.
+    pub search_index: Arc<Mutex<Option<SearchIndex>>>,
+    /// Held by the sync writing to the search index, one at a time
+    pub index_sync: Arc<Mutex<()>>,
+    pub ranking: Mutex<RankingConfig>,
+    /// Chat summaries by chat id, with the version of the chat each was
+    /// made from
+    pub summaries: Mutex<HashMap<i64, (ChatVersion, String)>>,
+    /// Pushes new messages while the data source is a live database
+    pub watcher: Mutex<Option<MessageWatcher>>,
 }
 
 impl AppState {
     pub fn new() -> Self {
+        let db_pool = DbPool::new();
         Self {
             llm_config: Mutex::new(LlmConfig::default()),
+            data_source: Mutex::new(DataSource::default()),
+            snapshot_mode: Mutex::new(false),
+            snapshot: Arc::new(Mutex::new(None)),
+            db_pool,
+            address_book_path: Arc::new(Mutex::new(None)),
+            contacts: Arc::new(Mutex::new(None)),
+            search_index: Arc::new(Mutex::new(None)),
+            index_sync: Arc::new(Mutex::new(())),
+            ranking: Mutex::new(RankingConfig::default()),
+            summaries: Mutex::new(HashMap::new()),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// Run `f` with a pooled connection to the current data source (or its
+    /// snapshot) on the blocking thread pool.
+    ///
+    /// rusqlite is synchronous, so querying inside an async command would
+    /// stall the runtime, including streaming LLM output. Every call checks
+    /// out its own connection, letting DB-heavy commands run side by side.
+    pub async fn with_db<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ChatDb) -> Result<T, String> + Send + 'static,
+    {
+        let source = self.get_data_source()?;
+        let snapshot_mode = self.get_snapshot_mode()?;
+        let snapshot = self.snapshot.clone();
+        let pool = self.db_pool.clone();
+        let address_book_path = self.get_address_book_path()?;
+        let contacts = self.contacts.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            let target = resolve_db_target(source, snapshot_mode, &snapshot)?;
+            let mut db = pool.get(&target).map_err(|e| e.to_string())?;
+            db.contacts = cached_contacts(&contacts, address_book_path)?;
+            f(&db)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    /// Like `with_db`, through the `MessageStore` the config selects.
+    /// Prefer this for anything that doesn't need chat.db itself.
+    pub async fn with_store<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn MessageStore) -> Result<T, String> + Send + 'static,
+    {
+        let backend = self.store_backend_job()?;
+
+        tauri::async_runtime::spawn_blocking(move || {
+            backend()?.with_store(f).map_err(|e| e.to_string())?
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    /// Builds the backend for the configured data source. Resolving it can
+    /// take a snapshot or load contacts, so it runs off the async runtime.
+    fn store_backend_job(
+        &self,
+    ) -> Result<impl FnOnce() -> Result<ChatDbBackend, String> + Send + 'static, String> {
+        let source = self.get_data_source()?;
+        let snapshot_mode = self.get_snapshot_mode()?;
+        let snapshot = self.snapshot.clone();
+        let pool = self.db_pool.clone();
+        let address_book_path = self.get_address_book_path()?;
+        let contacts = self.contacts.clone();
+
+        Ok(move || {
+            Ok(ChatDbBackend {
+                pool,
+                target: resolve_db_target(source, snapshot_mode, &snapshot)?,
+                contacts: cached_contacts(&contacts, address_book_path)?,
+            })
+        })
+    }
+
+    /// Like `with_store`, with the search index attached. Searches see
+    /// what has been indexed so far; `sync_search_index` catches it up.
+    pub async fn with_index<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn MessageStore) -> Result<T, String> + Send + 'static,
+    {
+        let path = self
+            .with_built_index(|index| Ok(index.path().to_path_buf()))
+            .await?;
+
+        self.with_store(move |store| {
+            store
+                .attach_search_index(&path)
+                .map_err(|e| e.to_string())?;
+            f(store)
+        })
+        .await
+    }
+
+    /// Like `with_search_index`, for searching. Until the first sync of
+    /// the current data source has finished, the index only holds part of
+    /// it, so this starts a sync and fails with `INDEX_BUILDING` instead.
+    pub async fn with_built_index<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SearchIndex) -> Result<T, String> + Send + 'static,
+    {
+        let source_key = self.get_data_source()?.db_path().display().to_string();
+        let result = self
+            .with_search_index(move |index| {
+                if index.is_built(&source_key).map_err(|e| e.to_string())? {
+                    f(index).map(Some)
+                } else {
+                    Ok(None)
+                }
+            })
+            .await?;
+
+        match result {
+            Some(result) => Ok(result),
+            None => {
+                // A sync already under way finishes the job
+                if self.index_sync.try_lock().is_ok() {
+                    self.sync_search_index_in_background()?;
+                }
+                Err(INDEX_BUILDING.to_string())
+            }
+        }
+    }
+
+    /// Index messages added, edited or unsent since the last sync and wait
+    /// for it to finish
+    pub async fn sync_search_index(&self) -> Result<IndexStatus, String> {
+        let sync = self.index_sync_job()?;
+        tauri::async_runtime::spawn_blocking(sync)
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    /// Like `sync_search_index` without waiting, e.g. at startup. A sync
+    /// that fails is caught up by the next one.
+    pub fn sync_search_index_in_background(&self) -> Result<(), String> {
+        let sync = self.index_sync_job()?;
+        tauri::async_runtime::spawn_blocking(move || {
+            let _ = sync();
+        });
+        Ok(())
+    }
+
+    fn index_sync_job(
+        &self,
+    ) -> Result<impl FnOnce() -> Result<IndexStatus, String> + Send + 'static, String> {
+        let source = self.get_data_source()?;
+        let snapshot_mode = self.get_snapshot_mode()?;
+        let snapshot = self.snapshot.clone();
+        let pool = self.db_pool.clone();
+        let index_sync = self.index_sync.clone();
+
+        Ok(move || {
+            let source_key = source.db_path().display().to_string();
+            let target = resolve_db_target(source, snapshot_mode, &snapshot)?;
+            let db = pool.get(&target).map_err(|e| e.to_string())?;
+            sync_search_index(&index_sync, &source_key, &db)
+        })
+    }
+
+    /// The search index alone, without reading chat.db, e.g. to store
+    /// embeddings
+    pub async fn with_search_index<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SearchIndex) -> Result<T, String> + Send + 'static,
+    {
+        let search_index = self.search_index.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            let mut current = search_index.lock().map_err(|e| e.to_string())?;
+            f(open_search_index(&mut current)?)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    /// Watch the current data source for new messages, replacing any
+    /// earlier watch. Backups don't change, so they aren't watched.
+    pub fn watch_messages(&self, app: AppHandle) -> Result<(), String> {
+        let source = self.get_data_source()?;
+        let mut watcher = self.watcher.lock().map_err(|e| e.to_string())?;
+        *watcher = None;
+        if !source.is_live() {
+            return Ok(());
         }
+
+        let targets = WatchTargets {
+            address_book_path: self.address_book_path.clone(),
+            contacts: self.contacts.clone(),
+            index_sync: self.index_sync.clone(),
+        };
+        *watcher = Some(MessageWatcher::start(app, source, targets).map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    /// The cached summary of a chat, if it was made from `version`
+    pub fn get_summary(
+        &self,
+        chat_id: i64,
+        version: ChatVersion,
+    ) -> Result<Option<String>, String> {
+        let summaries = self.summaries.lock().map_err(|e| e.to_string())?;
+        Ok(summaries
+            .get(&chat_id)
+            .filter(|(cached, _)| *cached == version)
+            .map(|(_, summary)| summary.clone()))
+    }
+
+    pub fn cache_summary(
+        &self,
+        chat_id: i64,
+        version: ChatVersion,
+        summary: String,
+    ) -> Result<(), String> {
+        let mut summaries = self.summaries.lock().map_err(|e| e.to_string())?;
+        summaries.insert(chat_id, (version, summary));
+        Ok(())
+    }
+
+    pub fn get_snapshot_mode(&self) -> Result<bool, String> {
+        let enabled = self.snapshot_mode.lock().map_err(|e| e.to_string())?;
+        Ok(*enabled)
+    }
+
+    pub fn update_snapshot_mode(&self, enabled: bool) -> Result<(), String> {
+        let mut current = self.snapshot_mode.lock().map_err(|e| e.to_string())?;
+        *current = enabled;
+        Ok(())
     }
 
-    pub fn get_db(&self) -> Result<ChatDb, String> {
-        ChatDb::new().map_err(|e| e.to_string())
+    pub fn get_snapshot(&self) -> Result<Option<Snapshot>, String> {
+        let snapshot = self.snapshot.lock().map_err(|e| e.to_string())?;
+        Ok(snapshot.clone())
+    }
+
+    pub async fn refresh_snapshot(&self) -> Result<Snapshot, String> {
+        let source = self.get_data_source()?;
+        let snapshot = self.snapshot.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            // Hold the lock while copying so concurrent refreshes don't race
+            // on the snapshot file
+            let mut current = snapshot.lock().map_err(|e| e.to_string())?;
+            let taken = Snapshot::take(&source).map_err(|e| e.to_string())?;
+            *current = Some(taken.clone());
+            Ok(taken)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    pub fn get_address_book_path(&self) -> Result<Option<PathBuf>, String> {
+        let path = self.address_book_path.lock().map_err(|e| e.to_string())?;
+        Ok(path.clone())
+    }
+
+    pub fn update_address_book_path(&self, path: Option<PathBuf>) -> Result<(), String> {
+        let mut current = self.address_book_path.lock().map_err(|e| e.to_string())?;
+        *current = path;
+        self.clear_contacts()
+    }
+
+    /// Drop the cached contacts so the next query reloads them
+    pub fn clear_contacts(&self) -> Result<(), String> {
+        let mut contacts = self.contacts.lock().map_err(|e| e.to_string())?;
+        *contacts = None;
+        Ok(())
+    }
+
+    pub async fn reload_contacts(&self) -> Result<ContactsStatus, String> {
+        let path = self.get_address_book_path()?;
+        let contacts = self.contacts.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            let mut book = ContactBook::load_local().map_err(|e| e.to_string())?;
+            // Keep the app's own contacts even if the AddressBook is unreadable
+            let address_book = book.add_address_book(path.as_deref());
+            let handles = book.len();
+            let mut current = contacts.lock().map_err(|e| e.to_string())?;
+            *current = Some(Arc::new(book));
+            let skipped_stores = address_book.map_err(|e| e.to_string())?;
+            Ok(ContactsStatus {
+                handles,
+                skipped_stores,
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    pub fn get_data_source(&self) -> Result<DataSource, String> {
+        let source = self.data_source.lock().map_err(|e| e.to_string())?;
+        Ok(source.clone())
+    }
+
+    pub fn update_data_source(&self, source: DataSource) -> Result<(), String> {
+        let mut current = self.data_source.lock().map_err(|e| e.to_string())?;
+        *current = source;
+        let mut summaries = self.summaries.lock().map_err(|e| e.to_string())?;
+        summaries.clear();
+        Ok(())
     }
 
     pub fn get_llm_client(&self) -> Result<LlmClient, String> {
@@ -33,6 +372,14 @@ impl AppState {
         Ok(LlmClient::new(config))
     }
 
+    pub fn get_embedder(&self) -> Result<OllamaEmbedder, String> {
+        let config = self.llm_config.lock().map_err(|e| e.to_string())?;
+        Ok(OllamaEmbedder::new(
+            config.ollama_url.clone(),
+            config.embedding_model.clone(),
+        ))
+    }
+
     pub fn get_nl2sql_engine(&self) -> Result<Nl2SqlEngine, String> {
         let llm = self.get_llm_client()?;
         Ok(Nl2SqlEngine::new(llm))
@@ -62,6 +409,28 @@ impl AppState {
         Ok(())
     }
 
+    pub fn update_embedding_model(&self, model: String) -> Result<(), String> {
+        let mut config = self.llm_config.lock().map_err(|e| e.to_string())?;
+        config.embedding_model = model;
+        Ok(())
+    }
+
+    pub fn get_embedding_model(&self) -> Result<String, String> {
+        let config = self.llm_config.lock().map_err(|e| e.to_string())?;
+        Ok(config.embedding_model.clone())
+    }
+
+    pub fn get_ranking_config(&self) -> Result<RankingConfig, String> {
+        let ranking = self.ranking.lock().map_err(|e| e.to_string())?;
+        Ok(ranking.clone())
+    }
+
+    pub fn update_ranking_config(&self, ranking: RankingConfig) -> Result<(), String> {
+        let mut current = self.ranking.lock().map_err(|e| e.to_string())?;
+        *current = ranking;
+        Ok(())
+    }
+
     pub fn get_provider(&self) -> Result<LlmProvider, String> {
         let config = self.llm_config.lock().map_err(|e| e.to_string())?;
         Ok(config.provider.clone())
@@ -78,6 +447,65 @@ impl AppState {
     }
 }
 
+/// Pick what a connection should read from. In snapshot mode the current
+/// snapshot is reused until refreshed; the first one is taken lazily (and
+/// again after the data source changes).
+fn resolve_db_target(
+    source: DataSource,
+    snapshot_mode: bool,
+    snapshot: &Mutex<Option<Snapshot>>,
+) -> Result<DbTarget, String> {
+    if !snapshot_mode || !source.supports_snapshots() {
+        return Ok(DbTarget::Source(source));
+    }
+
+    let mut current = snapshot.lock().map_err(|e| e.to_string())?;
+    if let Some(existing) = current.as_ref().filter(|s| s.source == source) {
+        return Ok(DbTarget::Snapshot(existing.clone()));
+    }
+
+    let taken = Snapshot::take(&source).map_err(|e| e.to_string())?;
+    *current = Some(taken.clone());
+    Ok(DbTarget::Snapshot(taken))
+}
+
+/// The search index, opened on first use
+fn open_search_index(current: &mut Option<SearchIndex>) -> Result<&mut SearchIndex, String> {
+    match current {
+        Some(index) => Ok(index),
+        None => Ok(current.insert(SearchIndex::open().map_err(|e| e.to_string())?)),
+    }
+}
+
+/// Bring `search_index.db` in line with `db`, identified by `source_key`.
+/// The sync has a connection of its own, leaving the shared one free for
+/// searches while it writes.
+pub(super) fn sync_search_index(
+    index_sync: &Mutex<()>,
+    source_key: &str,
+    db: &ChatDb,
+) -> Result<IndexStatus, String> {
+    let _syncing = index_sync.lock().map_err(|e| e.to_string())?;
+    let mut index = SearchIndex::open().map_err(|e| e.to_string())?;
+    index.sync(source_key, db).map_err(|e| e.to_string())?;
+    index.status().map_err(|e| e.to_string())
+}
+
+/// The loaded contacts, reading them on first use. Contacts are optional:
+/// without Contacts access, only the app's own aliases and imports are used.
+pub(super) fn cached_contacts(
+    contacts: &Mutex<Option<Arc<ContactBook>>>,
+    address_book_path: Option<PathBuf>,
+) -> Result<Arc<ContactBook>, String> {
+    let mut current = contacts.lock().map_err(|e| e.to_string())?;
+    let book = current.get_or_insert_with(|| {
+        let mut book = ContactBook::load_local().unwrap_or_default();
+        let _ = book.add_address_book(address_book_path.as_deref());
+        Arc::new(book)
+    });
+    Ok(Arc::clone(book))
+}
+
 impl Default for AppState {
     fn default() -> Self {
         Self::new()
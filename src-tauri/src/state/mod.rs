@@ -1,3 +1,4 @@
 mod app_state;
+mod watcher;
 
 pub use app_state::AppState;
@@ -0,0 +1,170 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::{AppHandle, Emitter};
+
+use super::app_state::{cached_contacts, sync_search_index};
+use crate::contacts::ContactBook;
+use crate::db::DataSource;
+
+/// Event carrying a `NewMessage` for each message that arrives
+pub const NEW_MESSAGE_EVENT: &str = "new-message";
+
+/// Quiet time after the last write before reading, so a burst (a message,
+/// its attachments, receipts) is read once
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// Read anyway once writes have kept coming for this long
+const MAX_DELAY: Duration = Duration::from_secs(2);
+/// File events can be coalesced or lost, so check this often regardless
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Most messages read per query while catching up
+const BATCH_SIZE: i64 = 500;
+
+/// What the watcher keeps up to date besides the frontend. Settings are
+/// shared with `AppState` and read on each check, so changes apply to a
+/// running watch.
+pub struct WatchTargets {
+    pub address_book_path: Arc<Mutex<Option<PathBuf>>>,
+    pub contacts: Arc<Mutex<Option<Arc<ContactBook>>>>,
+    pub index_sync: Arc<Mutex<()>>,
+}
+
+/// Watches a live database for new messages. Dropping it stops the watch.
+pub struct MessageWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl MessageWatcher {
+    /// Watch the folder holding `source` rather than the files themselves:
+    /// WAL checkpoints truncate or recreate `-wal`, which would end a watch
+    /// on the file
+    pub fn start(
+        app: AppHandle,
+        source: DataSource,
+        targets: WatchTargets,
+    ) -> Result<Self, notify::Error> {
+        let db_path = source.db_path();
+        let db_name = db_path.file_name().map(OsString::from).unwrap_or_default();
+        let mut wal_name = db_name.clone();
+        wal_name.push("-wal");
+        let dir = db_path
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        // Only writes to the database and its WAL matter; our own reads
+        // touch `-shm`
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            let relevant = event.paths.iter().any(|path| {
+                path.file_name()
+                    .is_some_and(|name| name == db_name || name == wal_name)
+            });
+            if relevant {
+                let _ = tx.send(());
+            }
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let source_key = db_path.display().to_string();
+        thread::spawn(move || watch(rx, app, source, source_key, targets));
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+fn watch(
+    rx: Receiver<()>,
+    app: AppHandle,
+    source: DataSource,
+    source_key: String,
+    targets: WatchTargets,
+) {
+    // Start from what's already there; only later arrivals are pushed
+    let mut last_rowid = source
+        .open()
+        .ok()
+        .and_then(|db| db.max_message_rowid().ok());
+
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(()) => {
+                if !settle(&rx) {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        // Failures (Messages mid-write, access revoked) are retried on the
+        // next change
+        if let Ok(rowid) = check(&app, &source, &source_key, &targets, last_rowid) {
+            last_rowid = Some(rowid);
+        }
+    }
+}
+
+/// Wait out a burst of writes. False once the watcher is gone.
+fn settle(rx: &Receiver<()>) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < MAX_DELAY {
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+    true
+}
+
+/// Push messages after `last_rowid`, then bring the search index (which
+/// also picks up edits) in line. Returns the ROWID to
+/// continue from.
+fn check(
+    app: &AppHandle,
+    source: &DataSource,
+    source_key: &str,
+    targets: &WatchTargets,
+    last_rowid: Option<i64>,
+) -> Result<i64, String> {
+    // A fresh connection each time, so a replaced database is picked up
+    let mut db = source.open().map_err(|e| e.to_string())?;
+    let max_rowid = db.max_message_rowid().map_err(|e| e.to_string())?;
+    // Without a starting point, or if the database was replaced by an
+    // older one, start again from its newest message
+    let Some(mut last_rowid) = last_rowid.filter(|&rowid| rowid <= max_rowid) else {
+        return Ok(max_rowid);
+    };
+
+    let address_book_path = targets
+        .address_book_path
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone();
+    db.contacts = cached_contacts(&targets.contacts, address_book_path)?;
+    while last_rowid < max_rowid {
+        // Keep what was already pushed if a later batch fails
+        let Ok((messages, next)) = db.get_new_messages(last_rowid, BATCH_SIZE) else {
+            break;
+        };
+        if next == last_rowid {
+            break;
+        }
+        last_rowid = next;
+        for new_message in messages {
+            let _ = app.emit(NEW_MESSAGE_EVENT, new_message);
+        }
+    }
+
+    // A failed sync catches up on the next one
+    let _ = sync_search_index(&targets.index_sync, source_key, &db);
+    Ok(last_rowid)
+}
@@ -1,34 +1,117 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Utc};
 
 /// macOS uses its own epoch starting from 2001-01-01 00:00:00 UTC
-/// The chat.db stores timestamps in nanoseconds since this epoch
 const MAC_EPOCH_OFFSET: i64 = 978_307_200; // Seconds from Unix epoch to Mac epoch
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
 
+/// Timestamps smaller than this (either side of the epoch) are seconds. As
+/// seconds it is over 3,000 years from 2001; as nanoseconds, under two
+/// minutes, so no real date is ambiguous.
+const MAX_SECONDS: u64 = 100_000_000_000;
+
+/// How a chat.db timestamp is stored. Since High Sierra the `date*`
+/// columns hold nanoseconds since the Mac epoch; older databases and some
+/// migrated rows hold seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampUnit {
+    Seconds,
+    Nanoseconds,
+}
+
+impl TimestampUnit {
+    /// The unit of one value. Databases can mix both, so this is decided
+    /// per value rather than per database.
+    pub fn detect(timestamp: i64) -> Self {
+        if timestamp.unsigned_abs() < MAX_SECONDS {
+            TimestampUnit::Seconds
+        } else {
+            TimestampUnit::Nanoseconds
+        }
+    }
+}
+
+/// Convert a chat.db timestamp in either unit, keeping nanoseconds
 pub fn mac_timestamp_to_datetime(timestamp: i64) -> DateTime<Utc> {
-    // Convert from nanoseconds to seconds and add the epoch offset
-    let unix_seconds = (timestamp / 1_000_000_000) + MAC_EPOCH_OFFSET;
-    Utc.timestamp_opt(unix_seconds, 0)
-        .single()
-        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).single().unwrap())
+    let (seconds, nanos) = match TimestampUnit::detect(timestamp) {
+        TimestampUnit::Seconds => (timestamp, 0),
+        TimestampUnit::Nanoseconds => (
+            timestamp.div_euclid(NANOS_PER_SECOND),
+            timestamp.rem_euclid(NANOS_PER_SECOND) as u32,
+        ),
+    };
+    DateTime::from_timestamp(seconds + MAC_EPOCH_OFFSET, nanos).unwrap_or_default()
+}
+
+/// A chat.db timestamp in either unit as nanoseconds, so values stored in
+/// different units compare and sort correctly
+pub fn mac_timestamp_to_nanos(timestamp: i64) -> i64 {
+    match TimestampUnit::detect(timestamp) {
+        TimestampUnit::Seconds => timestamp.saturating_mul(NANOS_PER_SECOND),
+        TimestampUnit::Nanoseconds => timestamp,
+    }
+}
+
+/// Like `mac_timestamp_to_datetime`, for columns such as `date_edited`
+/// where 0 or NULL means "never"
+pub fn optional_mac_timestamp_to_datetime(timestamp: Option<i64>) -> Option<DateTime<Utc>> {
+    timestamp
+        .filter(|&t| t != 0)
+        .map(mac_timestamp_to_datetime)
 }
 
+/// Nanoseconds since the Mac epoch, as current databases store them
 pub fn datetime_to_mac_timestamp(dt: DateTime<Utc>) -> i64 {
-    let unix_seconds = dt.timestamp();
-    (unix_seconds - MAC_EPOCH_OFFSET) * 1_000_000_000
+    let seconds = dt.timestamp() - MAC_EPOCH_OFFSET;
+    seconds
+        .saturating_mul(NANOS_PER_SECOND)
+        .saturating_add(i64::from(dt.timestamp_subsec_nanos()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn utc(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().to_utc()
+    }
+
     #[test]
     fn test_mac_timestamp_conversion() {
-        // Test round-trip conversion
-        let now = Utc::now();
-        let mac_ts = datetime_to_mac_timestamp(now);
-        let converted = mac_timestamp_to_datetime(mac_ts);
+        // Nanoseconds survive the round trip, including before 2001
+        for date in [
+            "2024-03-01T12:34:56.123456789Z",
+            "2001-01-01T00:05:00.000000001Z",
+            "1999-06-15T08:00:00.5Z",
+        ] {
+            let dt = utc(date);
+            assert_eq!(mac_timestamp_to_datetime(datetime_to_mac_timestamp(dt)), dt);
+        }
+
+        // Legacy seconds give the same date as nanoseconds
+        let dt = utc("2016-11-05T00:53:20Z");
+        let nanos = datetime_to_mac_timestamp(dt);
+        let seconds = nanos / NANOS_PER_SECOND;
+        assert_eq!(TimestampUnit::detect(seconds), TimestampUnit::Seconds);
+        assert_eq!(TimestampUnit::detect(nanos), TimestampUnit::Nanoseconds);
+        assert_eq!(mac_timestamp_to_datetime(seconds), dt);
+        assert_eq!(mac_timestamp_to_datetime(nanos), dt);
+        assert_eq!(mac_timestamp_to_nanos(seconds), nanos);
+        assert_eq!(mac_timestamp_to_nanos(nanos), nanos);
+        assert_eq!(
+            mac_timestamp_to_datetime(-86_400),
+            utc("2000-12-31T00:00:00Z")
+        );
+    }
 
-        // Should be within a second due to nanosecond precision loss
-        assert!((now.timestamp() - converted.timestamp()).abs() <= 1);
+    #[test]
+    fn test_zero_timestamps() {
+        assert_eq!(mac_timestamp_to_datetime(0), utc("2001-01-01T00:00:00Z"));
+        assert_eq!(datetime_to_mac_timestamp(utc("2001-01-01T00:00:00Z")), 0);
+        assert_eq!(optional_mac_timestamp_to_datetime(Some(0)), None);
+        assert_eq!(optional_mac_timestamp_to_datetime(None), None);
+        assert_eq!(
+            optional_mac_timestamp_to_datetime(Some(731_000_000)),
+            Some(utc("2024-03-01T15:33:20Z"))
+        );
     }
 }
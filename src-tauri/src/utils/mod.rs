@@ -1,7 +1,8 @@
 mod date;
+mod paths;
 
-pub use date::mac_timestamp_to_datetime;
-
-// datetime_to_mac_timestamp is available if needed in the future
-#[allow(unused_imports)]
-pub use date::datetime_to_mac_timestamp;
+pub use date::{
+    datetime_to_mac_timestamp, mac_timestamp_to_datetime, mac_timestamp_to_nanos,
+    optional_mac_timestamp_to_datetime,
+};
+pub use paths::app_data_dir;
@@ -0,0 +1,17 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// App-private directory for config and data we own
+/// (~/Library/Application Support/com.backchannel.app)
+pub fn app_data_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    let dir = PathBuf::from(home)
+        .join("Library")
+        .join("Application Support")
+        .join("com.backchannel.app");
+
+    // Create directory if it doesn't exist
+    let _ = fs::create_dir_all(&dir);
+
+    dir
+}
@@ -9,6 +9,8 @@ export function ConversationList() {
     selectedConversation,
     selectConversation,
     isLoading,
+    nextCursor,
+    loadMoreConversations,
   } = useConversationStore();
 
   if (isLoading && conversations.length === 0) {
@@ -25,7 +27,10 @@ export function ConversationList() {
         <h2 className="font-semibold text-gray-900 dark:text-white">
           Conversations
         </h2>
-        <p className="text-sm text-gray-500">{conversations.length} total</p>
+        <p className="text-sm text-gray-500">
+          {conversations.length}
+          {nextCursor ? "+" : ""} total
+        </p>
       </div>
 
       <div className="flex-1 overflow-y-auto">
@@ -83,6 +88,16 @@ export function ConversationList() {
             </div>
           </button>
         ))}
+
+        {nextCursor && (
+          <button
+            onClick={() => loadMoreConversations()}
+            disabled={isLoading}
+            className="w-full p-3 text-sm text-blue-600 dark:text-blue-400 hover:bg-gray-50 dark:hover:bg-gray-800 disabled:opacity-50"
+          >
+            {isLoading ? "Loading..." : "Load more"}
+          </button>
+        )}
       </div>
     </div>
   );
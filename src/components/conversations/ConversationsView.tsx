@@ -1,15 +1,27 @@
 import { useEffect } from "react";
+import { listen } from "@tauri-apps/api/event";
+import type { NewMessage } from "@/lib/types";
 import { useConversationStore } from "@/stores/conversationStore";
 import { ConversationList } from "./ConversationList";
 import { ConversationDetail } from "./ConversationDetail";
 
 export function ConversationsView() {
-  const { selectedConversation, loadConversations } = useConversationStore();
+  const { selectedConversation, loadConversations, receiveMessage } =
+    useConversationStore();
 
   useEffect(() => {
     loadConversations();
   }, [loadConversations]);
 
+  useEffect(() => {
+    const unlisten = listen<NewMessage>("new-message", (event) =>
+      receiveMessage(event.payload),
+    );
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, [receiveMessage]);
+
   return (
     <div className="flex-1 flex overflow-hidden">
       <ConversationList />
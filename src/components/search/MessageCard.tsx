@@ -1,6 +1,7 @@
 import type { SearchResult } from "@/lib/types";
 import { formatDate } from "@/lib/utils";
-import { User, ArrowRight } from "lucide-react";
+import { useSearchStore } from "@/stores/searchStore";
+import { User, ArrowRight, ChevronUp, ChevronDown } from "lucide-react";
 
 interface MessageCardProps {
   result: SearchResult;
@@ -8,12 +9,18 @@ interface MessageCardProps {
 
 export function MessageCard({ result }: MessageCardProps) {
   const { message, context_before, context_after } = result;
+  const expandContext = useSearchStore((state) => state.expandContext);
 
   return (
     <div className="bg-white dark:bg-gray-800 rounded-lg border border-gray-200 dark:border-gray-700 overflow-hidden">
       {/* Context before */}
       {context_before.length > 0 && (
         <div className="px-4 py-2 bg-gray-50 dark:bg-gray-900 border-b border-gray-200 dark:border-gray-700">
+          <ExpandButton
+            label="Show earlier"
+            icon={<ChevronUp className="h-3 w-3" />}
+            onClick={() => expandContext(message.id, "before")}
+          />
           {context_before.map((msg) => (
             <ContextMessage key={msg.id} message={msg} />
           ))}
@@ -66,12 +73,37 @@ export function MessageCard({ result }: MessageCardProps) {
           {context_after.map((msg) => (
             <ContextMessage key={msg.id} message={msg} />
           ))}
+          <ExpandButton
+            label="Show later"
+            icon={<ChevronDown className="h-3 w-3" />}
+            onClick={() => expandContext(message.id, "after")}
+          />
         </div>
       )}
     </div>
   );
 }
 
+function ExpandButton({
+  label,
+  icon,
+  onClick,
+}: {
+  label: string;
+  icon: React.ReactNode;
+  onClick: () => void;
+}) {
+  return (
+    <button
+      onClick={onClick}
+      className="flex items-center gap-1 py-1 text-xs text-blue-600 dark:text-blue-400 hover:underline"
+    >
+      {icon}
+      {label}
+    </button>
+  );
+}
+
 function ContextMessage({ message }: { message: SearchResult["message"] }) {
   return (
     <div className="py-1 text-sm text-gray-500">
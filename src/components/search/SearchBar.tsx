@@ -50,7 +50,7 @@ export function SearchBar() {
               placeholder={
                 useNaturalLanguage
                   ? "Find messages about dinner plans with John..."
-                  : "Search messages..."
+                  : "Search messages, e.g. from:alice has:link \"dinner plans\""
               }
               className="w-full pl-10 pr-4 py-3 rounded-lg border border-gray-300
                 dark:border-gray-600 dark:bg-gray-800 dark:text-white
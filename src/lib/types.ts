@@ -4,10 +4,86 @@ export interface Message {
   text: string | null;
   handle_id: number;
   date: string;
+  date_read: string | null;
+  date_delivered: string | null;
   is_from_me: boolean;
   service: string;
   contact_name: string | null;
   contact_id: string | null;
+  attachments: Attachment[];
+  reactions: Reaction[];
+  reply_to: ReplyTo | null;
+  date_edited: string | null;
+  date_retracted: string | null;
+  is_unsent: boolean;
+  retracted_parts: number[];
+  edit_history: MessageVersion[];
+  runs: TextRun[];
+}
+
+export interface TextRun {
+  start: number;
+  length: number;
+  text: string;
+  part_index: number | null;
+  attributes: TextAttribute[];
+}
+
+export type TextAttribute =
+  | { kind: "mention"; value: string }
+  | { kind: "link"; value: string }
+  | { kind: "file_transfer"; value: string }
+  | { kind: "effect"; value: TextEffect };
+
+export type TextEffect =
+  | "bold"
+  | "italic"
+  | "underline"
+  | "strikethrough"
+  | { animation: number };
+
+export interface MessageVersion {
+  part_index: number;
+  text: string | null;
+  date: string | null;
+}
+
+export interface ReplyTo {
+  guid: string;
+  part_index: number;
+}
+
+export type ReactionKind =
+  | "loved"
+  | "liked"
+  | "disliked"
+  | "laughed"
+  | "emphasized"
+  | "questioned"
+  | "emoji";
+
+export interface Reaction {
+  kind: ReactionKind;
+  emoji: string | null;
+  handle_id: number;
+  contact_id: string | null;
+  contact_name: string | null;
+  is_from_me: boolean;
+  date: string;
+  part_index: number;
+  is_removed: boolean;
+}
+
+export interface Attachment {
+  id: number;
+  message_id: number;
+  guid: string;
+  filename: string | null;
+  mime_type: string | null;
+  uti: string | null;
+  total_bytes: number;
+  transfer_name: string | null;
+  is_sticker: boolean;
 }
 
 export interface Handle {
@@ -15,6 +91,7 @@ export interface Handle {
   identifier: string;
   service: string;
   uncanonicalized_id: string | null;
+  contact_name: string | null;
 }
 
 export interface Chat {
@@ -32,10 +109,53 @@ export interface Conversation {
   message_count: number;
 }
 
+export interface ConversationPage {
+  conversations: Conversation[];
+  /** Opaque "date:rowid" cursor for the next page */
+  next_cursor: string | null;
+}
+
+export interface MessagePage {
+  /** Newest first */
+  messages: Message[];
+  /** Pass as `before` for older messages; null at the start of the chat */
+  older_cursor: string | null;
+  /** Pass as `after` for newer messages; null at the latest message */
+  newer_cursor: string | null;
+}
+
+/** Payload of the "new-message" event */
+export interface NewMessage {
+  chat_id: number | null;
+  message: Message;
+}
+
+export interface IndexStatus {
+  indexed: number;
+  last_rowid: number;
+}
+
+export interface EmbeddingStatus {
+  model: string;
+  embedded: number;
+  pending: number;
+}
+
+/** Relative weights for ordering search results */
+export interface RankingConfig {
+  term_match: number;
+  recency: number;
+  affinity: number;
+  recency_half_life_days: number;
+  /** Results the LLM reorders; 0 turns reranking off */
+  rerank_top_n: number;
+}
+
 export interface SearchResult {
   message: Message;
   context_before: Message[];
   context_after: Message[];
+  /** Ranking score, higher is better */
   relevance_score: number;
 }
 
@@ -66,3 +186,53 @@ export interface LlmConfig {
   temperature: number;
   max_tokens: number;
 }
+
+export type DataSource =
+  | { kind: "local" }
+  | { kind: "file"; path: string }
+  | { kind: "ios_backup"; path: string };
+
+export interface Snapshot {
+  source: DataSource;
+  path: string;
+  taken_at: string;
+}
+
+/** Features the current chat.db supports, which depend on the macOS release */
+export interface Capabilities {
+  rich_text: boolean;
+  reactions: boolean;
+  replies: boolean;
+  edits: boolean;
+  unsends: boolean;
+  read_receipts: boolean;
+  link_previews: boolean;
+  attachments: boolean;
+  stickers: boolean;
+}
+
+export interface Diagnostics {
+  capabilities: Capabilities;
+  /** `table.column` for each optional column the database lacks */
+  missing_columns: string[];
+  message_count: number;
+  sqlite_version: string;
+}
+
+export interface Alias {
+  id: number;
+  handle: string;
+  name: string;
+}
+
+export interface ImportSummary {
+  contacts: number;
+  handles: number;
+}
+
+export interface Person {
+  id: string;
+  name: string | null;
+  handles: Handle[];
+  chat_ids: number[];
+}
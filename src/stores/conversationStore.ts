@@ -1,11 +1,20 @@
 import { create } from "zustand";
 import { invoke, Channel } from "@tauri-apps/api/core";
-import type { Conversation, Message } from "@/lib/types";
+import type {
+  Conversation,
+  ConversationPage,
+  Message,
+  MessagePage,
+  NewMessage,
+} from "@/lib/types";
 
 interface ConversationState {
   conversations: Conversation[];
+  nextCursor: string | null;
   selectedConversation: Conversation | null;
   messages: Message[];
+  olderCursor: string | null;
+  newerCursor: string | null;
   summary: string;
   analysis: string;
   isLoading: boolean;
@@ -14,18 +23,25 @@ interface ConversationState {
   error: string | null;
 
   loadConversations: () => Promise<void>;
+  loadMoreConversations: () => Promise<void>;
   selectConversation: (conversation: Conversation) => Promise<void>;
   loadMessages: (chatId: number, limit?: number) => Promise<void>;
+  loadOlderMessages: (chatId: number, limit?: number) => Promise<void>;
+  jumpToDate: (chatId: number, date: Date, limit?: number) => Promise<void>;
   summarize: (chatId: number) => Promise<void>;
   summarizeStreaming: (chatId: number) => Promise<void>;
   analyze: (chatId: number) => Promise<void>;
+  receiveMessage: (newMessage: NewMessage) => void;
   clearSelection: () => void;
 }
 
 export const useConversationStore = create<ConversationState>((set, get) => ({
   conversations: [],
+  nextCursor: null,
   selectedConversation: null,
   messages: [],
+  olderCursor: null,
+  newerCursor: null,
   summary: "",
   analysis: "",
   isLoading: false,
@@ -36,9 +52,30 @@ export const useConversationStore = create<ConversationState>((set, get) => ({
   loadConversations: async () => {
     set({ isLoading: true, error: null });
     try {
-      const conversations =
-        await invoke<Conversation[]>("get_conversations");
-      set({ conversations, isLoading: false });
+      const page = await invoke<ConversationPage>("get_conversations");
+      set({
+        conversations: page.conversations,
+        nextCursor: page.next_cursor,
+        isLoading: false,
+      });
+    } catch (error) {
+      set({ error: String(error), isLoading: false });
+    }
+  },
+
+  loadMoreConversations: async () => {
+    const cursor = get().nextCursor;
+    if (!cursor) return;
+    set({ isLoading: true, error: null });
+    try {
+      const page = await invoke<ConversationPage>("get_conversations", {
+        cursor,
+      });
+      set({
+        conversations: [...get().conversations, ...page.conversations],
+        nextCursor: page.next_cursor,
+        isLoading: false,
+      });
     } catch (error) {
       set({ error: String(error), isLoading: false });
     }
@@ -52,11 +89,56 @@ export const useConversationStore = create<ConversationState>((set, get) => ({
   loadMessages: async (chatId: number, limit = 100) => {
     set({ isLoading: true, error: null });
     try {
-      const messages = await invoke<Message[]>("get_conversation_messages", {
+      const page = await invoke<MessagePage>("get_conversation_messages", {
         chatId,
         limit,
       });
-      set({ messages, isLoading: false });
+      set({
+        messages: page.messages,
+        olderCursor: page.older_cursor,
+        newerCursor: page.newer_cursor,
+        isLoading: false,
+      });
+    } catch (error) {
+      set({ error: String(error), isLoading: false });
+    }
+  },
+
+  loadOlderMessages: async (chatId: number, limit = 100) => {
+    const { olderCursor } = get();
+    if (!olderCursor) return;
+
+    set({ isLoading: true, error: null });
+    try {
+      const page = await invoke<MessagePage>("get_conversation_messages", {
+        chatId,
+        limit,
+        before: olderCursor,
+      });
+      set((state) => ({
+        messages: [...state.messages, ...page.messages],
+        olderCursor: page.older_cursor,
+        isLoading: false,
+      }));
+    } catch (error) {
+      set({ error: String(error), isLoading: false });
+    }
+  },
+
+  jumpToDate: async (chatId: number, date: Date, limit = 100) => {
+    set({ isLoading: true, error: null });
+    try {
+      const page = await invoke<MessagePage>("jump_to_date", {
+        chatId,
+        date: date.toISOString(),
+        limit,
+      });
+      set({
+        messages: page.messages,
+        olderCursor: page.older_cursor,
+        newerCursor: page.newer_cursor,
+        isLoading: false,
+      });
     } catch (error) {
       set({ error: String(error), isLoading: false });
     }
@@ -114,10 +196,41 @@ export const useConversationStore = create<ConversationState>((set, get) => ({
     }
   },
 
+  receiveMessage: ({ chat_id, message }: NewMessage) => {
+    const { conversations, selectedConversation, messages, newerCursor } =
+      get();
+    const existing = conversations.find((c) => c.chat.id === chat_id);
+    if (!existing) {
+      // A chat we haven't loaded, or a brand new one
+      get().loadConversations();
+      return;
+    }
+
+    const updated = {
+      ...existing,
+      last_message: message,
+      message_count: existing.message_count + 1,
+    };
+    set({
+      conversations: [
+        updated,
+        ...conversations.filter((c) => c.chat.id !== chat_id),
+      ],
+    });
+
+    // Only add to the open chat when it's showing the latest messages
+    const isOpen = selectedConversation?.chat.id === chat_id;
+    if (isOpen && !newerCursor && !messages.some((m) => m.id === message.id)) {
+      set({ messages: [message, ...messages] });
+    }
+  },
+
   clearSelection: () =>
     set({
       selectedConversation: null,
       messages: [],
+      olderCursor: null,
+      newerCursor: null,
       summary: "",
       analysis: "",
     }),
@@ -15,6 +15,11 @@ interface SearchState {
   search: (query: string) => Promise<void>;
   askQuestion: (question: string) => Promise<void>;
   simpleSearch: (query: string) => Promise<void>;
+  semanticSearch: (query: string, hybrid?: boolean) => Promise<void>;
+  expandContext: (
+    messageId: number,
+    direction: "before" | "after"
+  ) => Promise<void>;
   toggleSources: () => void;
   clearResults: () => void;
 }
@@ -77,6 +82,50 @@ export const useSearchStore = create<SearchState>((set, get) => ({
     }
   },
 
+  semanticSearch: async (query: string, hybrid = true) => {
+    set({ isLoading: true, error: null, query, aiAnswer: null });
+    try {
+      const results = await invoke<SearchResult[]>("semantic_search", {
+        query,
+        limit: 20,
+        hybrid,
+      });
+      set({ results, isLoading: false });
+    } catch (error) {
+      set({ error: String(error), isLoading: false });
+    }
+  },
+
+  expandContext: async (messageId: number, direction: "before" | "after") => {
+    const result = get().results.find((r) => r.message.id === messageId);
+    if (!result) return;
+
+    // Continue from the edge of the current window
+    const edge =
+      direction === "before"
+        ? result.context_before[0] ?? result.message
+        : result.context_after[result.context_after.length - 1] ??
+          result.message;
+    try {
+      const more = await invoke<Message[]>("get_message_context", {
+        messageId: edge.id,
+        direction,
+        count: 10,
+      });
+      set((state) => ({
+        results: state.results.map((r) =>
+          r.message.id !== messageId
+            ? r
+            : direction === "before"
+              ? { ...r, context_before: [...more, ...r.context_before] }
+              : { ...r, context_after: [...r.context_after, ...more] }
+        ),
+      }));
+    } catch (error) {
+      set({ error: String(error) });
+    }
+  },
+
   toggleSources: () => set({ showSources: !get().showSources }),
 
   clearResults: () =>